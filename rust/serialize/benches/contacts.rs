@@ -0,0 +1,53 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use serialize::bench::Fixture;
+use serialize::{crypto, FileStore, MemoryStore};
+
+use std::fs;
+
+fn search_by_name(c: &mut Criterion) {
+    let mut group = c.benchmark_group("search_by_name");
+    for contacts in [100, 1_000, 10_000] {
+        let fixture = Fixture::new(1, contacts);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(contacts),
+            &fixture,
+            |b, fixture| b.iter(|| fixture.search_by_name("account0", "contact 42")),
+        );
+    }
+    group.finish();
+}
+
+// Saving and loading every account and contact, the way the server does on every change to the
+// accounts and on Save. In memory it's only serialization, on disk the files are encrypted too.
+fn persistence(c: &mut Criterion) {
+    // A throwaway key, so nothing is read from or written to the server's key file
+    std::env::set_var("SERIALIZE_KEYS", format!("1:{}", "42".repeat(32)));
+    crypto::init().unwrap();
+    let dir = std::env::temp_dir().join(format!("serialize-bench-{}", std::process::id()));
+
+    let mut group = c.benchmark_group("persistence");
+    for accounts in [10, 100] {
+        let fixture = Fixture::new(accounts, 1_000);
+        let memory = MemoryStore::new();
+        let disk = FileStore::new(&dir);
+
+        group.bench_with_input(
+            BenchmarkId::new("save/memory", accounts),
+            &fixture,
+            |b, fixture| b.iter(|| fixture.save(&memory)),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("save/disk", accounts),
+            &fixture,
+            |b, fixture| b.iter(|| fixture.save(&disk)),
+        );
+        group.bench_function(BenchmarkId::new("load/disk", accounts), |b| {
+            b.iter(|| Fixture::load(&disk))
+        });
+    }
+    group.finish();
+    let _ = fs::remove_dir_all(&dir);
+}
+
+criterion_group!(benches, search_by_name, persistence);
+criterion_main!(benches);
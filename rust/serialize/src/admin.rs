@@ -0,0 +1,211 @@
+use crate::{replication, snapshots, Role, State};
+
+use serde::{Deserialize, Serialize};
+
+use std::sync::atomic::Ordering;
+
+// What admins can do, from the server console or as `Query::Admin` from an admin account.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Command {
+    Accounts,
+    Stats,
+    Logout(String),
+    ResetPassword(String, String),
+    Unlock(String),
+    SetRole(String, Role),
+    Save,
+    Snapshot,
+    Promote,
+}
+
+impl Command {
+    // Whether the command changes accounts, which a standby leaves to the primary.
+    pub fn writes(&self) -> bool {
+        matches!(
+            self,
+            Command::ResetPassword(..) | Command::Unlock(_) | Command::SetRole(..)
+        )
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccountInfo {
+    name: String,
+    role: Role,
+    locked: bool,
+    contacts: usize,
+    sessions: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Stats {
+    uptime: u64, // seconds
+    accounts: usize,
+    locked: usize,
+    contacts: usize,
+    sessions: usize,
+    requests: u64,
+    standby: bool,
+    replicas: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Reply {
+    Accounts(Vec<AccountInfo>),
+    Stats(Stats),
+    Done(String),
+}
+
+// Runs a command on behalf of an admin, the caller checks the role.
+pub fn run(state: &State, command: &Command) -> Result<Reply, String> {
+    let State {
+        data,
+        contacts,
+        sessions,
+        store,
+        ..
+    } = state;
+    match command {
+        Command::Accounts => {
+            let data = data.read().unwrap();
+            let contacts = contacts.read().unwrap();
+            let sessions = sessions.lock().unwrap();
+            Ok(Reply::Accounts(
+                data.clients
+                    .iter()
+                    .map(|acc| AccountInfo {
+                        name: acc.name.clone(),
+                        role: acc.role,
+                        locked: acc.locked,
+                        contacts: contacts
+                            .contacts_list
+                            .get(&acc.name)
+                            .map_or(0, |list| list.len()),
+                        sessions: sessions.count_for(&acc.name),
+                    })
+                    .collect(),
+            ))
+        }
+        Command::Stats => {
+            let data = data.read().unwrap();
+            let contacts = contacts.read().unwrap();
+            Ok(Reply::Stats(Stats {
+                uptime: state.started_at.elapsed().as_secs(),
+                accounts: data.clients.len(),
+                locked: data.clients.iter().filter(|acc| acc.locked).count(),
+                contacts: contacts.contacts_list.values().map(|list| list.len()).sum(),
+                sessions: sessions.lock().unwrap().count(),
+                requests: state.requests.load(Ordering::Relaxed),
+                standby: state.standby.load(Ordering::SeqCst),
+                replicas: state.replicas.lock().unwrap().count(),
+            }))
+        }
+        Command::Logout(account) => {
+            if !data.read().unwrap().has_client(account) {
+                return Err(format!("Account \"{}\" doesn't exist", account));
+            }
+            let ended = sessions.lock().unwrap().logout(account);
+            Ok(Reply::Done(format!(
+                "Ended {} session(s) of \"{}\"",
+                ended, account
+            )))
+        }
+        Command::ResetPassword(account, password) => {
+            if password.is_empty() {
+                return Err("The password can't be empty".to_owned());
+            }
+            edit_account(state, account, |acc| acc.password = password.clone())?;
+            Ok(Reply::Done(format!(
+                "Reset the password of \"{}\"",
+                account
+            )))
+        }
+        Command::Unlock(account) => {
+            edit_account(state, account, |acc| {
+                acc.locked = false;
+                acc.failed_logins = 0;
+            })?;
+            Ok(Reply::Done(format!("Unlocked \"{}\"", account)))
+        }
+        Command::SetRole(account, role) => {
+            edit_account(state, account, |acc| acc.role = *role)?;
+            Ok(Reply::Done(format!("\"{}\" is now {:?}", account, role)))
+        }
+        Command::Save => {
+            state.save();
+            Ok(Reply::Done("Saved accounts and contacts".to_owned()))
+        }
+        Command::Snapshot => {
+            match snapshots::take(data, contacts, store.as_ref(), state.config.keep_snapshots) {
+                Ok(name) => Ok(Reply::Done(format!("Took snapshot {}", name))),
+                Err(e) => Err(format!("Couldn't take snapshot: {}", e)),
+            }
+        }
+        Command::Promote => replication::promote(state).map(Reply::Done),
+    }
+}
+
+// Changes an account and saves the accounts file right away, like every other account change.
+fn edit_account(
+    state: &State,
+    account: &str,
+    edit: impl FnOnce(&mut crate::Account),
+) -> Result<(), String> {
+    state.edit_accounts(|data| {
+        match data.clients.iter_mut().find(|acc| acc.name == account) {
+            Some(acc) => edit(acc),
+            None => return Err(format!("Account \"{}\" doesn't exist", account)),
+        }
+        data.save(state.store.as_ref());
+        Ok(())
+    })
+}
+
+// Console syntax of the admin commands.
+pub fn parse(args: &[&str]) -> Option<Command> {
+    Some(match args {
+        ["accounts"] => Command::Accounts,
+        ["stats"] => Command::Stats,
+        ["logout", account] => Command::Logout(account.to_string()),
+        ["reset-password", account, password] => {
+            Command::ResetPassword(account.to_string(), password.to_string())
+        }
+        ["unlock", account] => Command::Unlock(account.to_string()),
+        ["role", account, "admin"] => Command::SetRole(account.to_string(), Role::Admin),
+        ["role", account, "user"] => Command::SetRole(account.to_string(), Role::User),
+        ["save"] => Command::Save,
+        ["snapshot"] => Command::Snapshot,
+        ["promote"] => Command::Promote,
+        _ => return None,
+    })
+}
+
+pub fn print(reply: &Result<Reply, String>) {
+    match reply {
+        Ok(Reply::Accounts(accounts)) => {
+            for acc in accounts {
+                println!(
+                    "{} ({:?}{}): {} contact(s), {} session(s)",
+                    acc.name,
+                    acc.role,
+                    if acc.locked { ", locked" } else { "" },
+                    acc.contacts,
+                    acc.sessions
+                );
+            }
+        }
+        Ok(Reply::Stats(stats)) => println!(
+            "{} up {}s, {} account(s) ({} locked), {} contact(s), {} session(s), {} request(s) served, {} standby(s)",
+            if stats.standby { "standby" } else { "primary" },
+            stats.uptime,
+            stats.accounts,
+            stats.locked,
+            stats.contacts,
+            stats.sessions,
+            stats.requests,
+            stats.replicas
+        ),
+        Ok(Reply::Done(message)) => println!("{}", message),
+        Err(e) => println!("{}", e),
+    }
+}
@@ -0,0 +1,45 @@
+// What the criterion benchmarks in benches/ measure, reachable without a connection in between.
+use crate::{Contact, Contacts, Data, Store};
+
+pub struct Fixture {
+    data: Data,
+    contacts: Contacts,
+}
+
+impl Fixture {
+    // Accounts named "account0", "account1"... holding `per_account` contacts each. Contact names
+    // repeat every 100 contacts, so every search finds a few of them.
+    pub fn new(accounts: usize, per_account: u64) -> Self {
+        let mut data = Data::new();
+        let mut contacts = Contacts::new();
+        for account in 0..accounts {
+            let account = format!("account{}", account);
+            data.add_client(&account, "password");
+            let list = contacts.contacts_list.entry(account).or_default();
+            for phone in 0..per_account {
+                let name = format!("contact {}", phone % 100);
+                list.insert(phone, Contact::new(&name, phone, None));
+            }
+        }
+        Fixture { data, contacts }
+    }
+
+    pub fn search_by_name(&self, account: &str, name: &str) -> Vec<u64> {
+        self.contacts.find_by_name(account, name)
+    }
+
+    pub fn save(&self, store: &dyn Store) {
+        self.data.save(store);
+        self.contacts.save(store);
+    }
+
+    pub fn load(store: &dyn Store) -> Self {
+        let mut fixture = Fixture {
+            data: Data::new(),
+            contacts: Contacts::new(),
+        };
+        fixture.data.recover(store).unwrap();
+        fixture.contacts.recover(store).unwrap();
+        fixture
+    }
+}
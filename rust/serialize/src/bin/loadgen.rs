@@ -0,0 +1,400 @@
+// Load generator for the contacts server. Simulated clients run a mix of logins, adds, searches
+// and lists as fast as the server answers, then throughput, latency percentiles and errors are
+// reported for each kind of request.
+use serde_json::{json, Value};
+use serialize::{Config, Limits, MemoryStore, Server};
+
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const USAGE: &str = "usage: loadgen [--addr HOST:PORT] [--clients N] [--requests N] [--contacts N] [--mix login=1,add=3,search=4,list=2]
+Each client makes --requests requests after adding --contacts contacts to its own account.
+Without --addr a server with an in-memory store is started in this process.";
+
+const PASSWORD: &str = "loadgen";
+const NAMES: u64 = 50; // contact names repeat, so searches find a few contacts each
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Login,
+    Add,
+    Search,
+    List,
+}
+
+const OPS: [Op; 4] = [Op::Login, Op::Add, Op::Search, Op::List];
+
+impl Op {
+    fn name(self) -> &'static str {
+        match self {
+            Op::Login => "login",
+            Op::Add => "add",
+            Op::Search => "search",
+            Op::List => "list",
+        }
+    }
+}
+
+struct Options {
+    addr: Option<String>,
+    clients: usize,
+    requests: usize,
+    contacts: u64,
+    mix: [u32; 4], // weights, in the order of OPS
+}
+
+fn parse_mix(mix: &str) -> Result<[u32; 4], String> {
+    let mut weights = [0; 4];
+    for part in mix.split(',') {
+        let (name, weight) = part
+            .split_once('=')
+            .ok_or_else(|| format!("expected operation=weight, got {}", part))?;
+        let op = OPS
+            .iter()
+            .position(|op| op.name() == name)
+            .ok_or_else(|| format!("unknown operation {}", name))?;
+        weights[op] = weight
+            .parse()
+            .map_err(|_| format!("{} takes a number, got {}", name, weight))?;
+    }
+    if weights.iter().all(|&weight| weight == 0) {
+        return Err("the mix has no operations".to_owned());
+    }
+    Ok(weights)
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        addr: None,
+        clients: 8,
+        requests: 1000,
+        contacts: 100,
+        mix: [1, 3, 4, 2],
+    };
+    for option in args.chunks(2) {
+        let (name, value) = match option {
+            [name, value] => (name.as_str(), value.as_str()),
+            [name] => return Err(format!("{} needs a value", name)),
+            _ => unreachable!(),
+        };
+        let number = || {
+            value
+                .parse()
+                .map_err(|_| format!("{} takes a number, got {}", name, value))
+        };
+        match name {
+            "--addr" => options.addr = Some(value.to_owned()),
+            "--clients" => options.clients = number()?,
+            "--requests" => options.requests = number()?,
+            "--contacts" => options.contacts = number()? as u64,
+            "--mix" => options.mix = parse_mix(value)?,
+            _ => return Err(format!("unknown option {}", name)),
+        }
+    }
+    Ok(options)
+}
+
+// xorshift, good enough to pick operations and names without pulling in a dependency.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn pick(&mut self, weights: &[u32; 4]) -> Op {
+        let total: u64 = weights.iter().map(|&weight| weight as u64).sum();
+        let mut roll = self.next() % total;
+        for (op, &weight) in OPS.iter().zip(weights) {
+            if roll < weight as u64 {
+                return *op;
+            }
+            roll -= weight as u64;
+        }
+        unreachable!()
+    }
+}
+
+struct Connection {
+    stream: TcpStream,
+    responses: serde_json::StreamDeserializer<'static, serde_json::de::IoRead<TcpStream>, Value>,
+}
+
+impl Connection {
+    fn open(addr: SocketAddr) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+        let reader = stream.try_clone()?;
+        Ok(Connection {
+            stream,
+            responses: serde_json::Deserializer::from_reader(reader).into_iter(),
+        })
+    }
+
+    // In one write, so the generator itself doesn't add small packets to what is measured.
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        self.stream.write_all(&serde_json::to_vec(message)?)
+    }
+
+    fn request(&mut self, query: &str, name: Option<&str>, phone: Option<u64>) -> io::Result<()> {
+        self.send(&json!({"result": {"Ok": {"query": query, "name": name, "phone": phone}}}))
+    }
+
+    // The answer to a request, Err if the server refused it.
+    fn receive(&mut self) -> io::Result<Result<Value, Value>> {
+        let feedback = match self.responses.next() {
+            Some(_feedback) => _feedback?,
+            None => return Err(io::ErrorKind::UnexpectedEof.into()),
+        };
+        match feedback.get("result") {
+            Some(Value::Object(result)) if result.contains_key("Ok") => {
+                Ok(Ok(result["Ok"].clone()))
+            }
+            Some(Value::Object(result)) if result.contains_key("Err") => {
+                Ok(Err(result["Err"].clone()))
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "malformed response",
+            )),
+        }
+    }
+
+    // Reads multi-part answers up to the closing Done. Returns false if the server refused it.
+    fn receive_until_done(&mut self) -> io::Result<bool> {
+        loop {
+            match self.receive()? {
+                Ok(ans) if ans["query"] == "Done" => return Ok(true),
+                Ok(_) => {}
+                Err(_) => return Ok(false),
+            }
+        }
+    }
+
+    fn authenticate(&mut self, query: &str, account: &str) -> io::Result<bool> {
+        self.request(query, None, None)?;
+        self.send(&json!({"name": account, "password": PASSWORD}))?;
+        Ok(self.receive()?.is_ok())
+    }
+}
+
+// Logs in, creating the account the first time.
+fn log_in(addr: SocketAddr, account: &str) -> io::Result<Connection> {
+    let mut connection = Connection::open(addr)?;
+    if connection.authenticate("CreateAccount", account)?
+        || connection.authenticate("Login", account)?
+    {
+        Ok(connection)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("couldn't log in to {}", account),
+        ))
+    }
+}
+
+struct Sample {
+    op: Op,
+    latency: Duration,
+    ok: bool,
+}
+
+fn contact_name(rng: &mut Rng) -> String {
+    format!("contact {}", rng.next() % NAMES)
+}
+
+// Makes one request and tells whether the server accepted it.
+fn perform(
+    op: Op,
+    connection: &mut Connection,
+    addr: SocketAddr,
+    account: &str,
+    rng: &mut Rng,
+) -> io::Result<bool> {
+    match op {
+        Op::Login => {
+            let mut connection = Connection::open(addr)?;
+            connection.authenticate("Login", account)
+        }
+        Op::Add => {
+            // Phones are random, so runs against the same server don't collide
+            connection.request("Add", Some(&contact_name(rng)), Some(rng.next()))?;
+            Ok(connection.receive()?.is_ok())
+        }
+        Op::Search => {
+            connection.request("SearchByName", Some(&contact_name(rng)), None)?;
+            // Finding nothing is an answer too, the account may not have that name yet
+            connection.receive_until_done().map(|_| true)
+        }
+        Op::List => {
+            connection.request("ShowList", None, None)?;
+            connection.receive_until_done()
+        }
+    }
+}
+
+// Logs the client in and gives its account some contacts to search and list.
+fn set_up(addr: SocketAddr, account: &str, contacts: u64, rng: &mut Rng) -> io::Result<Connection> {
+    let mut connection = log_in(addr, account)?;
+    for _ in 0..contacts {
+        connection.request("Add", Some(&contact_name(rng)), Some(rng.next()))?;
+        // A phone that is taken already just means one contact less
+        let _ = connection.receive()?;
+    }
+    Ok(connection)
+}
+
+fn run_client(
+    addr: SocketAddr,
+    index: usize,
+    options: &Options,
+    start: &Barrier,
+) -> io::Result<Vec<Sample>> {
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64;
+    let mut rng = Rng::new(seed ^ (index as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    let account = format!("loadgen{}", index);
+    let setup = set_up(addr, &account, options.contacts, &mut rng);
+    // Every client waits for the others to be set up, even the ones that failed to
+    start.wait();
+    let mut connection = setup?;
+
+    let mut samples = Vec::with_capacity(options.requests);
+    for _ in 0..options.requests {
+        let op = rng.pick(&options.mix);
+        let started = Instant::now();
+        let result = perform(op, &mut connection, addr, &account, &mut rng);
+        samples.push(Sample {
+            op,
+            latency: started.elapsed(),
+            ok: *result.as_ref().unwrap_or(&false),
+        });
+        // The connection may be out of step with the server after an I/O error
+        if result.is_err() {
+            connection = match log_in(addr, &account) {
+                Ok(_connection) => _connection,
+                Err(_) => break,
+            };
+        }
+    }
+    Ok(samples)
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn report_line(label: &str, samples: &[&Sample]) {
+    if samples.is_empty() {
+        return;
+    }
+    let mut latencies: Vec<Duration> = samples.iter().map(|sample| sample.latency).collect();
+    latencies.sort_unstable();
+    let errors = samples.iter().filter(|sample| !sample.ok).count();
+    println!(
+        "{:<9} {:>9} {:>7} {:>9.2} {:>9.2} {:>9.2} {:>9.2}",
+        label,
+        samples.len(),
+        errors,
+        millis(percentile(&latencies, 0.5)),
+        millis(percentile(&latencies, 0.9)),
+        millis(percentile(&latencies, 0.99)),
+        millis(latencies[latencies.len() - 1]),
+    );
+}
+
+fn report(samples: &[Sample], elapsed: Duration, clients: usize, failed: usize) {
+    println!(
+        "{} clients, {} requests in {:.2}s: {:.0} requests/s",
+        clients,
+        samples.len(),
+        elapsed.as_secs_f64(),
+        samples.len() as f64 / elapsed.as_secs_f64()
+    );
+    if failed > 0 {
+        println!("{} clients couldn't connect or log in", failed);
+    }
+    println!(
+        "{:<9} {:>9} {:>7} {:>9} {:>9} {:>9} {:>9}",
+        "operation", "requests", "errors", "p50 ms", "p90 ms", "p99 ms", "max ms"
+    );
+    for op in OPS.iter() {
+        let of_op: Vec<&Sample> = samples.iter().filter(|sample| sample.op == *op).collect();
+        report_line(op.name(), &of_op);
+    }
+    report_line("all", &samples.iter().collect::<Vec<_>>());
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match parse_options(&args) {
+        Ok(_options) => _options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let addr = match &options.addr {
+        Some(addr) => match addr.to_socket_addrs().map(|mut addrs| addrs.next()) {
+            Ok(Some(_addr)) => _addr,
+            _ => {
+                eprintln!("Couldn't resolve {}", addr);
+                std::process::exit(2);
+            }
+        },
+        None => {
+            let config = Config {
+                limits: Limits {
+                    max_contacts: usize::MAX,
+                    ..Limits::default()
+                },
+                log_requests: false,
+                ..Config::default()
+            };
+            let server = Server::bind_with("127.0.0.1:0", MemoryStore::new(), config).unwrap();
+            let addr = server.local_addr().unwrap();
+            thread::spawn(move || server.run());
+            addr
+        }
+    };
+
+    let options = Arc::new(options);
+    let start = Arc::new(Barrier::new(options.clients + 1));
+    let clients: Vec<_> = (0..options.clients)
+        .map(|index| {
+            let options = Arc::clone(&options);
+            let start = Arc::clone(&start);
+            thread::spawn(move || run_client(addr, index, &options, &start))
+        })
+        .collect();
+    start.wait();
+    let started = Instant::now();
+
+    let mut samples = Vec::new();
+    let mut failed = 0;
+    for client in clients {
+        match client.join().unwrap() {
+            Ok(client_samples) => samples.extend(client_samples),
+            Err(_) => failed += 1,
+        }
+    }
+    report(&samples, started.elapsed(), options.clients, failed);
+}
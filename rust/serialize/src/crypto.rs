@@ -0,0 +1,246 @@
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock, RwLock};
+
+// Keys are given as `<id>:<64 hex digits>`, one per line in the key file or comma separated in
+// the environment variable. The key with the highest id encrypts, the older ones are kept so
+// files written before a rotation can still be read.
+const KEYS_ENV: &str = "SERIALIZE_KEYS";
+const KEY_FILE_ENV: &str = "SERIALIZE_KEY_FILE";
+// Kept out of the data directory, so copying or backing up the data files doesn't take the key
+// along with them. Better still, point SERIALIZE_KEY_FILE at a file on another volume.
+const DEFAULT_KEY_FILE: &str = "server.key";
+// Where the key file used to be generated, refused so the key gets moved away from the data.
+const OLD_KEY_FILE: &str = "data/server.key";
+
+// Encrypted files start with the magic and the id of the key they were sealed with, followed by
+// the nonce and the ciphertext. The header is authenticated along with the contents.
+const MAGIC: &[u8; 4] = b"SCE1";
+const KEY_ID_LEN: usize = 4;
+const NONCE_LEN: usize = 24;
+const HEADER_LEN: usize = MAGIC.len() + KEY_ID_LEN + NONCE_LEN;
+
+struct Keyring {
+    keys: BTreeMap<u32, Key>,
+    file: Option<PathBuf>, // None when the keys come from the environment
+}
+
+static KEYRING: OnceLock<RwLock<Keyring>> = OnceLock::new();
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn parse_key(entry: &str) -> io::Result<(u32, Key)> {
+    let (id, hex) = entry
+        .split_once(':')
+        .ok_or_else(|| invalid(format!("key entry is not <id>:<hex key>: {}", entry)))?;
+    let id = id
+        .trim()
+        .parse()
+        .map_err(|_| invalid(format!("key id is not a number: {}", id)))?;
+    let bytes = hex::decode(hex.trim()).map_err(|e| invalid(format!("key {}: {}", id, e)))?;
+    if bytes.len() != 32 {
+        return Err(invalid(format!("key {} is not 32 bytes long", id)));
+    }
+    Ok((id, *Key::from_slice(&bytes)))
+}
+
+fn parse_keys<'a>(entries: impl Iterator<Item = &'a str>) -> io::Result<BTreeMap<u32, Key>> {
+    let mut keys = BTreeMap::new();
+    for entry in entries
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty() && !entry.starts_with('#'))
+    {
+        let (id, key) = parse_key(entry)?;
+        if keys.insert(id, key).is_some() {
+            return Err(invalid(format!("key {} is given twice", id)));
+        }
+    }
+    if keys.is_empty() {
+        return Err(invalid("no keys given".to_owned()));
+    }
+    Ok(keys)
+}
+
+fn key_entry(id: u32, key: &Key) -> String {
+    format!("{}:{}\n", id, hex::encode(key))
+}
+
+// Only the server should be able to read the key file.
+fn create_key_file(path: &Path) -> io::Result<fs::File> {
+    let mut options = OpenOptions::new();
+    options.create_new(true).write(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+fn load(generate: bool) -> io::Result<Keyring> {
+    if let Ok(entries) = env::var(KEYS_ENV) {
+        return Ok(Keyring {
+            keys: parse_keys(entries.split(','))?,
+            file: None,
+        });
+    }
+
+    let path = PathBuf::from(env::var(KEY_FILE_ENV).unwrap_or_else(|_| DEFAULT_KEY_FILE.into()));
+    match fs::read_to_string(&path) {
+        Ok(contents) => Ok(Keyring {
+            keys: parse_keys(contents.lines())
+                .map_err(|e| invalid(format!("{}: {}", path.display(), e)))?,
+            file: Some(path),
+        }),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound && Path::new(OLD_KEY_FILE).exists() => {
+            Err(invalid(format!(
+                "the key is no longer read from {}, move it to {} or set {} to where it is",
+                OLD_KEY_FILE,
+                path.display(),
+                KEY_FILE_ENV
+            )))
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound && !generate => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "there is no key in {}, set {} to where it is or give the keys in {}",
+                path.display(),
+                KEY_FILE_ENV,
+                KEYS_ENV
+            ),
+        )),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            let key = XChaCha20Poly1305::generate_key(&mut OsRng);
+            create_key_file(&path)?.write_all(key_entry(1, &key).as_bytes())?;
+            println!("Generated a new server key in {}", path.display());
+            Ok(Keyring {
+                keys: std::iter::once((1, key)).collect(),
+                file: Some(path),
+            })
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// Loads the server keys, generating a key file on the first run. Must be called before any
+// data file is read or written.
+pub fn init() -> io::Result<()> {
+    set_keyring(load(true)?)
+}
+
+// Like init, for commands that only read or rewrite existing data: without keys there's nothing
+// they could decrypt, so a missing key file is an error instead of a new key.
+pub fn init_existing() -> io::Result<()> {
+    set_keyring(load(false)?)
+}
+
+fn set_keyring(keyring: Keyring) -> io::Result<()> {
+    KEYRING
+        .set(RwLock::new(keyring))
+        .map_err(|_| invalid("keys were already loaded".to_owned()))
+}
+
+fn keyring() -> &'static RwLock<Keyring> {
+    KEYRING.get().expect("server keys are not loaded")
+}
+
+pub fn seal(plaintext: &[u8]) -> Vec<u8> {
+    let keyring = keyring().read().unwrap();
+    let (id, key) = keyring.keys.iter().next_back().unwrap();
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+    let mut sealed = Vec::with_capacity(HEADER_LEN + plaintext.len() + 16);
+    sealed.extend_from_slice(MAGIC);
+    sealed.extend_from_slice(&id.to_le_bytes());
+    sealed.extend_from_slice(&nonce);
+    let ciphertext = XChaCha20Poly1305::new(key)
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad: &sealed[..HEADER_LEN],
+            },
+        )
+        .unwrap();
+    sealed.extend_from_slice(&ciphertext);
+    sealed
+}
+
+// Files written before encryption was added are plain JSON. They are encrypted once by
+// `serialize encrypt`, after that a file without the header is refused rather than trusted.
+pub fn is_sealed(contents: &[u8]) -> bool {
+    contents.starts_with(MAGIC)
+}
+
+pub fn open(contents: &[u8]) -> io::Result<Vec<u8>> {
+    if !is_sealed(contents) {
+        return Err(invalid(
+            "file is not encrypted, files from before encryption are encrypted with `serialize encrypt`"
+                .to_owned(),
+        ));
+    }
+    if contents.len() < HEADER_LEN {
+        return Err(invalid("encrypted file is truncated".to_owned()));
+    }
+    let mut id = [0; KEY_ID_LEN];
+    id.copy_from_slice(&contents[MAGIC.len()..MAGIC.len() + KEY_ID_LEN]);
+    let id = u32::from_le_bytes(id);
+
+    let keyring = keyring().read().unwrap();
+    let key = keyring.keys.get(&id).ok_or_else(|| {
+        invalid(format!(
+            "file is encrypted with key {}, which isn't loaded",
+            id
+        ))
+    })?;
+    let nonce = XNonce::from_slice(&contents[MAGIC.len() + KEY_ID_LEN..HEADER_LEN]);
+    XChaCha20Poly1305::new(key)
+        .decrypt(
+            nonce,
+            Payload {
+                msg: &contents[HEADER_LEN..],
+                aad: &contents[..HEADER_LEN],
+            },
+        )
+        .map_err(|_| invalid("file was tampered with or the key is wrong".to_owned()))
+}
+
+pub fn read(path: &str) -> io::Result<Vec<u8>> {
+    open(&fs::read(path)?)
+}
+
+// Adds a new key to the key file, to encrypt everything from now on. Older keys stay in the file
+// so backups sealed with them can still be read, they can be removed by hand once those are gone.
+// Returns the new key's id.
+pub fn rotate() -> io::Result<u32> {
+    let mut keyring = keyring().write().unwrap();
+    let path = match &keyring.file {
+        Some(path) => path.clone(),
+        None => {
+            return Err(invalid(format!(
+                "keys come from {}, add the new key there and restart the server",
+                KEYS_ENV
+            )))
+        }
+    };
+    let id = keyring.keys.keys().next_back().unwrap() + 1;
+    let key = XChaCha20Poly1305::generate_key(&mut OsRng);
+
+    let mut contents = fs::read_to_string(&path)?;
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        contents.push('\n');
+    }
+    contents.push_str(&key_entry(id, &key));
+    let partial = path.with_extension("key.partial");
+    let _ = fs::remove_file(&partial);
+    create_key_file(&partial)?.write_all(contents.as_bytes())?;
+    fs::rename(&partial, &path)?;
+
+    keyring.keys.insert(id, key);
+    Ok(id)
+}
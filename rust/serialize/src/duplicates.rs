@@ -0,0 +1,148 @@
+use crate::Contact;
+
+use std::collections::HashMap;
+
+// Names at least this similar (1.0 being equal once normalized) are taken as the same person.
+const NAME_SIMILARITY: f64 = 0.8;
+// Phone numbers are taken as the same if one ends with the other, e.g. with and without the
+// country code, as long as the shorter one has at least this many digits.
+const PHONE_SUFFIX_DIGITS: u32 = 7;
+// Names are compared pairwise, each comparison costing the product of their lengths. On a large
+// list the comparisons stop once they have cost this much, the names left are only matched by
+// phone number and email.
+const MAX_NAME_COST: usize = 100_000_000;
+
+// Lowercases, drops punctuation and sorts the words, so "Silva, Ana" and "ana silva" match.
+pub fn normalize_name(name: &str) -> String {
+    let cleaned: String = name
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect();
+    let mut words: Vec<&str> = cleaned.split_whitespace().collect();
+    words.sort_unstable();
+    words.join(" ")
+}
+
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == cb { 0 } else { 1 };
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+fn similarity(a: &[char], b: &[char]) -> f64 {
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 0.0;
+    }
+    1.0 - levenshtein(a, b) as f64 / longest as f64
+}
+
+fn email_key(contact: &Contact) -> Option<String> {
+    contact
+        .email
+        .as_ref()
+        .map(|email| email.trim().to_ascii_lowercase())
+}
+
+fn root(parents: &mut [usize], i: usize) -> usize {
+    let mut i = i;
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+fn join(parents: &mut [usize], i: usize, j: usize) {
+    let (a, b) = (root(parents, i), root(parents, j));
+    parents[b] = a;
+}
+
+// Groups contacts that are likely the same person, transitively: if A looks like B and B looks
+// like C, all three end up in one cluster. Only clusters of two or more are returned, each
+// sorted by phone number.
+//
+// Phone numbers and emails are looked up rather than compared pair by pair. Names can't be,
+// but two names can only be similar enough if their lengths are close, so each name is only
+// compared with the ones of about its length, up to MAX_NAME_COST in all.
+pub fn find(contacts: &HashMap<u64, Contact>) -> Vec<Vec<u64>> {
+    let mut list: Vec<&Contact> = contacts.values().collect();
+    list.sort_by_key(|contact| contact.phone);
+    let mut parents: Vec<usize> = (0..list.len()).collect();
+
+    let indices: HashMap<u64, usize> = list
+        .iter()
+        .enumerate()
+        .map(|(i, contact)| (contact.phone, i))
+        .collect();
+    let mut emails: HashMap<String, usize> = HashMap::new();
+    for (i, contact) in list.iter().enumerate() {
+        // The numbers this one ends with, shortest first
+        let mut modulus = 10u64.pow(PHONE_SUFFIX_DIGITS);
+        while modulus <= contact.phone {
+            let suffix = contact.phone % modulus;
+            if suffix >= modulus / 10 {
+                if let Some(&j) = indices.get(&suffix) {
+                    join(&mut parents, i, j);
+                }
+            }
+            modulus = match modulus.checked_mul(10) {
+                Some(modulus) => modulus,
+                None => break,
+            };
+        }
+        if let Some(key) = email_key(contact) {
+            match emails.get(&key) {
+                Some(&j) => join(&mut parents, j, i),
+                None => {
+                    emails.insert(key, i);
+                }
+            }
+        }
+    }
+
+    let mut names: Vec<(usize, Vec<char>)> = list
+        .iter()
+        .enumerate()
+        .map(|(i, contact)| (i, normalize_name(&contact.name).chars().collect()))
+        .filter(|(_, name): &(usize, Vec<char>)| !name.is_empty())
+        .collect();
+    names.sort_by_key(|(_, name)| name.len());
+    let mut cost = 0;
+    'names: for (n, (i, a)) in names.iter().enumerate() {
+        for (j, b) in &names[n + 1..] {
+            // The edit distance is at least the difference in length
+            if (a.len() as f64) < b.len() as f64 * NAME_SIMILARITY {
+                break;
+            }
+            cost += a.len() * b.len();
+            if cost > MAX_NAME_COST {
+                break 'names;
+            }
+            if similarity(a, b) >= NAME_SIMILARITY {
+                join(&mut parents, *i, *j);
+            }
+        }
+    }
+
+    let mut clusters: HashMap<usize, Vec<u64>> = HashMap::new();
+    for (i, contact) in list.iter().enumerate() {
+        let r = root(&mut parents, i);
+        clusters.entry(r).or_default().push(contact.phone);
+    }
+    let mut clusters: Vec<Vec<u64>> = clusters
+        .into_values()
+        .filter(|phones| phones.len() > 1)
+        .collect();
+    clusters.sort();
+    clusters
+}
@@ -0,0 +1,335 @@
+// Birthdays and anniversaries of contacts: the upcoming ones in a window of days, and all of them
+// as a yearly recurring iCalendar (.ics) export.
+use crate::Contact;
+
+use serde::{Deserialize, Serialize};
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const DEFAULT_WINDOW: u32 = 30; // days
+pub const MAX_WINDOW: u32 = 366;
+// Lines of an .ics file are folded after this many bytes.
+const ICS_LINE_LENGTH: usize = 75;
+
+// A calendar date, or only a day of the year when the year isn't known. Written as YYYY-MM-DD or
+// --MM-DD, the way vCard writes birthdays without a year.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Date {
+    year: Option<i32>,
+    month: u32,
+    day: u32,
+}
+
+fn is_leap(year: i32) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: Option<i32>, month: u32) -> u32 {
+    match month {
+        2 if year.is_none_or(is_leap) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Days since 1970-01-01, and back (http://howardhinnant.github.io/date_algorithms.html).
+fn days_from_civil(year: i32, month: u32, day: u32) -> i64 {
+    let year = i64::from(year) - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i32, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = (year_of_era + era * 400 + i64::from(month <= 2)) as i32;
+    (year, month, day)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+impl Date {
+    pub fn today() -> Self {
+        Date::from_days((now() / 86_400) as i64)
+    }
+
+    fn from_days(days: i64) -> Self {
+        let (year, month, day) = civil_from_days(days);
+        Date {
+            year: Some(year),
+            month,
+            day,
+        }
+    }
+
+    fn days(&self) -> Option<i64> {
+        self.year
+            .map(|year| days_from_civil(year, self.month, self.day))
+    }
+
+    // The day it falls on in `year`. Those on February 29th are kept on the 28th in other years.
+    fn in_year(&self, year: i32) -> i64 {
+        let day = self.day.min(days_in_month(Some(year), self.month));
+        days_from_civil(year, self.month, day)
+    }
+
+    pub fn has_year(&self) -> bool {
+        self.year.is_some()
+    }
+
+    pub fn parse(date: &str) -> Option<Self> {
+        let number = |field: &str| -> Option<u32> {
+            if field.is_empty() || !field.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            field.parse().ok()
+        };
+        let (year, month_day) = match date.strip_prefix("--") {
+            Some(month_day) => (None, month_day),
+            None if date.len() == 5 => (None, date),
+            None => {
+                let (year, month_day) = date.split_at(date.find('-')?);
+                let year = number(year).filter(|year| (1..=9999).contains(year))?;
+                (Some(year as i32), &month_day[1..])
+            }
+        };
+        let (month, day) = month_day.split_once('-')?;
+        let (month, day) = (number(month)?, number(day)?);
+        if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+            return None;
+        }
+        Some(Date { year, month, day })
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.year {
+            Some(year) => write!(f, "{:04}-{:02}-{:02}", year, self.month, self.day),
+            None => write!(f, "--{:02}-{:02}", self.month, self.day),
+        }
+    }
+}
+
+impl TryFrom<String> for Date {
+    type Error = String;
+
+    fn try_from(date: String) -> Result<Self, String> {
+        Date::parse(&date).ok_or_else(|| format!("not a date: {}", date))
+    }
+}
+
+impl From<Date> for String {
+    fn from(date: Date) -> Self {
+        date.to_string()
+    }
+}
+
+// The dates kept on a contact.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Dates {
+    pub birthday: Option<Date>,
+    pub anniversary: Option<Date>,
+}
+
+// Dates sent along with an edit. Like emails, a date that isn't sent is left as it is and an
+// empty one clears it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DateEdits {
+    birthday: Option<Option<Date>>,
+    anniversary: Option<Option<Date>>,
+}
+
+impl DateEdits {
+    // None if one of the dates is malformed.
+    pub fn parse(birthday: Option<&str>, anniversary: Option<&str>) -> Option<Self> {
+        let edit = |date: Option<&str>| match date {
+            None => Some(None),
+            Some("") => Some(Some(None)),
+            Some(date) => Date::parse(date).map(|date| Some(Some(date))),
+        };
+        Some(DateEdits {
+            birthday: edit(birthday)?,
+            anniversary: edit(anniversary)?,
+        })
+    }
+
+    pub fn apply(&self, dates: &mut Dates) {
+        if let Some(birthday) = self.birthday {
+            dates.birthday = birthday;
+        }
+        if let Some(anniversary) = self.anniversary {
+            dates.anniversary = anniversary;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum EventKind {
+    Birthday,
+    Anniversary,
+}
+
+impl EventKind {
+    fn of(dates: &Dates) -> impl Iterator<Item = (EventKind, Date)> {
+        let birthday = dates.birthday.map(|date| (EventKind::Birthday, date));
+        let anniversary = dates.anniversary.map(|date| (EventKind::Anniversary, date));
+        birthday.into_iter().chain(anniversary)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Event {
+    kind: EventKind,
+    name: String,
+    phone: u64,
+    date: Date,         // the day it falls on this time
+    years: Option<u32>, // age or years married then, when the year is known
+}
+
+// Birthdays and anniversaries falling within `days` days starting at `from`, soonest first.
+// `from` must be a full date.
+pub fn upcoming<'a>(
+    contacts: impl Iterator<Item = &'a Contact>,
+    from: Date,
+    days: u32,
+) -> Vec<Event> {
+    let (start, year) = match (from.days(), from.year) {
+        (Some(start), Some(year)) => (start, year),
+        _ => return Vec::new(),
+    };
+    let end = start + i64::from(days.min(MAX_WINDOW));
+    let mut events: Vec<Event> = contacts
+        .flat_map(|contact| {
+            EventKind::of(&contact.dates).filter_map(move |(kind, date)| {
+                let mut next = date.in_year(year);
+                if next < start {
+                    next = date.in_year(year + 1);
+                }
+                let next = Date::from_days(next);
+                let years = match date.year {
+                    Some(since) => Some(u32::try_from(next.year? - since).ok()?),
+                    None => None,
+                };
+                if next.days()? >= end {
+                    return None;
+                }
+                Some(Event {
+                    kind,
+                    name: contact.name.clone(),
+                    phone: contact.phone,
+                    date: next,
+                    years,
+                })
+            })
+        })
+        .collect();
+    events.sort_by_key(|event| (event.date.days(), event.phone));
+    events
+}
+
+// Text values in iCalendar escape backslashes, semicolons, commas and newlines.
+fn ics_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+// Ends the line with CRLF, folding it into continuation lines that start with a space.
+fn ics_line(ics: &mut String, line: &str) {
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > ICS_LINE_LENGTH {
+            ics.push_str("\r\n ");
+            length = 1;
+        }
+        ics.push(c);
+        length += c.len_utf8();
+    }
+    ics.push_str("\r\n");
+}
+
+// Every birthday and anniversary of the account as a yearly event.
+pub fn calendar<'a>(account: &str, contacts: impl Iterator<Item = &'a Contact>) -> String {
+    let now = now();
+    let (year, month, day) = civil_from_days((now / 86_400) as i64);
+    let stamp = format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        now % 86_400 / 3600,
+        now % 3600 / 60,
+        now % 60
+    );
+
+    let mut contacts: Vec<&Contact> = contacts.collect();
+    contacts.sort_by_key(|contact| contact.phone);
+    let mut ics = String::new();
+    ics_line(&mut ics, "BEGIN:VCALENDAR");
+    ics_line(&mut ics, "VERSION:2.0");
+    ics_line(&mut ics, "PRODID:-//serialize//contacts//EN");
+    for contact in contacts {
+        for (kind, date) in EventKind::of(&contact.dates) {
+            // Without a year it starts in a leap year, so February 29th is a valid start
+            let start = Date {
+                year: Some(date.year.unwrap_or(2000)),
+                ..date
+            };
+            let summary = match kind {
+                EventKind::Birthday => format!("{}'s birthday", contact.name),
+                EventKind::Anniversary => format!("{}'s anniversary", contact.name),
+            };
+            ics_line(&mut ics, "BEGIN:VEVENT");
+            ics_line(
+                &mut ics,
+                &format!(
+                    "UID:{}",
+                    ics_text(&format!("{:?}-{}-{}", kind, contact.phone, account))
+                ),
+            );
+            ics_line(&mut ics, &format!("DTSTAMP:{}", stamp));
+            ics_line(
+                &mut ics,
+                &format!(
+                    "DTSTART;VALUE=DATE:{:04}{:02}{:02}",
+                    start.year.unwrap(),
+                    start.month,
+                    start.day
+                ),
+            );
+            // The last day of February in years without a 29th
+            if date.month == 2 && date.day == 29 {
+                ics_line(&mut ics, "RRULE:FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=-1");
+            } else {
+                ics_line(&mut ics, "RRULE:FREQ=YEARLY");
+            }
+            ics_line(&mut ics, &format!("SUMMARY:{}", ics_text(&summary)));
+            ics_line(&mut ics, "TRANSP:TRANSPARENT");
+            ics_line(&mut ics, "END:VEVENT");
+        }
+    }
+    ics_line(&mut ics, "END:VCALENDAR");
+    ics
+}
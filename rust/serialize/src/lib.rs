@@ -0,0 +1,1795 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+
+use std::io::{self, prelude::*};
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+mod admin;
+#[doc(hidden)]
+pub mod bench;
+pub mod crypto;
+mod duplicates;
+mod events;
+mod limits;
+mod migrations;
+mod net;
+pub mod offline;
+mod replication;
+mod sessions;
+mod snapshots;
+mod store;
+mod subscriptions;
+use events::{Date, DateEdits, Dates, Event};
+use limits::LimitedReader;
+pub use limits::Limits;
+use migrations::Kind;
+pub use net::Endpoint;
+use net::{Listener, Stream};
+pub use replication::Upstream;
+use replication::{Replicas, Replication};
+use sessions::Sessions;
+pub use store::{FileStore, MemoryStore, Store};
+use subscriptions::{SharedStream, Subscriptions};
+
+// Files in the store
+const DATA_FILE: &str = "Data.json";
+const CONTACTS_LIST_FILE: &str = "Contacts_list.json";
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Query {
+    Save,
+    Add,
+    Remove,
+    SearchByName,
+    SearchByPhone,
+    ShowList,
+    Done,
+    Login,
+    CreateAccount,
+    Update,
+    Subscribe,
+    Changed,
+    SyncSince,
+    Conflict,
+    DeleteAccount,
+    RenameAccount,
+    ExportData,
+    FindDuplicates,
+    Merge,
+    QuotaExceeded,
+    FieldTooLong,
+    MessageTooLarge,
+    AccountLocked,
+    Admin,
+    Forbidden,
+    Replicate,
+    ReadOnly,
+    UpcomingEvents,
+    ExportCalendar,
+    InvalidDate,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+enum ChangeKind {
+    Added,
+    Updated,
+    Removed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Change {
+    revision: u64,
+    kind: ChangeKind,
+    name: String,
+    phone: u64,
+    #[serde(default)]
+    email: Option<String>,
+    #[serde(flatten)]
+    dates: Dates,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Ans {
+    query: Query,
+    name: Option<String>,
+    phone: Option<u64>,
+    revision: Option<u64>,
+    change: Option<Change>,
+    export: Option<Export>,
+    email: Option<String>,
+    phones: Option<Vec<u64>>,
+    preview: Option<bool>,
+    clusters: Option<Vec<Vec<Contact>>>,
+    admin: Option<admin::Command>,
+    admin_reply: Option<Result<admin::Reply, String>>,
+    birthday: Option<String>,
+    anniversary: Option<String>,
+    from: Option<String>, // first day of the window of upcoming events, today if not set
+    days: Option<u32>,
+    events: Option<Vec<Event>>,
+    calendar: Option<String>, // .ics
+}
+
+impl Ans {
+    fn new(query: Query) -> Self {
+        Ans {
+            query,
+            name: None,
+            phone: None,
+            revision: None,
+            change: None,
+            export: None,
+            email: None,
+            phones: None,
+            preview: None,
+            clusters: None,
+            admin: None,
+            admin_reply: None,
+            birthday: None,
+            anniversary: None,
+            from: None,
+            days: None,
+            events: None,
+            calendar: None,
+        }
+    }
+
+    // Whether the request carries the fields its query needs. Incomplete requests are answered
+    // with an error on the query instead of being handled.
+    fn is_complete(&self) -> bool {
+        match self.query {
+            Query::Add | Query::Update | Query::Merge => {
+                self.name.is_some() && self.phone.is_some()
+            }
+            Query::Remove | Query::SearchByPhone => self.phone.is_some(),
+            Query::SearchByName | Query::RenameAccount => self.name.is_some(),
+            _ => true,
+        }
+    }
+
+    // Whether the request changes accounts or contacts, which a standby leaves to the primary.
+    fn writes(&self) -> bool {
+        match self.query {
+            Query::Add
+            | Query::Update
+            | Query::Remove
+            | Query::DeleteAccount
+            | Query::RenameAccount
+            | Query::Replicate => true,
+            Query::Merge => self.preview == Some(false),
+            Query::Admin => self.admin.as_ref().is_some_and(admin::Command::writes),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Feedback {
+    result: Result<Ans, Query>,
+}
+
+impl Feedback {
+    fn new_ok(query: Query, name: Option<String>, phone: Option<u64>) -> Self {
+        Feedback {
+            result: Ok(Ans {
+                name,
+                phone,
+                ..Ans::new(query)
+            }),
+        }
+    }
+
+    fn new_revision(query: Query, revision: u64) -> Self {
+        Feedback {
+            result: Ok(Ans {
+                revision: Some(revision),
+                ..Ans::new(query)
+            }),
+        }
+    }
+
+    fn new_change(query: Query, change: &Change) -> Self {
+        Feedback {
+            result: Ok(Ans {
+                name: Some(change.name.clone()),
+                phone: Some(change.phone),
+                revision: Some(change.revision),
+                email: change.email.clone(),
+                birthday: change.dates.birthday.map(String::from),
+                anniversary: change.dates.anniversary.map(String::from),
+                ..Ans::new(query)
+            }),
+        }
+    }
+
+    fn new_export(export: Export) -> Self {
+        Feedback {
+            result: Ok(Ans {
+                revision: Some(export.revision),
+                export: Some(export),
+                ..Ans::new(Query::ExportData)
+            }),
+        }
+    }
+
+    fn new_err(query: Query) -> Self {
+        Feedback { result: Err(query) }
+    }
+
+    fn new_event(change: Change) -> Self {
+        Feedback {
+            result: Ok(Ans {
+                revision: Some(change.revision),
+                change: Some(change),
+                ..Ans::new(Query::Changed)
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Contact {
+    name: String,
+    phone: u64,
+    revision: u64, // revision of the last change to this contact
+    email: Option<String>,
+    #[serde(flatten)]
+    dates: Dates,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Contacts {
+    contacts_list: HashMap<String, HashMap<u64, Contact>>, // <account name, contacts list>
+    revisions: HashMap<String, u64>,                       // <account name, last revision>
+    removed: HashMap<String, HashMap<u64, u64>>, // <account name, <phone, revision it was removed at>>
+}
+
+// Everything the server holds about an account, as handed out by `Query::ExportData`.
+#[derive(Debug, Serialize, Deserialize)]
+struct Export {
+    account: String,
+    exported_at: u64, // seconds since the unix epoch
+    revision: u64,
+    contacts: Vec<Contact>,
+}
+
+// Consecutive failed logins after which the account is locked until an admin unlocks it.
+const MAX_FAILED_LOGINS: u32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum Role {
+    User,
+    Admin,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Account {
+    name: String,
+    password: String,
+    role: Role,
+    failed_logins: u32,
+    locked: bool,
+}
+
+// What clients send to log in, create an account or confirm its deletion.
+#[derive(Debug, Serialize, Deserialize)]
+struct Credentials {
+    name: String,
+    password: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Data {
+    clients: Vec<Account>,
+}
+
+impl Data {
+    fn new() -> Self {
+        Data {
+            clients: Vec::new(),
+        }
+    }
+
+    fn check_login(&self, creds: &Credentials) -> bool {
+        self.clients
+            .iter()
+            .any(|acc| acc.name == creds.name && acc.password == creds.password)
+    }
+
+    // Counts failed attempts, locking the account after too many in a row.
+    fn login(&mut self, creds: &Credentials, store: &dyn Store) -> Result<(), Query> {
+        let acc = match self.clients.iter_mut().find(|acc| acc.name == creds.name) {
+            Some(_acc) => _acc,
+            None => return Err(Query::Login),
+        };
+        if acc.locked {
+            return Err(Query::AccountLocked);
+        }
+        if acc.password != creds.password {
+            acc.failed_logins += 1;
+            if acc.failed_logins < MAX_FAILED_LOGINS {
+                return Err(Query::Login);
+            }
+            acc.locked = true;
+            self.save(store);
+            return Err(Query::AccountLocked);
+        }
+        acc.failed_logins = 0;
+        Ok(())
+    }
+
+    fn has_client(&self, name: &str) -> bool {
+        self.clients.iter().any(|acc| acc.name == name)
+    }
+
+    fn is_admin(&self, name: &str) -> bool {
+        self.clients
+            .iter()
+            .any(|acc| acc.name == name && acc.role == Role::Admin)
+    }
+
+    // fn search_client(&self, creds: &Account) -> Option<&Account> {
+    //     self.clients.iter().find_map(|acc| {
+    //         if acc.name == creds.name {
+    //             Some(acc)
+    //         } else {
+    //             None
+    //         }
+    //     })
+    // }
+
+    fn add_client(&mut self, name: &str, password: &str) -> bool {
+        if self.clients.iter().any(|acc| acc.name == name) {
+            return false;
+        }
+
+        let (name, password) = (name.to_owned(), password.to_owned());
+        self.clients.push(Account::new(&name, &password));
+
+        true
+    }
+
+    fn remove_client(&mut self, name: &str) {
+        self.clients.retain(|acc| acc.name != name);
+    }
+
+    fn rename_client(&mut self, name: &str, new_name: &str) -> bool {
+        if self.clients.iter().any(|acc| acc.name == new_name) {
+            return false;
+        }
+        match self.clients.iter_mut().find(|acc| acc.name == name) {
+            Some(acc) => {
+                acc.name = new_name.to_owned();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn save(&self, store: &dyn Store) {
+        if let Err(e) = self.try_save(store) {
+            println!("Couldn't save {}: {}", DATA_FILE, e);
+        }
+    }
+
+    fn try_save(&self, store: &dyn Store) -> io::Result<()> {
+        store.write(DATA_FILE, migrations::to_string(&self).as_bytes())
+    }
+
+    // Returns the format version the file was written in.
+    fn recover(&mut self, store: &dyn Store) -> Result<u64, String> {
+        Ok(match load_file(store, DATA_FILE, Kind::Data)? {
+            Some((data, version)) => {
+                *self = data;
+                version
+            }
+            None => migrations::CURRENT_VERSION,
+        })
+    }
+}
+
+impl Contact {
+    fn new(name: &str, phone: u64, email: Option<String>) -> Self {
+        let name = name.to_owned();
+        Contact {
+            name,
+            phone,
+            revision: 0,
+            email,
+            dates: Dates::default(),
+        }
+    }
+}
+
+impl Account {
+    fn new(name: &str, password: &str) -> Self {
+        let name = name.to_owned();
+        let password = password.to_owned();
+        Account {
+            name,
+            password,
+            role: Role::User,
+            failed_logins: 0,
+            locked: false,
+        }
+    }
+}
+
+impl Contacts {
+    fn new() -> Self {
+        Contacts {
+            contacts_list: HashMap::default(),
+            revisions: HashMap::default(),
+            removed: HashMap::default(),
+        }
+    }
+
+    fn next_revision(&mut self, account: &str) -> u64 {
+        let revision = self.revisions.entry(account.to_owned()).or_insert(0);
+        *revision += 1;
+        *revision
+    }
+
+    fn revision(&self, account: &str) -> u64 {
+        self.revisions.get(account).copied().unwrap_or(0)
+    }
+
+    // Sessions whose account was renamed or deleted by another session are told to log in again.
+    fn session_ended(&self, stream: &mut Stream, account: &str) -> bool {
+        if self.contacts_list.contains_key(account) {
+            return false;
+        }
+        let _ = serde_json::to_writer(stream, &Feedback::new_err(Query::Login));
+        true
+    }
+
+    fn remove_account(&mut self, account: &str) {
+        self.contacts_list.remove(account);
+        self.revisions.remove(account);
+        self.removed.remove(account);
+    }
+
+    fn rename_account(&mut self, account: &str, new_name: &str) {
+        if let Some(list) = self.contacts_list.remove(account) {
+            self.contacts_list.insert(new_name.to_owned(), list);
+        }
+        if let Some(revision) = self.revisions.remove(account) {
+            self.revisions.insert(new_name.to_owned(), revision);
+        }
+        if let Some(removed) = self.removed.remove(account) {
+            self.removed.insert(new_name.to_owned(), removed);
+        }
+    }
+
+    fn export(&self, account: &str) -> Export {
+        let mut contacts: Vec<Contact> = self.contacts_list[account].values().cloned().collect();
+        contacts.sort_by_key(|contact| contact.phone);
+        Export {
+            account: account.to_owned(),
+            exported_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            revision: self.revision(account),
+            contacts,
+        }
+    }
+
+    // Stamps a change to `phone` with the next revision of `account`. Removals are kept as
+    // tombstones so that clients syncing from an older revision still learn about them.
+    fn record(&mut self, account: &str, kind: ChangeKind, name: &str, phone: u64) -> Change {
+        let revision = self.next_revision(account);
+        let removed = self.removed.entry(account.to_owned()).or_default();
+        let mut email = None;
+        let mut dates = Dates::default();
+        match kind {
+            ChangeKind::Removed => {
+                removed.insert(phone, revision);
+            }
+            ChangeKind::Added | ChangeKind::Updated => {
+                removed.remove(&phone);
+                if let Some(contact) = self.contacts_list.get_mut(account).unwrap().get_mut(&phone)
+                {
+                    contact.revision = revision;
+                    email = contact.email.clone();
+                    dates = contact.dates;
+                }
+            }
+        }
+        Change {
+            revision,
+            kind,
+            name: name.to_owned(),
+            phone,
+            email,
+            dates,
+        }
+    }
+
+    // An edit made against revision `base` conflicts if the contact changed after that.
+    fn is_stale(&self, account: &str, phone: u64, base: Option<u64>) -> bool {
+        let base = match base {
+            Some(_base) => _base,
+            None => return false,
+        };
+        let changed_at = match self.contacts_list.get(account).unwrap().get(&phone) {
+            Some(contact) => contact.revision,
+            None => self
+                .removed
+                .get(account)
+                .and_then(|removed| removed.get(&phone).copied())
+                .unwrap_or(0),
+        };
+        changed_at > base
+    }
+
+    // Applies a change made on the primary, with the revision it got there.
+    fn apply(&mut self, account: &str, change: &Change) {
+        let revision = self.revisions.entry(account.to_owned()).or_insert(0);
+        *revision = (*revision).max(change.revision);
+        let list = self.contacts_list.entry(account.to_owned()).or_default();
+        let removed = self.removed.entry(account.to_owned()).or_default();
+        match change.kind {
+            ChangeKind::Added | ChangeKind::Updated => {
+                removed.remove(&change.phone);
+                list.insert(
+                    change.phone,
+                    Contact {
+                        name: change.name.clone(),
+                        phone: change.phone,
+                        revision: change.revision,
+                        email: change.email.clone(),
+                        dates: change.dates,
+                    },
+                );
+            }
+            ChangeKind::Removed => {
+                list.remove(&change.phone);
+                removed.insert(change.phone, change.revision);
+            }
+        }
+    }
+
+    // Makes the contacts of `account` match `snapshot`, recording every difference as a change so
+    // that synced clients pick the restore up like any other edit.
+    fn restore_account(&mut self, account: &str, snapshot: &HashMap<u64, Contact>) -> Vec<Change> {
+        let current = self.contacts_list.entry(account.to_owned()).or_default();
+        let mut removed: Vec<(String, u64)> = current
+            .values()
+            .filter(|contact| !snapshot.contains_key(&contact.phone))
+            .map(|contact| (contact.name.clone(), contact.phone))
+            .collect();
+        let mut restored: Vec<(ChangeKind, Contact)> = snapshot
+            .values()
+            .filter_map(|contact| match current.get(&contact.phone) {
+                Some(existing)
+                    if existing.name == contact.name
+                        && existing.email == contact.email
+                        && existing.dates == contact.dates =>
+                {
+                    None
+                }
+                Some(_) => Some((ChangeKind::Updated, contact.clone())),
+                None => Some((ChangeKind::Added, contact.clone())),
+            })
+            .collect();
+        removed.sort_by_key(|(_, phone)| *phone);
+        restored.sort_by_key(|(_, contact)| contact.phone);
+
+        let mut changes = Vec::new();
+        for (name, phone) in removed {
+            self.contacts_list.get_mut(account).unwrap().remove(&phone);
+            changes.push(self.record(account, ChangeKind::Removed, &name, phone));
+        }
+        for (kind, contact) in restored {
+            let (name, phone) = (contact.name.clone(), contact.phone);
+            self.contacts_list
+                .get_mut(account)
+                .unwrap()
+                .insert(phone, contact);
+            changes.push(self.record(account, kind, &name, phone));
+        }
+        changes
+    }
+
+    fn save(&self, store: &dyn Store) {
+        if let Err(e) = self.try_save(store) {
+            println!("Couldn't save {}: {}", CONTACTS_LIST_FILE, e);
+        }
+    }
+
+    fn try_save(&self, store: &dyn Store) -> io::Result<()> {
+        store.write(CONTACTS_LIST_FILE, migrations::to_string(&self).as_bytes())
+    }
+
+    // Returns the format version the file was written in.
+    fn recover(&mut self, store: &dyn Store) -> Result<u64, String> {
+        Ok(
+            match load_file(store, CONTACTS_LIST_FILE, Kind::Contacts)? {
+                Some((contacts, version)) => {
+                    *self = contacts;
+                    version
+                }
+                None => migrations::CURRENT_VERSION,
+            },
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn add_contact(
+        &mut self,
+        stream: &mut Stream,
+        account: &str,
+        name: &str,
+        phone: u64,
+        email: Option<String>,
+        dates: DateEdits,
+        max_contacts: usize,
+    ) -> Option<Change> {
+        if self.session_ended(stream, account) {
+            return None;
+        }
+        let list = &self.contacts_list[account];
+        if list.contains_key(&phone) {
+            let _ = serde_json::to_writer(stream, &Feedback::new_err(Query::Add));
+            None
+        } else if list.len() >= max_contacts {
+            let _ = serde_json::to_writer(stream, &Feedback::new_err(Query::QuotaExceeded));
+            None
+        } else {
+            let mut new_contact =
+                Contact::new(name, phone, email.filter(|email| !email.is_empty()));
+            dates.apply(&mut new_contact.dates);
+            self.contacts_list
+                .get_mut(account)
+                .unwrap()
+                .insert(phone, new_contact);
+            let change = self.record(account, ChangeKind::Added, name, phone);
+            let _ = serde_json::to_writer(stream, &Feedback::new_change(Query::Add, &change));
+            Some(change)
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn update(
+        &mut self,
+        stream: &mut Stream,
+        account: &str,
+        name: &str,
+        phone: u64,
+        email: Option<String>,
+        dates: DateEdits,
+        base: Option<u64>,
+    ) -> Option<Change> {
+        if self.session_ended(stream, account) {
+            return None;
+        }
+        if self.is_stale(account, phone, base) {
+            let _ = serde_json::to_writer(stream, &Feedback::new_err(Query::Conflict));
+            return None;
+        }
+        match self.contacts_list.get_mut(account).unwrap().get_mut(&phone) {
+            Some(contact) => {
+                contact.name = name.to_owned();
+                // Clients that don't know about emails leave them untouched, an empty one clears it
+                if let Some(email) = email {
+                    contact.email = Some(email).filter(|email| !email.is_empty());
+                }
+                dates.apply(&mut contact.dates);
+                let change = self.record(account, ChangeKind::Updated, name, phone);
+                let _ =
+                    serde_json::to_writer(stream, &Feedback::new_change(Query::Update, &change));
+                Some(change)
+            }
+            None => {
+                let _ = serde_json::to_writer(stream, &Feedback::new_err(Query::Update));
+                None
+            }
+        }
+    }
+
+    fn remove(
+        &mut self,
+        stream: &mut Stream,
+        account: &str,
+        phone: u64,
+        base: Option<u64>,
+    ) -> Option<Change> {
+        if self.session_ended(stream, account) {
+            return None;
+        }
+        if self.is_stale(account, phone, base) {
+            let _ = serde_json::to_writer(stream, &Feedback::new_err(Query::Conflict));
+            return None;
+        }
+        match self.contacts_list.get_mut(account).unwrap().remove(&phone) {
+            Some(_contact) => {
+                let change = self.record(account, ChangeKind::Removed, &_contact.name, phone);
+                let _ =
+                    serde_json::to_writer(stream, &Feedback::new_change(Query::Remove, &change));
+                Some(change)
+            }
+            None => {
+                let _ = serde_json::to_writer(stream, &Feedback::new_err(Query::Remove));
+                None
+            }
+        }
+    }
+
+    fn find_by_name(&self, account: &str, name: &str) -> Vec<u64> {
+        self.contacts_list
+            .get(account)
+            .unwrap()
+            .iter()
+            .filter_map(|(&phone, contact)| {
+                if contact.name == name {
+                    Some(phone)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn search_by_name(&self, mut stream: &mut Stream, account: &str, name: &str) {
+        if self.session_ended(stream, account) {
+            return;
+        }
+        let search = self.find_by_name(account, name);
+        if search.is_empty() {
+            let _ = serde_json::to_writer(
+                stream,
+                &Feedback {
+                    result: Err(Query::SearchByName),
+                },
+            );
+            return;
+        }
+        for phone in search.iter() {
+            let _ = serde_json::to_writer(
+                &mut stream,
+                &Feedback::new_ok(Query::SearchByName, None, Some(phone.to_owned())),
+            );
+        }
+        let _ = serde_json::to_writer(stream, &Feedback::new_ok(Query::Done, None, None));
+    }
+
+    fn search_by_number(&self, stream: &mut Stream, account: &str, phone: u64) {
+        if self.session_ended(stream, account) {
+            return;
+        }
+        let opt = self.contacts_list.get(account).unwrap().get(&phone);
+        if let Some(contact) = opt {
+            let _ = serde_json::to_writer(
+                stream,
+                &Feedback::new_ok(Query::SearchByPhone, Some(contact.name.clone()), None),
+            );
+        } else {
+            let _ = serde_json::to_writer(stream, &Feedback::new_err(Query::SearchByPhone));
+        }
+    }
+
+    fn show_list(&self, mut stream: &mut Stream, account: &str, log: bool) {
+        if self.session_ended(stream, account) {
+            return;
+        }
+        if log {
+            println!("<name>: <phone number>");
+        }
+        for contact in self.contacts_list.get(account).unwrap().iter() {
+            if log {
+                println!("{:?}", contact);
+            }
+            let _ = serde_json::to_writer(
+                &mut stream,
+                &Feedback::new_ok(
+                    Query::ShowList,
+                    Some(contact.1.name.clone()),
+                    Some(*contact.0),
+                ),
+            );
+        }
+    }
+
+    // Sends every change made after revision `since`, oldest first, followed by the revision the
+    // client is now up to date with. A client that is ahead of the server (e.g. after a restore)
+    // gets an error and has to start over from revision 0.
+    fn sync_since(&self, mut stream: &mut Stream, account: &str, since: u64) {
+        if self.session_ended(stream, account) {
+            return;
+        }
+        let revision = self.revision(account);
+        if since > revision {
+            let _ = serde_json::to_writer(stream, &Feedback::new_err(Query::SyncSince));
+            return;
+        }
+
+        let mut changes: Vec<Change> = self
+            .contacts_list
+            .get(account)
+            .unwrap()
+            .values()
+            .filter(|contact| since == 0 || contact.revision > since)
+            .map(|contact| Change {
+                revision: contact.revision,
+                kind: ChangeKind::Added,
+                name: contact.name.clone(),
+                phone: contact.phone,
+                email: contact.email.clone(),
+                dates: contact.dates,
+            })
+            .collect();
+        if since > 0 {
+            if let Some(removed) = self.removed.get(account) {
+                changes.extend(removed.iter().filter(|(_, &rev)| rev > since).map(
+                    |(&phone, &rev)| Change {
+                        revision: rev,
+                        kind: ChangeKind::Removed,
+                        name: String::new(),
+                        phone,
+                        email: None,
+                        dates: Dates::default(),
+                    },
+                ));
+            }
+        }
+        changes.sort_by_key(|change| change.revision);
+
+        for change in changes {
+            let _ = serde_json::to_writer(
+                &mut stream,
+                &Feedback {
+                    result: Ok(Ans {
+                        revision: Some(change.revision),
+                        change: Some(change),
+                        ..Ans::new(Query::SyncSince)
+                    }),
+                },
+            );
+        }
+        let _ = serde_json::to_writer(stream, &Feedback::new_revision(Query::Done, revision));
+    }
+
+    // Birthdays and anniversaries in the `days` days from `from`, today and the next 30 days if
+    // not given.
+    fn upcoming_events(
+        &self,
+        stream: &mut Stream,
+        account: &str,
+        from: Option<&str>,
+        days: Option<u32>,
+    ) {
+        if self.session_ended(stream, account) {
+            return;
+        }
+        let from = match from {
+            Some(from) => Date::parse(from).filter(Date::has_year),
+            None => Some(Date::today()),
+        };
+        let days = days.unwrap_or(events::DEFAULT_WINDOW);
+        let from = match from {
+            Some(_from) if (1..=events::MAX_WINDOW).contains(&days) => _from,
+            _ => {
+                let _ = serde_json::to_writer(stream, &Feedback::new_err(Query::UpcomingEvents));
+                return;
+            }
+        };
+        let events = events::upcoming(self.contacts_list[account].values(), from, days);
+        let _ = serde_json::to_writer(
+            stream,
+            &Feedback {
+                result: Ok(Ans {
+                    events: Some(events),
+                    ..Ans::new(Query::UpcomingEvents)
+                }),
+            },
+        );
+    }
+
+    fn export_calendar(&self, stream: &mut Stream, account: &str) {
+        if self.session_ended(stream, account) {
+            return;
+        }
+        let calendar = events::calendar(account, self.contacts_list[account].values());
+        let _ = serde_json::to_writer(
+            stream,
+            &Feedback {
+                result: Ok(Ans {
+                    calendar: Some(calendar),
+                    ..Ans::new(Query::ExportCalendar)
+                }),
+            },
+        );
+    }
+
+    // A copy of the account's contacts to look for duplicates in, so the comparisons are made
+    // without holding the lock.
+    fn duplicate_candidates(
+        &self,
+        stream: &mut Stream,
+        account: &str,
+    ) -> Option<HashMap<u64, Contact>> {
+        if self.session_ended(stream, account) {
+            return None;
+        }
+        Some(self.contacts_list[account].clone())
+    }
+
+    // Combines the contacts in `phones` into the one kept at `keep`, with the chosen name and
+    // email and the dates of all of them, and removes the rest. With `preview` set nothing is
+    // changed, the client is only shown what the merge would result in.
+    #[allow(clippy::too_many_arguments)]
+    fn merge(
+        &mut self,
+        stream: &mut Stream,
+        account: &str,
+        phones: &[u64],
+        keep: u64,
+        name: &str,
+        email: Option<String>,
+        preview: bool,
+    ) -> Vec<Change> {
+        if self.session_ended(stream, account) {
+            return Vec::new();
+        }
+        let list = &self.contacts_list[account];
+        if phones.len() < 2
+            || name.is_empty()
+            || !phones.contains(&keep)
+            || phones.iter().any(|phone| !list.contains_key(phone))
+        {
+            let _ = serde_json::to_writer(stream, &Feedback::new_err(Query::Merge));
+            return Vec::new();
+        }
+        let mut removed: Vec<u64> = phones.iter().copied().filter(|&p| p != keep).collect();
+        removed.sort_unstable();
+        removed.dedup();
+        // Like an update, an email that isn't sent is kept and an empty one clears it
+        let email = match email {
+            Some(email) => Some(email).filter(|email| !email.is_empty()),
+            None => list[&keep].email.clone(),
+        };
+        // The kept contact's dates win, the ones it lacks are taken from the removed contacts
+        let mut dates = list[&keep].dates;
+        for phone in removed.iter() {
+            let other = list[phone].dates;
+            dates.birthday = dates.birthday.or(other.birthday);
+            dates.anniversary = dates.anniversary.or(other.anniversary);
+        }
+
+        let mut changes = Vec::new();
+        if !preview {
+            for phone in removed.iter() {
+                let contact = self.contacts_list.get_mut(account).unwrap().remove(phone);
+                changes.push(self.record(
+                    account,
+                    ChangeKind::Removed,
+                    &contact.unwrap().name,
+                    *phone,
+                ));
+            }
+            let contact = self
+                .contacts_list
+                .get_mut(account)
+                .unwrap()
+                .get_mut(&keep)
+                .unwrap();
+            contact.name = name.to_owned();
+            contact.email = email.clone();
+            contact.dates = dates;
+            changes.push(self.record(account, ChangeKind::Updated, name, keep));
+        }
+
+        let _ = serde_json::to_writer(
+            stream,
+            &Feedback {
+                result: Ok(Ans {
+                    name: Some(name.to_owned()),
+                    phone: Some(keep),
+                    revision: changes.last().map(|change| change.revision),
+                    email,
+                    phones: Some(removed),
+                    preview: Some(preview),
+                    birthday: dates.birthday.map(String::from),
+                    anniversary: dates.anniversary.map(String::from),
+                    ..Ans::new(Query::Merge)
+                }),
+            },
+        );
+        changes
+    }
+}
+
+fn find_duplicates(stream: &mut Stream, list: &HashMap<u64, Contact>) {
+    let clusters: Vec<Vec<Contact>> = duplicates::find(list)
+        .iter()
+        .map(|phones| phones.iter().map(|phone| list[phone].clone()).collect())
+        .collect();
+    let _ = serde_json::to_writer(
+        stream,
+        &Feedback {
+            result: Ok(Ans {
+                clusters: Some(clusters),
+                ..Ans::new(Query::FindDuplicates)
+            }),
+        },
+    );
+}
+
+// Deletes the account and every contact it holds, once the password is confirmed again.
+fn delete_account(state: &State, account: &str, creds: &Credentials) -> bool {
+    let mut data = state.data.write().unwrap();
+    if creds.name != account || !data.check_login(creds) {
+        return false;
+    }
+    let mut contacts = state.contacts.write().unwrap();
+    data.remove_client(account);
+    contacts.remove_account(account);
+
+    let mut replicas = state.replicas.lock().unwrap();
+    replicas.send(&Replication::AccountDeleted(account.to_owned()));
+    replicas.send_accounts(&data);
+    drop(replicas);
+    data.save(state.store.as_ref());
+    contacts.save(state.store.as_ref());
+    true
+}
+
+// Renames the account and re-keys its contacts while holding both write locks, so no session
+// ever sees one without the other.
+fn rename_account(state: &State, account: &str, new_name: &str) -> bool {
+    let mut data = state.data.write().unwrap();
+    if new_name.is_empty() || !data.rename_client(account, new_name) {
+        return false;
+    }
+    let mut contacts = state.contacts.write().unwrap();
+    contacts.rename_account(account, new_name);
+
+    let mut replicas = state.replicas.lock().unwrap();
+    replicas.send(&Replication::AccountRenamed {
+        from: account.to_owned(),
+        to: new_name.to_owned(),
+    });
+    replicas.send_accounts(&data);
+    drop(replicas);
+    data.save(state.store.as_ref());
+    contacts.save(state.store.as_ref());
+    true
+}
+
+// Answers a request that went past the size limit. The rest of it is still in the stream, so the
+// connection can't be used anymore and is closed afterwards.
+fn reject_oversized<R: Read>(reader: &LimitedReader<R>, stream: &mut Stream) {
+    if reader.exceeded() {
+        let _ = serde_json::to_writer(stream, &Feedback::new_err(Query::MessageTooLarge));
+    }
+}
+
+fn handle_client(mut stream: Stream, state: Arc<State>) {
+    let State {
+        data,
+        contacts,
+        subscriptions,
+        sessions,
+        store,
+        config,
+        ..
+    } = &*state;
+    let limits = config.limits;
+    let reader = stream.try_clone().unwrap();
+    let mut reader = LimitedReader::new(std::io::BufReader::new(reader), limits.max_message_size);
+
+    let mut account_name = loop {
+        reader.start_message();
+        let mut de = serde_json::Deserializer::from_reader(&mut reader);
+        // Requests are always sent as Ok, anything else is as malformed as broken JSON
+        let ans: Ans = match Feedback::deserialize(&mut de).map(|feedback| feedback.result) {
+            Ok(Ok(_ans)) => _ans,
+            _ => {
+                reject_oversized(&reader, &mut stream);
+                return;
+            }
+        };
+
+        let mut de = serde_json::Deserializer::from_reader(&mut reader);
+        let creds: Credentials = match Credentials::deserialize(&mut de) {
+            Ok(_creds) => _creds,
+            Err(_) => {
+                reject_oversized(&reader, &mut stream);
+                return;
+            }
+        };
+        if !limits.fits([Some(creds.name.as_str()), Some(creds.password.as_str())]) {
+            let _ = serde_json::to_writer(&mut stream, &Feedback::new_err(Query::FieldTooLong));
+            continue;
+        }
+
+        match ans.query {
+            Query::CreateAccount if state.standby.load(Ordering::SeqCst) => {
+                let _ = serde_json::to_writer(&mut stream, &Feedback::new_err(Query::ReadOnly));
+            }
+            Query::Login => match state.edit_accounts(|data| data.login(&creds, store.as_ref())) {
+                Ok(()) => {
+                    let _ = serde_json::to_writer(
+                        &mut stream,
+                        &Feedback::new_ok(Query::Login, None, None),
+                    );
+
+                    break creds.name;
+                }
+                Err(query) => {
+                    let _ = serde_json::to_writer(&mut stream, &Feedback::new_err(query));
+                }
+            },
+            Query::CreateAccount => {
+                if state.edit_accounts(|data| data.add_client(&creds.name, &creds.password)) {
+                    let _ = serde_json::to_writer(
+                        &mut stream,
+                        &Feedback::new_ok(Query::CreateAccount, None, None),
+                    );
+
+                    break creds.name.to_string();
+                } else {
+                    let _ = serde_json::to_writer(
+                        &mut stream,
+                        &Feedback::new_err(Query::CreateAccount),
+                    );
+                }
+            }
+            // Nothing else can be asked before logging in
+            _ => {
+                let _ = serde_json::to_writer(&mut stream, &Feedback::new_err(Query::Login));
+            }
+        }
+    };
+    contacts
+        .write()
+        .unwrap()
+        .contacts_list
+        .entry(account_name.clone())
+        .or_default();
+
+    // From here on responses and pushed change events share the stream, so every write goes
+    // through the lock to keep multi-part responses from being interleaved with events. The client
+    // can hang up at any point, failed writes are ignored and the next read ends the session.
+    let session = sessions.lock().unwrap().open(&account_name, &stream);
+    let stream: SharedStream = Arc::new(Mutex::new(stream));
+    let mut subscription: Option<usize> = None;
+    loop {
+        reader.start_message();
+        let mut de = serde_json::Deserializer::from_reader(&mut reader);
+        let ans: Ans = match Feedback::deserialize(&mut de).map(|feedback| feedback.result) {
+            Ok(Ok(_ans)) => _ans,
+            _ => {
+                reject_oversized(&reader, &mut stream.lock().unwrap());
+                break;
+            }
+        };
+        // A delete is followed by the password, sent the same way as at login. It is read before
+        // the request is looked at, so the stream stays in step when the request is refused.
+        let creds = match ans.query {
+            Query::DeleteAccount => {
+                let mut de = serde_json::Deserializer::from_reader(&mut reader);
+                match Credentials::deserialize(&mut de) {
+                    Ok(_creds) => Some(_creds),
+                    Err(_) => {
+                        reject_oversized(&reader, &mut stream.lock().unwrap());
+                        break;
+                    }
+                }
+            }
+            _ => None,
+        };
+        if config.log_requests {
+            println!("{:?}", ans.query);
+        }
+        state.requests.fetch_add(1, Ordering::Relaxed);
+        let mut out = stream.lock().unwrap();
+        if !limits.fits([ans.name.as_deref(), ans.email.as_deref()]) {
+            let _ = serde_json::to_writer(&mut *out, &Feedback::new_err(Query::FieldTooLong));
+            continue;
+        }
+        if !ans.is_complete() {
+            let _ = serde_json::to_writer(&mut *out, &Feedback::new_err(ans.query));
+            continue;
+        }
+        let dates = match DateEdits::parse(ans.birthday.as_deref(), ans.anniversary.as_deref()) {
+            Some(_dates) => _dates,
+            None => {
+                let _ = serde_json::to_writer(&mut *out, &Feedback::new_err(Query::InvalidDate));
+                continue;
+            }
+        };
+        if ans.writes() && state.standby.load(Ordering::SeqCst) {
+            let _ = serde_json::to_writer(&mut *out, &Feedback::new_err(Query::ReadOnly));
+            continue;
+        }
+        let mut renamed_from: Option<String> = None;
+        let mut deleted = false;
+        let changes: Vec<Change> = match ans.query {
+            Query::Save => {
+                state.save();
+
+                let _ =
+                    serde_json::to_writer(&mut *out, &Feedback::new_ok(Query::Done, None, None));
+                Vec::new()
+            }
+            Query::Add => state.edit_contacts(&account_name, |contacts| {
+                contacts
+                    .add_contact(
+                        &mut out,
+                        &account_name,
+                        &ans.name.unwrap(),
+                        ans.phone.unwrap(),
+                        ans.email,
+                        dates,
+                        limits.max_contacts,
+                    )
+                    .into_iter()
+                    .collect()
+            }),
+            Query::Update => state.edit_contacts(&account_name, |contacts| {
+                contacts
+                    .update(
+                        &mut out,
+                        &account_name,
+                        &ans.name.unwrap(),
+                        ans.phone.unwrap(),
+                        ans.email,
+                        dates,
+                        ans.revision,
+                    )
+                    .into_iter()
+                    .collect()
+            }),
+            Query::Remove => state.edit_contacts(&account_name, |contacts| {
+                contacts
+                    .remove(&mut out, &account_name, ans.phone.unwrap(), ans.revision)
+                    .into_iter()
+                    .collect()
+            }),
+            Query::FindDuplicates => {
+                let list = contacts
+                    .read()
+                    .unwrap()
+                    .duplicate_candidates(&mut out, &account_name);
+                if let Some(list) = list {
+                    find_duplicates(&mut out, &list);
+                }
+                Vec::new()
+            }
+            Query::Merge => state.edit_contacts(&account_name, |contacts| {
+                contacts.merge(
+                    &mut out,
+                    &account_name,
+                    &ans.phones.unwrap_or_default(),
+                    ans.phone.unwrap(),
+                    &ans.name.unwrap(),
+                    ans.email,
+                    ans.preview.unwrap_or(true),
+                )
+            }),
+            Query::SyncSince => {
+                contacts.read().unwrap().sync_since(
+                    &mut out,
+                    &account_name,
+                    ans.revision.unwrap_or(0),
+                );
+                Vec::new()
+            }
+            Query::SearchByName => {
+                contacts.read().unwrap().search_by_name(
+                    &mut out,
+                    &account_name,
+                    &ans.name.unwrap(),
+                );
+                Vec::new()
+            }
+            Query::SearchByPhone => {
+                contacts.read().unwrap().search_by_number(
+                    &mut out,
+                    &account_name,
+                    ans.phone.unwrap(),
+                );
+                Vec::new()
+            }
+            Query::ShowList => {
+                contacts
+                    .read()
+                    .unwrap()
+                    .show_list(&mut out, &account_name, config.log_requests);
+
+                let _ =
+                    serde_json::to_writer(&mut *out, &Feedback::new_ok(Query::Done, None, None));
+                Vec::new()
+            }
+            Query::Subscribe => {
+                if subscription.is_none() {
+                    // See below for why the stream lock is let go first. An event can get in
+                    // before the answer, the client tells them apart.
+                    drop(out);
+                    subscription = Some(
+                        subscriptions
+                            .lock()
+                            .unwrap()
+                            .subscribe(&account_name, Arc::clone(&stream)),
+                    );
+                    out = stream.lock().unwrap();
+                }
+                let revision = contacts.read().unwrap().revision(&account_name);
+                let _ = serde_json::to_writer(
+                    &mut *out,
+                    &Feedback::new_revision(Query::Subscribe, revision),
+                );
+                Vec::new()
+            }
+            Query::DeleteAccount => {
+                // The password is confirmed again, read along with the request above
+                if delete_account(&state, &account_name, creds.as_ref().unwrap()) {
+                    deleted = true;
+                    let _ = serde_json::to_writer(
+                        &mut *out,
+                        &Feedback::new_ok(Query::DeleteAccount, None, None),
+                    );
+                } else {
+                    let _ =
+                        serde_json::to_writer(&mut *out, &Feedback::new_err(Query::DeleteAccount));
+                }
+                Vec::new()
+            }
+            Query::RenameAccount => {
+                let new_name = ans.name.unwrap();
+                if rename_account(&state, &account_name, &new_name) {
+                    renamed_from = Some(account_name);
+                    account_name = new_name;
+                    let _ = serde_json::to_writer(
+                        &mut *out,
+                        &Feedback::new_ok(Query::RenameAccount, Some(account_name.clone()), None),
+                    );
+                } else {
+                    let _ =
+                        serde_json::to_writer(&mut *out, &Feedback::new_err(Query::RenameAccount));
+                }
+                Vec::new()
+            }
+            Query::UpcomingEvents => {
+                contacts.read().unwrap().upcoming_events(
+                    &mut out,
+                    &account_name,
+                    ans.from.as_deref(),
+                    ans.days,
+                );
+                Vec::new()
+            }
+            Query::ExportCalendar => {
+                contacts
+                    .read()
+                    .unwrap()
+                    .export_calendar(&mut out, &account_name);
+                Vec::new()
+            }
+            Query::ExportData => {
+                let contacts = contacts.read().unwrap();
+                if !contacts.session_ended(&mut out, &account_name) {
+                    let _ = serde_json::to_writer(
+                        &mut *out,
+                        &Feedback::new_export(contacts.export(&account_name)),
+                    );
+                }
+                Vec::new()
+            }
+            Query::Replicate => {
+                // From here on the connection only carries changes to the standby
+                if data.read().unwrap().is_admin(&account_name) {
+                    if let Err(e) = replication::attach(&state, &out) {
+                        println!("Couldn't start replicating to a standby: {}", e);
+                    }
+                } else {
+                    let _ = serde_json::to_writer(&mut *out, &Feedback::new_err(Query::Forbidden));
+                }
+                Vec::new()
+            }
+            Query::Admin => {
+                // The role is checked on every request, so it can be taken away mid-session
+                let is_admin = data.read().unwrap().is_admin(&account_name);
+                let feedback = match ans.admin {
+                    Some(command) if is_admin => Feedback {
+                        result: Ok(Ans {
+                            admin_reply: Some(admin::run(&state, &command)),
+                            ..Ans::new(Query::Admin)
+                        }),
+                    },
+                    _ => Feedback::new_err(Query::Forbidden),
+                };
+                // An admin can end their own session, so the stream may be closed by now
+                let _ = serde_json::to_writer(&mut *out, &feedback);
+                Vec::new()
+            }
+            _ => Vec::new(),
+        };
+        drop(out);
+
+        // The subscriptions lock is never taken while holding a stream lock, since publishing
+        // takes them the other way around.
+        if let Some(old_name) = renamed_from {
+            subscriptions
+                .lock()
+                .unwrap()
+                .rename(&old_name, &account_name, subscription);
+            sessions.lock().unwrap().rename(session, &account_name);
+        }
+        if deleted {
+            subscriptions.lock().unwrap().remove_account(&account_name);
+            subscription = None;
+        }
+        if !changes.is_empty() {
+            let mut subscriptions = subscriptions.lock().unwrap();
+            for change in &changes {
+                subscriptions.publish(&account_name, subscription, change);
+            }
+        }
+
+        // The account was deleted, or renamed by another session
+        if !contacts
+            .read()
+            .unwrap()
+            .contacts_list
+            .contains_key(&account_name)
+        {
+            break;
+        }
+    }
+
+    if let Some(id) = subscription {
+        subscriptions.lock().unwrap().unsubscribe(&account_name, id);
+    }
+    sessions.lock().unwrap().close(session);
+}
+
+// Restores a snapshot, or one account from it, and saves the result before anyone can edit it.
+// The restore stands even if saving fails, so that error is returned along with what it did.
+fn restore(
+    state: &State,
+    name: &str,
+    account: Option<&str>,
+) -> Result<(snapshots::Restored, io::Result<()>), String> {
+    let snapshot = match snapshots::load(state.store.as_ref(), name) {
+        Ok(_snapshot) => _snapshot,
+        Err(e) => return Err(format!("Couldn't read snapshot {}: {}", name, e)),
+    };
+    if state.standby.load(Ordering::SeqCst) {
+        return Err("A standby can't be restored, restore the primary instead".to_owned());
+    }
+    let mut data = state.data.write().unwrap();
+    let mut contacts = state.contacts.write().unwrap();
+    let restored = snapshots::restore(&snapshot, account, &mut data, &mut contacts)?;
+
+    let mut replicas = state.replicas.lock().unwrap();
+    replicas.send_accounts(&data);
+    for account in restored.removed_accounts.iter() {
+        replicas.send(&Replication::AccountDeleted(account.clone()));
+    }
+    for (account, change) in restored.changes.iter() {
+        replicas.send(&Replication::Changed {
+            account: account.clone(),
+            change: change.clone(),
+        });
+    }
+    drop(replicas);
+    let saved = data
+        .try_save(state.store.as_ref())
+        .and_then(|()| contacts.try_save(state.store.as_ref()));
+    Ok((restored, saved))
+}
+
+// Reads a data file in any known version of the format. Missing and empty files are None.
+fn load_file<T: DeserializeOwned>(
+    store: &dyn Store,
+    file: &str,
+    kind: Kind,
+) -> Result<Option<(T, u64)>, String> {
+    let contents = match store.read(file) {
+        Ok(Some(_contents)) => _contents,
+        Ok(None) => return Ok(None),
+        Err(e) => return Err(format!("{}: {}", file, e)),
+    };
+    if contents.is_empty() {
+        return Ok(None);
+    }
+    migrations::load(kind, &contents)
+        .map(Some)
+        .map_err(|e| format!("{}: {}", file, e))
+}
+
+// Encrypts everything under a new key: the data files and every snapshot are written again.
+fn rotate_key(state: &State) -> io::Result<u32> {
+    let id = crypto::rotate()?;
+    state.save();
+    snapshots::reseal(state.store.as_ref())?;
+    Ok(id)
+}
+
+// Operator commands read from the server's standard input while it keeps serving clients.
+fn console(state: &State) {
+    for line in io::stdin().lock().lines() {
+        let line = match line {
+            Ok(_line) => _line,
+            Err(_) => break,
+        };
+        let args: Vec<&str> = line.split_whitespace().collect();
+        match args.as_slice() {
+            [] => {}
+            ["snapshots"] => match snapshots::list(state.store.as_ref()) {
+                Ok(names) => {
+                    for name in names {
+                        println!("{}", name);
+                    }
+                }
+                Err(e) => println!("Couldn't list snapshots: {}", e),
+            },
+            ["restore", name] | ["restore", name, _] => {
+                match restore(state, name, args.get(2).copied()) {
+                    Ok((restored, saved)) => {
+                        let mut subscriptions = state.subscriptions.lock().unwrap();
+                        for account in restored.removed_accounts.iter() {
+                            subscriptions.remove_account(account);
+                        }
+                        for (account, change) in restored.changes.iter() {
+                            subscriptions.publish(account, None, change);
+                        }
+                        println!(
+                            "Restored {} ({} changes, {} accounts removed)",
+                            name,
+                            restored.changes.len(),
+                            restored.removed_accounts.len()
+                        );
+                        if let Err(e) = saved {
+                            println!("Couldn't save the restored data, it's lost on restart: {}", e);
+                        }
+                    }
+                    Err(e) => println!("{}", e),
+                }
+            }
+            ["promote"] => match replication::promote(state) {
+                Ok(message) => println!("{}", message),
+                Err(e) => println!("{}", e),
+            },
+            ["rotate-key"] => match rotate_key(state) {
+                Ok(id) => println!("Everything is now encrypted with key {}", id),
+                Err(e) => println!("Couldn't rotate the key: {}", e),
+            },
+            args => match admin::parse(args) {
+                Some(command) => admin::print(&admin::run(state, &command)),
+                None => println!(
+                    "Commands: save | snapshot | snapshots | restore <snapshot> [account] | rotate-key | promote\n          accounts | stats | logout <account> | reset-password <account> <password>\n          unlock <account> | role <account> admin|user"
+                ),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub limits: Limits,
+    pub keep_snapshots: usize,
+    pub log_requests: bool, // print every request to standard output
+    pub standby_of: Option<Upstream>,
+    pub socket_mode: u32, // permissions of Unix sockets the server listens on
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            limits: Limits::default(),
+            keep_snapshots: snapshots::DEFAULT_RETENTION,
+            log_requests: true,
+            standby_of: None,
+            socket_mode: 0o600,
+        }
+    }
+}
+
+// Everything the client handlers and the console share.
+struct State {
+    data: RwLock<Data>,
+    contacts: RwLock<Contacts>,
+    subscriptions: Mutex<Subscriptions>,
+    sessions: Mutex<Sessions>,
+    store: Box<dyn Store>,
+    config: Config,
+    started_at: Instant,
+    requests: AtomicU64,
+    standby: AtomicBool,
+    upstream: Mutex<Option<Stream>>, // the connection a standby replicates from
+    replicas: Mutex<Replicas>,
+}
+
+impl State {
+    // Reads the accounts and contacts from the store, upgrading files written in an older format.
+    fn load(store: Box<dyn Store>, config: Config) -> Result<Self, String> {
+        let mut data = Data::new();
+        let mut contacts = Contacts::new();
+        for (file, recovered) in [
+            (DATA_FILE, data.recover(store.as_ref())),
+            (CONTACTS_LIST_FILE, contacts.recover(store.as_ref())),
+        ] {
+            let version = recovered?;
+            if version < migrations::CURRENT_VERSION {
+                println!(
+                    "Migrated {} from version {} to {}, it's written in the new format on the next save",
+                    file,
+                    version,
+                    migrations::CURRENT_VERSION
+                );
+            }
+        }
+        Ok(State {
+            data: RwLock::new(data),
+            contacts: RwLock::new(contacts),
+            subscriptions: Mutex::new(Subscriptions::new()),
+            sessions: Mutex::new(Sessions::new()),
+            store,
+            standby: AtomicBool::new(config.standby_of.is_some()),
+            config,
+            started_at: Instant::now(),
+            requests: AtomicU64::new(0),
+            upstream: Mutex::new(None),
+            replicas: Mutex::new(Replicas::new()),
+        })
+    }
+
+    // Changes accounts and queues them for the standbys while still holding the lock, so they get
+    // every version in order.
+    fn edit_accounts<T>(&self, edit: impl FnOnce(&mut Data) -> T) -> T {
+        let mut data = self.data.write().unwrap();
+        let result = edit(&mut data);
+        self.replicas.lock().unwrap().send_accounts(&data);
+        result
+    }
+
+    // The same for the contacts of `account`.
+    fn edit_contacts(
+        &self,
+        account: &str,
+        edit: impl FnOnce(&mut Contacts) -> Vec<Change>,
+    ) -> Vec<Change> {
+        let mut contacts = self.contacts.write().unwrap();
+        let changes = edit(&mut contacts);
+        let mut replicas = self.replicas.lock().unwrap();
+        for change in changes.iter() {
+            replicas.send(&Replication::Changed {
+                account: account.to_owned(),
+                change: change.clone(),
+            });
+        }
+        changes
+    }
+
+    fn save(&self) {
+        self.data.read().unwrap().save(self.store.as_ref());
+        self.contacts.read().unwrap().save(self.store.as_ref());
+    }
+}
+
+// The contacts server. Each client is served on its own thread.
+pub struct Server {
+    listeners: Vec<Listener>,
+    state: Arc<State>,
+}
+
+impl Server {
+    // Loads the accounts and contacts from `store` and listens on `addr`. Port 0 picks a free
+    // port, see `local_addr`.
+    pub fn bind<A: ToSocketAddrs, S: Store + 'static>(addr: A, store: S) -> io::Result<Self> {
+        Server::bind_with(addr, store, Config::default())
+    }
+
+    pub fn bind_with<A: ToSocketAddrs, S: Store + 'static>(
+        addr: A,
+        store: S,
+        config: Config,
+    ) -> io::Result<Self> {
+        let listener = Listener::Tcp(TcpListener::bind(addr)?);
+        Server::start(vec![listener], store, config)
+    }
+
+    // Listens on every one of `endpoints` at once.
+    pub fn listen<S: Store + 'static>(
+        endpoints: &[Endpoint],
+        store: S,
+        config: Config,
+    ) -> io::Result<Self> {
+        let listeners = endpoints
+            .iter()
+            .map(|endpoint| {
+                Listener::bind(endpoint, config.socket_mode).map_err(|e| {
+                    io::Error::new(e.kind(), format!("couldn't listen on {}: {}", endpoint, e))
+                })
+            })
+            .collect::<io::Result<_>>()?;
+        Server::start(listeners, store, config)
+    }
+
+    fn start<S: Store + 'static>(
+        listeners: Vec<Listener>,
+        store: S,
+        config: Config,
+    ) -> io::Result<Self> {
+        let state = State::load(Box::new(store), config)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let server = Server {
+            listeners,
+            state: Arc::new(state),
+        };
+        if let Some(upstream) = server.state.config.standby_of.clone() {
+            let state = Arc::clone(&server.state);
+            thread::spawn(move || replication::follow(state, upstream));
+        }
+        Ok(server)
+    }
+
+    // The address of the first TCP endpoint.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        for listener in self.listeners.iter() {
+            if let Listener::Tcp(listener) = listener {
+                return listener.local_addr();
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "the server isn't listening on TCP",
+        ))
+    }
+
+    pub fn endpoints(&self) -> io::Result<Vec<Endpoint>> {
+        self.listeners.iter().map(Listener::endpoint).collect()
+    }
+
+    // Reads operator commands from standard input on a background thread.
+    pub fn start_console(&self) {
+        let state = Arc::clone(&self.state);
+        thread::spawn(move || console(&state));
+    }
+
+    // Serves clients from every endpoint, each accepting on its own thread, for as long as the
+    // listeners work.
+    pub fn run(self) {
+        let Server { listeners, state } = self;
+        let client_count = Arc::new(AtomicUsize::new(0));
+        let accepting: Vec<_> = listeners
+            .into_iter()
+            .map(|listener| {
+                let state = Arc::clone(&state);
+                let client_count = Arc::clone(&client_count);
+                thread::spawn(move || accept(listener, state, client_count))
+            })
+            .collect();
+        for thread in accepting {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn accept(listener: Listener, state: Arc<State>, client_count: Arc<AtomicUsize>) {
+    loop {
+        let stream = match listener.accept() {
+            Ok(_stream) => _stream,
+            Err(e) => {
+                println!("Couldn't accept a connection: {}", e);
+                continue;
+            }
+        };
+        let state = Arc::clone(&state);
+        let client = client_count.fetch_add(1, Ordering::Relaxed) + 1;
+        thread::Builder::new()
+            .name(format!("client {}", client))
+            .spawn(move || handle_client(stream, state))
+            .unwrap();
+    }
+}
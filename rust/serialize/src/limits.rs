@@ -0,0 +1,73 @@
+use std::io::{self, Read};
+
+// What a single account or request may use, so one client can't grow the server without bound.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub max_contacts: usize,     // contacts per account
+    pub max_field_length: usize, // bytes in a name, email or password
+    pub max_message_size: usize, // bytes in a single request
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_contacts: 10_000,
+            max_field_length: 256,
+            max_message_size: 64 * 1024,
+        }
+    }
+}
+
+impl Limits {
+    pub fn fits<'a>(&self, fields: impl IntoIterator<Item = Option<&'a str>>) -> bool {
+        fields
+            .into_iter()
+            .flatten()
+            .all(|field| field.len() <= self.max_field_length)
+    }
+}
+
+// Fails the read once the current message has taken more than `limit` bytes, so an oversized
+// request is rejected while it is being deserialized instead of after it's been buffered whole.
+pub struct LimitedReader<R> {
+    inner: R,
+    limit: usize,
+    remaining: usize,
+    exceeded: bool,
+}
+
+impl<R: Read> LimitedReader<R> {
+    pub fn new(inner: R, limit: usize) -> Self {
+        LimitedReader {
+            inner,
+            limit,
+            remaining: limit,
+            exceeded: false,
+        }
+    }
+
+    pub fn start_message(&mut self) {
+        self.remaining = self.limit;
+        self.exceeded = false;
+    }
+
+    pub fn exceeded(&self) -> bool {
+        self.exceeded
+    }
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            self.exceeded = true;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "message is too large",
+            ));
+        }
+        let max = buf.len().min(self.remaining);
+        let read = self.inner.read(&mut buf[..max])?;
+        self.remaining -= read;
+        Ok(read)
+    }
+}
@@ -1,412 +1,154 @@
-use serde::{Deserialize, Serialize};
-use serde_json;
-
-use std::collections::HashMap;
-
-use std::fs::OpenOptions;
-use std::io::prelude::*;
-use std::net::{TcpListener, TcpStream};
-use std::sync::{Arc, RwLock};
-use std::thread;
-
-const DATA_FILE: &'static str = "data/Data.json";
-const CONTACTS_LIST_FILE: &'static str = "data/Contacts_list.json";
-
-#[derive(Debug, Serialize, Deserialize)]
-enum Query {
-    Save,
-    Add,
-    Remove,
-    SearchByName,
-    SearchByPhone,
-    ShowList,
-    Done,
-    Login,
-    CreateAccount,
-}
-
-#[derive(Serialize, Deserialize)]
-struct Ans {
-    query: Query,
-    name: Option<String>,
-    phone: Option<u64>,
-}
-
-#[derive(Serialize, Deserialize)]
-struct Feedback {
-    result: Result<Ans, Query>,
-}
-
-impl Feedback {
-    fn new_ok(query: Query, name: Option<String>, phone: Option<u64>) -> Self {
-        Feedback {
-            result: Ok(Ans { query, name, phone }),
-        }
-    }
-
-    fn new_err(query: Query) -> Self {
-        Feedback { result: Err(query) }
-    }
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Contact {
-    name: String,
-    phone: u64,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Contacts {
-    contacts_list: HashMap<String, HashMap<u64, Contact>>, // <account name, contacts list>
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Account {
-    name: String,
-    password: String,
+use serialize::{crypto, offline, Config, Endpoint, FileStore, Server, Upstream};
+
+const ADDRESS: &str = "172.31.26.0:54321";
+const DATA_DIR: &str = "data";
+// The admin account a standby logs in to the primary with
+const REPLICATION_ACCOUNT: &str = "SERIALIZE_REPLICATION_ACCOUNT";
+const REPLICATION_PASSWORD: &str = "SERIALIZE_REPLICATION_PASSWORD";
+
+const USAGE: &str = "usage: serialize [--listen ADDRESS]... [--socket-mode MODE] [--standby-of ADDRESS] [--keep-snapshots N] [--max-contacts N] [--max-field-length BYTES] [--max-message-size BYTES]
+       serialize restore <snapshot> [account]
+       serialize encrypt
+       serialize rotate-key
+       serialize decrypt <file>
+       serialize check
+addresses are tcp://HOST:PORT, tcp://[IPV6]:PORT, unix://PATH or a plain HOST:PORT";
+
+fn standby_of(primary: Endpoint) -> Result<Upstream, String> {
+    let var =
+        |name| std::env::var(name).map_err(|_| format!("--standby-of needs the {} variable", name));
+    Ok(Upstream {
+        primary,
+        account: var(REPLICATION_ACCOUNT)?,
+        password: var(REPLICATION_PASSWORD)?,
+    })
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Data {
-    clients: Vec<Account>,
-}
-
-impl Data {
-    fn new() -> Self {
-        Data {
-            clients: Vec::new(),
-        }
-    }
-
-    fn check_login(&self, creds: &Account) -> Option<Account> {
-        self.clients.iter().find_map(|acc| {
-            if acc.name == creds.name && acc.password == creds.password {
-                Some(Account::new(&acc.name, &acc.password))
-            } else {
-                None
+fn parse_options(
+    options: &[&str],
+    config: &mut Config,
+    listen: &mut Vec<Endpoint>,
+) -> Result<(), String> {
+    for option in options.chunks(2) {
+        let (name, value) = match option {
+            [name, value] => (*name, *value),
+            [name] => return Err(format!("{} needs a value", name)),
+            _ => unreachable!(),
+        };
+        match name {
+            "--listen" => {
+                listen.push(value.parse()?);
+                continue;
             }
-        })
-    }
-
-    // fn search_client(&self, creds: &Account) -> Option<&Account> {
-    //     self.clients.iter().find_map(|acc| {
-    //         if acc.name == creds.name {
-    //             Some(acc)
-    //         } else {
-    //             None
-    //         }
-    //     })
-    // }
-
-    fn add_client(&mut self, name: &str, password: &str) -> bool {
-        if let Some(_) =
-            self.clients
-                .iter()
-                .find_map(|acc| if acc.name == name { Some(acc) } else { None })
-        {
-            return false;
+            "--standby-of" => {
+                config.standby_of = Some(standby_of(value.parse()?)?);
+                continue;
+            }
+            "--socket-mode" => {
+                config.socket_mode = u32::from_str_radix(value, 8)
+                    .map_err(|_| format!("{} takes an octal mode like 660, got {}", name, value))?;
+                continue;
+            }
+            _ => {}
         }
-
-        let (name, password) = (name.to_owned(), password.to_owned());
-        self.clients.push(Account { name, password });
-
-        true
-    }
-
-    fn save(&self) {
-        let serialized = serde_json::to_string(&self).unwrap();
-        let mut file = match OpenOptions::new()
-            .create(true)
-            .write(true)
-            .read(true)
-            .truncate(true)
-            .open(DATA_FILE)
-        {
-            Ok(_file) => _file,
-            Err(_error) => panic!("Error trying to open contacts list file!"),
-        };
-        file.write_all(serialized.as_bytes()).unwrap();
-    }
-
-    fn recover(&mut self) {
-        let file = match OpenOptions::new()
-            .create(true)
-            .write(true)
-            .read(true)
-            .open(DATA_FILE)
-        {
-            Ok(_file) => _file,
-            Err(_error) => panic!("Error trying to open contacts list file!"),
-        };
-        if file.metadata().unwrap().len() > 0 {
-            *self = serde_json::from_reader(file).unwrap()
+        let value: usize = value
+            .parse()
+            .map_err(|_| format!("{} takes a number, got {}", name, value))?;
+        match name {
+            "--keep-snapshots" => config.keep_snapshots = value,
+            "--max-contacts" => config.limits.max_contacts = value,
+            "--max-field-length" => config.limits.max_field_length = value,
+            "--max-message-size" => config.limits.max_message_size = value,
+            _ => return Err(format!("unknown option {}", name)),
         }
     }
+    Ok(())
 }
 
-impl Contact {
-    fn new(name: &str, phone: u64) -> Self {
-        let name = name.to_owned();
-        Contact { name, phone }
-    }
-}
-
-impl Account {
-    fn new(name: &str, password: &str) -> Self {
-        let name = name.to_owned();
-        let password = password.to_owned();
-        Account { name, password }
-    }
-}
-
-impl Contacts {
-    fn new() -> Self {
-        Contacts {
-            contacts_list: HashMap::default(),
-        }
-    }
-
-    fn save(&self) {
-        let serialized = serde_json::to_string(&self).unwrap();
-        let mut file = match OpenOptions::new()
-            .create(true)
-            .write(true)
-            .read(true)
-            .truncate(true)
-            .open(CONTACTS_LIST_FILE)
-        {
-            Ok(_file) => _file,
-            Err(_error) => panic!("Error trying to open contacts list file!"),
-        };
-        file.write_all(serialized.as_bytes()).unwrap();
-    }
-
-    fn recover(&mut self) {
-        let file = match OpenOptions::new()
-            .create(true)
-            .write(true)
-            .read(true)
-            .open(CONTACTS_LIST_FILE)
-        {
-            Ok(_file) => _file,
-            Err(_error) => panic!("Error trying to open contacts list file!"),
-        };
-        if file.metadata().unwrap().len() > 0 {
-            *self = serde_json::from_reader(file).unwrap()
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let mut config = Config::default();
+    let mut listen = Vec::new();
+    // Only the server and the commands that encrypt what wasn't may create a key, the others
+    // need the one the data was encrypted with
+    let generate_key = match args.as_slice() {
+        ["decrypt", _] | ["check"] | ["restore", _] | ["restore", _, _] => false,
+        ["encrypt"] | ["rotate-key"] => true,
+        options => {
+            if let Err(e) = parse_options(options, &mut config, &mut listen) {
+                eprintln!("{}\n{}", e, USAGE);
+                return;
+            }
+            true
         }
-    }
-
-    fn add_contact(&mut self, stream: &mut TcpStream, account: &str, name: &str, phone: u64) {
-        if self
-            .contacts_list
-            .get(account).unwrap()
-            .contains_key(&phone)
-        {
-            serde_json::to_writer(stream, &Feedback::new_err(Query::Add)).unwrap();
-        } else {
-            let new_contact = Contact::new(name, phone);
-            self.contacts_list
-                .get_mut(account)
-                .unwrap()
-                .insert(phone, new_contact);
-            serde_json::to_writer(stream, &Feedback::new_ok(Query::Add, None, None)).unwrap();
+    };
+    let keys = if generate_key {
+        crypto::init()
+    } else {
+        crypto::init_existing()
+    };
+    if let Err(e) = keys {
+        eprintln!("Couldn't load the server keys: {}", e);
+        std::process::exit(1);
+    }
+    let store = FileStore::new(DATA_DIR);
+
+    match args.as_slice() {
+        // Offline inspection of a data file or snapshot, printed as plain JSON.
+        ["decrypt", path] => {
+            match crypto::read(path) {
+                Ok(contents) => println!("{}", String::from_utf8_lossy(&contents)),
+                Err(e) => eprintln!("Couldn't decrypt {}: {}", path, e),
+            }
+            return;
         }
-    }
-
-    fn remove(&mut self, stream: &mut TcpStream, account: &str, phone: u64) {
-        match self.contacts_list.get_mut(account).unwrap().remove(&phone) {
-            Some(_contact) => serde_json::to_writer(
-                stream,
-                &Feedback::new_ok(Query::Remove, Some(_contact.name), Some(_contact.phone)),
-            )
-            .unwrap(),
-            None => serde_json::to_writer(stream, &Feedback::new_err(Query::Remove)).unwrap(),
+        ["check"] => {
+            if !offline::check(&store) {
+                std::process::exit(1);
+            }
+            return;
         }
-    }
-
-    fn search_by_name(&self, mut stream: &mut TcpStream, account: &str, name: &str) {
-        let search: Vec<u64> = self
-            .contacts_list
-            .get(account)
-            .unwrap()
-            .iter()
-            .filter_map(|(&phone, contact)| {
-                if contact.name == name {
-                    Some(phone)
-                } else {
-                    None
-                }
-            })
-            .collect();
-        if search.is_empty() {
-            serde_json::to_writer(
-                stream,
-                &Feedback {
-                    result: Err(Query::SearchByName),
-                },
-            )
-            .unwrap();
+        // Offline restore of the data files, with the server stopped.
+        ["restore", name] | ["restore", name, _] => {
+            match offline::restore(store, name, args.get(2).copied()) {
+                Ok(changes) => println!("Restored {} ({} changes)", name, changes),
+                Err(e) => eprintln!("{}", e),
+            }
             return;
         }
-        for phone in search.iter() {
-            serde_json::to_writer(
-                &mut stream,
-                &Feedback::new_ok(Query::SearchByName, None, Some(phone.to_owned())),
-            )
-            .unwrap();
+        // One-time encryption of data files written before encryption was added.
+        ["encrypt"] => {
+            match offline::encrypt(&store) {
+                Ok(names) if names.is_empty() => println!("Nothing to encrypt"),
+                Ok(names) => println!("Encrypted {}", names.join(", ")),
+                Err(e) => eprintln!("Couldn't encrypt the data files: {}", e),
+            }
+            return;
         }
-        serde_json::to_writer(stream, &Feedback::new_ok(Query::Done, None, None)).unwrap()
-    }
-
-    fn search_by_number(&self, stream: &mut TcpStream, account: &str, phone: u64) {
-        let opt = self.contacts_list.get(account).unwrap().get(&phone);
-        if let Some(contact) = opt {
-            serde_json::to_writer(
-                stream,
-                &Feedback::new_ok(Query::SearchByPhone, Some(contact.name.clone()), None),
-            )
-            .unwrap()
-        } else {
-            serde_json::to_writer(stream, &Feedback::new_err(Query::SearchByPhone)).unwrap()
+        ["rotate-key"] => {
+            match offline::rotate_key(store) {
+                Ok(id) => println!("Everything is now encrypted with key {}", id),
+                Err(e) => eprintln!("Couldn't rotate the key: {}", e),
+            }
+            return;
         }
+        _ => {}
     }
 
-    fn show_list(&self, mut stream: &mut TcpStream, account: &str) {
-        println!("<name>: <phone number>");
-        for contact in self.contacts_list.get(account).unwrap().iter() {
-            println!("{:?}", contact);
-            serde_json::to_writer(
-                &mut stream,
-                &Feedback::new_ok(
-                    Query::ShowList,
-                    Some(contact.1.name.clone()),
-                    Some(*contact.0),
-                ),
-            )
-            .unwrap();
-        }
+    if listen.is_empty() {
+        listen.push(Endpoint::Tcp(ADDRESS.to_owned()));
     }
-}
-
-fn handle_client(mut stream: TcpStream, data: Arc<RwLock<Data>>, contacts: Arc<RwLock<Contacts>>) {
-    let reader = stream.try_clone().unwrap();
-    let mut reader = std::io::BufReader::new(reader);
-
-    let account_name = loop {
-        let mut de = serde_json::Deserializer::from_reader(&mut reader);
-        let ans: Ans = match Feedback::deserialize(&mut de) {
-            Ok(_feedback) => _feedback.result.ok().unwrap(),
-            Err(_) => unreachable!(),
-        };
-
-        let mut de = serde_json::Deserializer::from_reader(&mut reader);
-        let creds: Account = match Account::deserialize(&mut de) {
-            Ok(_creds) => _creds,
-            Err(_) => unreachable!(),
-        };
-
-        match ans.query {
-            Query::Login => {
-                let account = data.read().unwrap().check_login(&creds);
-                if let Some(acc) = account {
-                    serde_json::to_writer(&mut stream, &Feedback::new_ok(Query::Login, None, None))
-                        .unwrap();
-
-                    break acc.name;
-                } else {
-                    serde_json::to_writer(&mut stream, &Feedback::new_err(Query::Login)).unwrap();
-                }
-            }
-            Query::CreateAccount => {
-                if data
-                    .write()
-                    .unwrap()
-                    .add_client(&creds.name, &creds.password)
-                {
-                    serde_json::to_writer(
-                        &mut stream,
-                        &Feedback::new_ok(Query::CreateAccount, None, None),
-                    )
-                    .unwrap();
-
-                    break creds.name.to_string();
-                } else {
-                    serde_json::to_writer(&mut stream, &Feedback::new_err(Query::CreateAccount))
-                        .unwrap();
-                }
-            }
-            _ => unreachable!(),
+    let server = match Server::listen(&listen, store, config) {
+        Ok(_server) => _server,
+        Err(e) => {
+            eprintln!("Couldn't start the server: {}", e);
+            std::process::exit(1);
         }
     };
-    contacts.write().unwrap().contacts_list.entry(account_name.clone()).or_insert(HashMap::default());
-    loop {
-        let mut de = serde_json::Deserializer::from_reader(&mut reader);
-        let ans: Ans = match Feedback::deserialize(&mut de) {
-            Ok(_feedback) => _feedback.result.ok().unwrap(),
-            Err(_) => break,
-        };
-        println!("{:?}", ans.query);
-        match ans.query {
-            Query::Save => {
-                data.read().unwrap().save();
-                contacts.read().unwrap().save();
-
-                serde_json::to_writer(&mut stream, &Feedback::new_ok(Query::Done, None, None))
-                    .unwrap()
-            }
-            Query::Add => contacts.write().unwrap().add_contact(
-                &mut stream,
-                &account_name,
-                &ans.name.unwrap(),
-                ans.phone.unwrap(),
-            ),
-            Query::Remove => {
-                contacts
-                    .write()
-                    .unwrap()
-                    .remove(&mut stream, &account_name, ans.phone.unwrap())
-            }
-            Query::SearchByName => {
-                contacts
-                    .read()
-                    .unwrap()
-                    .search_by_name(&mut stream, &account_name, &ans.name.unwrap())
-            }
-            Query::SearchByPhone => {
-                contacts
-                    .read()
-                    .unwrap()
-                    .search_by_number(&mut stream, &account_name, ans.phone.unwrap())
-            }
-            Query::ShowList => {
-                contacts.read().unwrap().show_list(&mut stream, &account_name);
-
-                serde_json::to_writer(&mut stream, &Feedback::new_ok(Query::Done, None, None))
-                    .unwrap()
-            }
-            _ => {}
-        }
-    }
-}
-
-fn main() {
-    let data = Arc::new(RwLock::new(Data::new()));
-    let contacts = Arc::new(RwLock::new(Contacts::new()));
-    data.write().unwrap().recover();
-    contacts.write().unwrap().recover();
-
-    let listener = TcpListener::bind("172.31.26.0:54321").unwrap();
-    let mut client_count = 1;
-
-    for stream in listener.incoming() {
-        let data_clone = Arc::clone(&data);
-        let contacts_clone = Arc::clone(&contacts);
-        thread::Builder::new()
-            .name(format!("client {}", client_count))
-            .spawn(|| {
-                handle_client(stream.unwrap(), data_clone, contacts_clone);
-            })
-            .unwrap();
-        client_count += 1;
+    for endpoint in server.endpoints().unwrap_or_default() {
+        println!("Listening on {}", endpoint);
     }
+    server.start_console();
+    server.run();
 }
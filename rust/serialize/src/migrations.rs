@@ -0,0 +1,109 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+#[derive(Clone, Copy)]
+pub enum Kind {
+    Data,
+    Contacts,
+}
+
+// MIGRATIONS[n] upgrades a file from version n to n + 1. Files written before the format was
+// versioned have no version field and are version 0.
+type Migration = fn(Kind, &mut Map<String, Value>);
+const MIGRATIONS: &[Migration] = &[add_revisions_and_emails, add_roles_and_lockout, add_dates];
+pub const CURRENT_VERSION: u64 = MIGRATIONS.len() as u64;
+
+// Version 1 keeps a revision log per account and an optional email per contact.
+fn add_revisions_and_emails(kind: Kind, file: &mut Map<String, Value>) {
+    if let Kind::Contacts = kind {
+        if let Some(lists) = file.get_mut("contacts_list").and_then(Value::as_object_mut) {
+            for list in lists.values_mut().filter_map(Value::as_object_mut) {
+                for contact in list.values_mut().filter_map(Value::as_object_mut) {
+                    contact.entry("revision").or_insert(json!(0));
+                    contact.entry("email").or_insert(Value::Null);
+                }
+            }
+        }
+        file.entry("revisions").or_insert(json!({}));
+        file.entry("removed").or_insert(json!({}));
+    }
+}
+
+// Version 2 gives every account a role and counts failed logins to lock it out.
+fn add_roles_and_lockout(kind: Kind, file: &mut Map<String, Value>) {
+    if let Kind::Data = kind {
+        if let Some(clients) = file.get_mut("clients").and_then(Value::as_array_mut) {
+            for account in clients.iter_mut().filter_map(Value::as_object_mut) {
+                account.entry("role").or_insert(json!("User"));
+                account.entry("failed_logins").or_insert(json!(0));
+                account.entry("locked").or_insert(json!(false));
+            }
+        }
+    }
+}
+
+// Version 3 keeps a birthday and an anniversary per contact.
+fn add_dates(kind: Kind, file: &mut Map<String, Value>) {
+    if let Kind::Contacts = kind {
+        if let Some(lists) = file.get_mut("contacts_list").and_then(Value::as_object_mut) {
+            for list in lists.values_mut().filter_map(Value::as_object_mut) {
+                for contact in list.values_mut().filter_map(Value::as_object_mut) {
+                    contact.entry("birthday").or_insert(Value::Null);
+                    contact.entry("anniversary").or_insert(Value::Null);
+                }
+            }
+        }
+    }
+}
+
+pub fn version(file: &Value) -> Result<u64, String> {
+    let version = match file.get("version") {
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| format!("version is not a number: {}", version))?,
+        None => 0,
+    };
+    if version > CURRENT_VERSION {
+        return Err(format!(
+            "written by a newer server (format version {}), this one only reads up to version {}",
+            version, CURRENT_VERSION
+        ));
+    }
+    Ok(version)
+}
+
+pub fn upgrade(kind: Kind, file: &mut Value, from: u64) -> Result<(), String> {
+    let file = file
+        .as_object_mut()
+        .ok_or_else(|| "not a JSON object".to_owned())?;
+    for migration in &MIGRATIONS[from as usize..] {
+        migration(kind, file);
+    }
+    Ok(())
+}
+
+// Parses a file in any known version of the format, returning it along with the version it was
+// written in.
+pub fn load<T: DeserializeOwned>(kind: Kind, contents: &[u8]) -> Result<(T, u64), String> {
+    let mut file: Value = serde_json::from_slice(contents).map_err(|e| e.to_string())?;
+    let from = version(&file)?;
+    upgrade(kind, &mut file, from)?;
+    let loaded = serde_json::from_value(file).map_err(|e| e.to_string())?;
+    Ok((loaded, from))
+}
+
+#[derive(Serialize)]
+struct Versioned<'a, T> {
+    version: u64,
+    #[serde(flatten)]
+    file: &'a T,
+}
+
+pub fn to_string<T: Serialize>(file: &T) -> String {
+    serde_json::to_string(&Versioned {
+        version: CURRENT_VERSION,
+        file,
+    })
+    .unwrap()
+}
@@ -0,0 +1,190 @@
+// The endpoints the server listens on and the connections it accepts: TCP over IPv4 or IPv6, and
+// Unix domain sockets for local tools, which are only as open as the socket file's permissions.
+use std::fmt;
+use std::fs;
+use std::io::{self, prelude::*};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::time::Duration;
+
+// Where to listen or connect, written as `tcp://HOST:PORT` or `unix://PATH`. A plain `HOST:PORT`
+// is TCP. IPv6 hosts go in brackets, `tcp://[::]:54321` listens on both IPv6 and IPv4 where the
+// system allows dual-stack sockets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(address: &str) -> Result<Self, String> {
+        let endpoint = match address.split_once("://") {
+            Some(("tcp", addr)) => Endpoint::Tcp(addr.to_owned()),
+            Some(("unix", path)) => Endpoint::Unix(PathBuf::from(path)),
+            Some((scheme, _)) => return Err(format!("unknown address scheme {}://", scheme)),
+            None => Endpoint::Tcp(address.to_owned()),
+        };
+        match &endpoint {
+            Endpoint::Tcp(addr) if addr.is_empty() => Err("tcp:// needs HOST:PORT".to_owned()),
+            Endpoint::Unix(path) if path.as_os_str().is_empty() => {
+                Err("unix:// needs the path of the socket".to_owned())
+            }
+            _ => Ok(endpoint),
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Endpoint::Tcp(addr) => write!(f, "tcp://{}", addr),
+            Endpoint::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+// A client connection over either kind of socket.
+#[derive(Debug)]
+pub enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Stream {
+    pub fn connect(endpoint: &Endpoint) -> io::Result<Self> {
+        Ok(match endpoint {
+            Endpoint::Tcp(addr) => Stream::Tcp(TcpStream::connect(addr.as_str())?),
+            Endpoint::Unix(path) => Stream::Unix(UnixStream::connect(path)?),
+        })
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            Stream::Tcp(stream) => Stream::Tcp(stream.try_clone()?),
+            Stream::Unix(stream) => Stream::Unix(stream.try_clone()?),
+        })
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.shutdown(how),
+            Stream::Unix(stream) => stream.shutdown(how),
+        }
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_write_timeout(timeout),
+            Stream::Unix(stream) => stream.set_write_timeout(timeout),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    // Unix sockets get `mode` as their permissions. A socket file left behind by a server that
+    // didn't shut down cleanly is replaced, one that still answers is in use.
+    pub fn bind(endpoint: &Endpoint, mode: u32) -> io::Result<Self> {
+        match endpoint {
+            Endpoint::Tcp(addr) => Ok(Listener::Tcp(TcpListener::bind(addr.as_str())?)),
+            Endpoint::Unix(path) => {
+                if let Ok(metadata) = fs::symlink_metadata(path) {
+                    if !metadata.file_type().is_socket() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AlreadyExists,
+                            format!("{} exists and is not a socket", path.display()),
+                        ));
+                    }
+                    if UnixStream::connect(path).is_ok() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AddrInUse,
+                            format!("another server is listening on {}", path.display()),
+                        ));
+                    }
+                    fs::remove_file(path)?;
+                }
+                let listener = bind_private(path, mode)?;
+                Ok(Listener::Unix(listener, path.clone()))
+            }
+        }
+    }
+
+    pub fn accept(&self) -> io::Result<Stream> {
+        Ok(match self {
+            Listener::Tcp(listener) => Stream::Tcp(listener.accept()?.0),
+            Listener::Unix(listener, _) => Stream::Unix(listener.accept()?.0),
+        })
+    }
+
+    // The endpoint as bound, with the port the system picked for port 0.
+    pub fn endpoint(&self) -> io::Result<Endpoint> {
+        Ok(match self {
+            Listener::Tcp(listener) => Endpoint::Tcp(listener.local_addr()?.to_string()),
+            Listener::Unix(_, path) => Endpoint::Unix(path.clone()),
+        })
+    }
+}
+
+// Binds the socket in a directory only the server can enter, gives it `mode` there and then
+// moves it into place, so there's never a moment where it's at `path` with the permissions the
+// umask left it. The directory sits next to `path` for the rename to stay on one filesystem.
+fn bind_private(path: &Path, mode: u32) -> io::Result<UnixListener> {
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a file path", path.display()),
+        )
+    })?;
+    let dir = path.with_file_name(format!(
+        ".{}.{}.bind",
+        name.to_string_lossy(),
+        process::id()
+    ));
+    fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let private = dir.join(name);
+    let bound = UnixListener::bind(&private).and_then(|listener| {
+        fs::set_permissions(&private, fs::Permissions::from_mode(mode))?;
+        fs::rename(&private, path)?;
+        Ok(listener)
+    });
+    if bound.is_err() {
+        let _ = fs::remove_file(&private);
+    }
+    fs::remove_dir(&dir)?;
+    bound
+}
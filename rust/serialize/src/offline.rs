@@ -0,0 +1,76 @@
+// Maintenance of the data files while the server is stopped.
+use crate::migrations::{self, Kind};
+use crate::{load_file, snapshots, Config, Contacts, Data, FileStore, State, Store};
+use crate::{CONTACTS_LIST_FILE, DATA_FILE};
+
+// Restores a snapshot, or one account from it, and saves the result. Returns how many contacts
+// changed.
+pub fn restore<S: Store + 'static>(
+    store: S,
+    name: &str,
+    account: Option<&str>,
+) -> Result<usize, String> {
+    let state = State::load(Box::new(store), Config::default())?;
+    let (restored, saved) = crate::restore(&state, name, account)?;
+    saved.map_err(|e| format!("Couldn't save the restored data: {}", e))?;
+    Ok(restored.changes.len())
+}
+
+// Encrypts the data files and snapshots written before encryption was added. Returns the names
+// of the files encrypted, none if the store already is.
+pub fn encrypt(store: &FileStore) -> Result<Vec<String>, String> {
+    let mut names = vec![DATA_FILE.to_owned(), CONTACTS_LIST_FILE.to_owned()];
+    let snapshots = snapshots::list(store).map_err(|e| e.to_string())?;
+    names.extend(snapshots.iter().map(|name| snapshots::snapshot_file(name)));
+    store.encrypt_plaintext(&names).map_err(|e| e.to_string())
+}
+
+// Returns the id of the new key.
+pub fn rotate_key<S: Store + 'static>(store: S) -> Result<u32, String> {
+    let state = State::load(Box::new(store), Config::default())?;
+    crate::rotate_key(&state).map_err(|e| e.to_string())
+}
+
+// Dry run of the migrations: reads the data files and every snapshot and tells what loading them
+// would do, without writing anything. Returns false if any of them can't be loaded.
+pub fn check(store: &dyn Store) -> bool {
+    let mut ok = true;
+    let mut report = |file: &str, version: Result<Option<u64>, String>| match version {
+        Ok(Some(version)) if version == migrations::CURRENT_VERSION => {
+            println!("{}: version {}, up to date", file, version)
+        }
+        Ok(Some(version)) => println!(
+            "{}: version {}, would be migrated to version {}",
+            file,
+            version,
+            migrations::CURRENT_VERSION
+        ),
+        Ok(None) => println!("{}: empty", file),
+        Err(e) => {
+            println!("{}", e);
+            ok = false;
+        }
+    };
+    report(
+        DATA_FILE,
+        load_file::<Data>(store, DATA_FILE, Kind::Data)
+            .map(|file| file.map(|(_, version)| version)),
+    );
+    report(
+        CONTACTS_LIST_FILE,
+        load_file::<Contacts>(store, CONTACTS_LIST_FILE, Kind::Contacts)
+            .map(|file| file.map(|(_, version)| version)),
+    );
+    match snapshots::list(store) {
+        Ok(names) => {
+            for name in names {
+                let version = snapshots::load(store, &name)
+                    .map(|snapshot| Some(snapshot.version))
+                    .map_err(|e| format!("{}: {}", name, e));
+                report(&name, version);
+            }
+        }
+        Err(e) => report("snapshots", Err(e.to_string())),
+    }
+    ok
+}
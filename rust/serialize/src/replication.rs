@@ -0,0 +1,235 @@
+// Primary/standby replication. A standby logs in to the primary with an admin account and asks to
+// replicate. It gets a snapshot of everything first, then every change as it's made, and serves
+// read-only queries from its copy until it's promoted to primary.
+use crate::net::{Endpoint, Stream};
+use crate::{Account, Change, Contacts, Credentials, Data, Feedback, Query, State};
+
+use serde::{Deserialize, Serialize};
+
+use std::io::{self, prelude::*, BufReader};
+use std::net::Shutdown;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// A standby that stops reading is dropped after this long, or once this many messages are
+// queued for it, instead of the primary keeping every change for it.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_QUEUED: usize = 10_000;
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+// Where a standby replicates from, and the admin account it logs in with.
+#[derive(Debug, Clone)]
+pub struct Upstream {
+    pub primary: Endpoint,
+    pub account: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum Replication {
+    Snapshot { data: Data, contacts: Contacts },
+    Accounts(Vec<Account>),
+    Changed { account: String, change: Change },
+    AccountRenamed { from: String, to: String },
+    AccountDeleted(String),
+}
+
+// A standby connected to this server. Its messages are queued while the caller holds the locks
+// of whatever changed, so they stay in the order the changes were made, and written by a thread
+// of its own, so a slow standby never holds those locks up.
+struct Replica {
+    queue: SyncSender<Arc<[u8]>>,
+    stream: Stream, // to hang up on it when it falls behind
+}
+
+impl Replica {
+    // False once the standby is gone or too far behind, it's hung up on then.
+    fn queue(&self, message: Arc<[u8]>) -> bool {
+        match self.queue.try_send(message) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                let _ = self.stream.shutdown(Shutdown::Both);
+                false
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+// Standbys connected to this server.
+pub struct Replicas {
+    replicas: Vec<Replica>,
+}
+
+impl Replicas {
+    pub fn new() -> Self {
+        Replicas {
+            replicas: Vec::new(),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.replicas.len()
+    }
+
+    // Queues `message` for every standby, dropping the ones that can't keep up. They reconnect
+    // and start over from a snapshot.
+    pub fn send(&mut self, message: &Replication) {
+        if self.replicas.is_empty() {
+            return;
+        }
+        let serialized: Arc<[u8]> = serde_json::to_vec(message).unwrap().into();
+        self.replicas
+            .retain(|replica| replica.queue(Arc::clone(&serialized)));
+    }
+
+    pub fn send_accounts(&mut self, data: &Data) {
+        self.send(&Replication::Accounts(data.clients.clone()));
+    }
+
+    // Starts feeding `stream`, `first` before anything sent from now on.
+    fn add(&mut self, stream: Stream, first: Vec<u8>) -> io::Result<()> {
+        let (queue, queued) = mpsc::sync_channel(MAX_QUEUED);
+        queue.send(first.into()).unwrap();
+        let writer = stream.try_clone()?;
+        thread::spawn(move || feed(writer, queued));
+        self.replicas.push(Replica { queue, stream });
+        Ok(())
+    }
+}
+
+// Writes what's queued for a standby until it's dropped or stops reading.
+fn feed(mut stream: Stream, queued: Receiver<Arc<[u8]>>) {
+    for message in queued {
+        if stream.write_all(&message).is_err() {
+            let _ = stream.shutdown(Shutdown::Both);
+            return;
+        }
+    }
+}
+
+// Starts streaming to a standby that asked to replicate: the answer, then a snapshot. Both read
+// locks are held until the standby is registered, so no change falls in between.
+pub fn attach(state: &State, stream: &Stream) -> io::Result<()> {
+    let replica = stream.try_clone()?;
+    replica.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    let data = state.data.read().unwrap();
+    let contacts = state.contacts.read().unwrap();
+    let mut replicas = state.replicas.lock().unwrap();
+
+    let mut serialized = serde_json::to_vec(&Feedback::new_ok(Query::Replicate, None, None))?;
+    serialized.extend(serde_json::to_vec(&Replication::Snapshot {
+        data: data.clone(),
+        contacts: contacts.clone(),
+    })?);
+    replicas.add(replica, serialized)
+}
+
+// Keeps the standby in sync with the primary, reconnecting whenever the connection is lost, until
+// it's promoted.
+pub fn follow(state: Arc<State>, upstream: Upstream) {
+    while state.standby.load(Ordering::SeqCst) {
+        let replicated = replicate_from(&state, &upstream);
+        // Promotion hangs up on the primary
+        if !state.standby.load(Ordering::SeqCst) {
+            break;
+        }
+        match replicated {
+            Ok(()) => println!("Lost the primary at {}", upstream.primary),
+            Err(e) => println!("Couldn't replicate from {}: {}", upstream.primary, e),
+        }
+        thread::sleep(RETRY_INTERVAL);
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
+
+fn replicate_from(state: &State, upstream: &Upstream) -> io::Result<()> {
+    let mut stream = Stream::connect(&upstream.primary)?;
+    *state.upstream.lock().unwrap() = Some(stream.try_clone()?);
+    // Promoted while connecting
+    if !state.standby.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut request = serde_json::to_vec(&Feedback::new_ok(Query::Login, None, None))?;
+    request.extend(serde_json::to_vec(&Credentials {
+        name: upstream.account.clone(),
+        password: upstream.password.clone(),
+    })?);
+    request.extend(serde_json::to_vec(&Feedback::new_ok(
+        Query::Replicate,
+        None,
+        None,
+    ))?);
+    stream.write_all(&request)?;
+    for answer in ["the login", "replication"] {
+        let mut de = serde_json::Deserializer::from_reader(&mut reader);
+        match Feedback::deserialize(&mut de)?.result {
+            Ok(_) => {}
+            Err(query) => {
+                return Err(invalid(&format!(
+                    "the primary refused {} ({:?})",
+                    answer, query
+                )))
+            }
+        }
+    }
+    println!("Replicating from {}", upstream.primary);
+
+    loop {
+        let mut de = serde_json::Deserializer::from_reader(&mut reader);
+        let message = match Replication::deserialize(&mut de) {
+            Ok(message) => message,
+            Err(e) if e.is_eof() || e.is_io() => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        apply(state, message);
+    }
+}
+
+// Applies a change from the primary, and pushes contact changes to clients subscribed here.
+fn apply(state: &State, message: Replication) {
+    match message {
+        Replication::Snapshot { data, contacts } => {
+            *state.data.write().unwrap() = data;
+            *state.contacts.write().unwrap() = contacts;
+            state.save();
+        }
+        Replication::Accounts(clients) => state.data.write().unwrap().clients = clients,
+        Replication::Changed { account, change } => {
+            state.contacts.write().unwrap().apply(&account, &change);
+            state
+                .subscriptions
+                .lock()
+                .unwrap()
+                .publish(&account, None, &change);
+        }
+        Replication::AccountRenamed { from, to } => {
+            state.contacts.write().unwrap().rename_account(&from, &to);
+            state.subscriptions.lock().unwrap().rename(&from, &to, None);
+        }
+        Replication::AccountDeleted(account) => {
+            state.contacts.write().unwrap().remove_account(&account);
+            state.subscriptions.lock().unwrap().remove_account(&account);
+        }
+    }
+}
+
+// Stops following the primary and starts taking writes.
+pub fn promote(state: &State) -> Result<String, String> {
+    if !state.standby.swap(false, Ordering::SeqCst) {
+        return Err("This server is the primary already".to_owned());
+    }
+    if let Some(stream) = state.upstream.lock().unwrap().take() {
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+    state.save();
+    Ok("Promoted to primary, writes are accepted now".to_owned())
+}
@@ -0,0 +1,68 @@
+use crate::net::Stream;
+
+use std::collections::HashMap;
+use std::net::Shutdown;
+
+struct Session {
+    account: String,
+    stream: Stream,
+}
+
+// Every logged in session, so admins can see who is connected and end sessions.
+pub struct Sessions {
+    next_id: usize,
+    sessions: HashMap<usize, Session>,
+}
+
+impl Sessions {
+    pub fn new() -> Self {
+        Sessions {
+            next_id: 0,
+            sessions: HashMap::new(),
+        }
+    }
+
+    pub fn open(&mut self, account: &str, stream: &Stream) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        if let Ok(stream) = stream.try_clone() {
+            let account = account.to_owned();
+            self.sessions.insert(id, Session { account, stream });
+        }
+        id
+    }
+
+    pub fn close(&mut self, id: usize) {
+        self.sessions.remove(&id);
+    }
+
+    pub fn rename(&mut self, id: usize, account: &str) {
+        if let Some(session) = self.sessions.get_mut(&id) {
+            session.account = account.to_owned();
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn count_for(&self, account: &str) -> usize {
+        self.sessions
+            .values()
+            .filter(|session| session.account == account)
+            .count()
+    }
+
+    // Shuts the connections down, their handlers see the stream end and clean up as they would
+    // for a client that disconnected. Returns how many sessions were ended.
+    pub fn logout(&mut self, account: &str) -> usize {
+        let mut ended = 0;
+        for session in self.sessions.values() {
+            if session.account == account {
+                let _ = session.stream.shutdown(Shutdown::Both);
+                ended += 1;
+            }
+        }
+        ended
+    }
+}
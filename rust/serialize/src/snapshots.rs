@@ -0,0 +1,208 @@
+use crate::migrations::{self, Kind};
+use crate::store::Store;
+use crate::{Change, Contacts, Data};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use std::io;
+use std::path::Path;
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SNAPSHOT_DIR: &str = "snapshots";
+const SNAPSHOT_PREFIX: &str = "snapshot-";
+pub const DEFAULT_RETENTION: usize = 5;
+
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    version: u64,
+    taken_at: u128,
+    data: &'a Data,
+    contacts: &'a Contacts,
+}
+
+pub struct Snapshot {
+    data: Data,
+    contacts: Contacts,
+    pub version: u64, // format version it was taken with
+}
+
+// Snapshots are named without the directory and extension, anything that looks like a path is
+// taken as it is.
+pub fn snapshot_file(name: &str) -> String {
+    if Path::new(name).components().count() > 1 {
+        name.to_owned()
+    } else {
+        format!("{}/{}.json", SNAPSHOT_DIR, name.trim_end_matches(".json"))
+    }
+}
+
+// Serializes accounts and contacts while holding both read locks, so the snapshot is consistent,
+// and only writes it to the store after the locks are released. Connected clients keep being
+// served while the file is written. Only the newest `keep` snapshots are kept.
+pub fn take(
+    data: &RwLock<Data>,
+    contacts: &RwLock<Contacts>,
+    store: &dyn Store,
+    keep: usize,
+) -> io::Result<String> {
+    let taken_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    let serialized = {
+        let data = data.read().unwrap();
+        let contacts = contacts.read().unwrap();
+        serde_json::to_string(&SnapshotRef {
+            version: migrations::CURRENT_VERSION,
+            taken_at,
+            data: &data,
+            contacts: &contacts,
+        })?
+    };
+
+    let name = format!("{}{}", SNAPSHOT_PREFIX, taken_at);
+    store.write(&snapshot_file(&name), serialized.as_bytes())?;
+
+    prune(store, keep)?;
+    Ok(name)
+}
+
+// Snapshot names, oldest first.
+pub fn list(store: &dyn Store) -> io::Result<Vec<String>> {
+    let mut names: Vec<String> = store
+        .list(SNAPSHOT_DIR)?
+        .into_iter()
+        .filter(|name| name.starts_with(SNAPSHOT_PREFIX) && name.ends_with(".json"))
+        .map(|name| name.trim_end_matches(".json").to_owned())
+        .collect();
+    names.sort_by_key(|name| {
+        name.trim_start_matches(SNAPSHOT_PREFIX)
+            .parse::<u128>()
+            .unwrap_or(0)
+    });
+    Ok(names)
+}
+
+fn prune(store: &dyn Store, keep: usize) -> io::Result<()> {
+    let names = list(store)?;
+    if names.len() > keep {
+        for name in &names[..names.len() - keep] {
+            store.remove(&snapshot_file(name))?;
+        }
+    }
+    Ok(())
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Snapshots hold both data files and are upgraded the same way they are.
+pub fn load(store: &dyn Store, name: &str) -> io::Result<Snapshot> {
+    let contents = store
+        .read(&snapshot_file(name))?
+        .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+    let mut snapshot: Value = serde_json::from_slice(&contents)?;
+    let version = migrations::version(&snapshot).map_err(invalid)?;
+    let mut part = |field: &str, kind: Kind| -> io::Result<Value> {
+        let mut part = snapshot
+            .get_mut(field)
+            .map(Value::take)
+            .ok_or_else(|| invalid(format!("snapshot has no {}", field)))?;
+        migrations::upgrade(kind, &mut part, version).map_err(invalid)?;
+        Ok(part)
+    };
+    let data = part("data", Kind::Data)?;
+    let contacts = part("contacts", Kind::Contacts)?;
+    Ok(Snapshot {
+        data: serde_json::from_value(data)?,
+        contacts: serde_json::from_value(contacts)?,
+        version,
+    })
+}
+
+// Writes every snapshot again, so they are sealed with the current key after a key rotation.
+pub fn reseal(store: &dyn Store) -> io::Result<()> {
+    for name in list(store)? {
+        let file = snapshot_file(&name);
+        if let Some(contents) = store.read(&file)? {
+            store.write(&file, &contents)?;
+        }
+    }
+    Ok(())
+}
+
+// What a restore did, to be passed on to subscribed sessions and standbys.
+pub struct Restored {
+    pub changes: Vec<(String, Change)>, // <account name, change made to it>
+    pub removed_accounts: Vec<String>, // accounts the snapshot doesn't have, dropped with their contacts
+}
+
+// Restores the whole server, or only the contact list of `account`, to the state in `snapshot`.
+// Contact lists are restored through the revision log, so the changes made to each account are
+// returned, to be pushed to subscribed sessions.
+pub fn restore(
+    snapshot: &Snapshot,
+    account: Option<&str>,
+    data: &mut Data,
+    contacts: &mut Contacts,
+) -> Result<Restored, String> {
+    let mut removed_accounts = Vec::new();
+    let accounts: Vec<String> = match account {
+        Some(account) => {
+            if !data.clients.iter().any(|acc| acc.name == account) {
+                return Err(format!("Account \"{}\" doesn't exist anymore", account));
+            }
+            if !snapshot.contacts.contacts_list.contains_key(account) {
+                return Err(format!(
+                    "Account \"{}\" has no contacts list in this snapshot",
+                    account
+                ));
+            }
+            vec![account.to_owned()]
+        }
+        None => {
+            data.clients = snapshot.data.clients.clone();
+            // Accounts created after the snapshot go, their contacts with them
+            removed_accounts = contacts
+                .contacts_list
+                .keys()
+                .filter(|account| !data.has_client(account))
+                .cloned()
+                .collect();
+            removed_accounts.sort();
+            for account in removed_accounts.iter() {
+                contacts.remove_account(account);
+            }
+            let mut accounts: Vec<String> = contacts
+                .contacts_list
+                .keys()
+                .chain(snapshot.contacts.contacts_list.keys())
+                .filter(|account| data.has_client(account))
+                .cloned()
+                .collect();
+            accounts.sort();
+            accounts.dedup();
+            accounts
+        }
+    };
+
+    let empty = Default::default();
+    let mut changes = Vec::new();
+    for account in accounts {
+        let list = snapshot
+            .contacts
+            .contacts_list
+            .get(&account)
+            .unwrap_or(&empty);
+        for change in contacts.restore_account(&account, list) {
+            changes.push((account.clone(), change));
+        }
+    }
+    Ok(Restored {
+        changes,
+        removed_accounts,
+    })
+}
@@ -0,0 +1,143 @@
+use crate::crypto;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Where the server keeps its files: the accounts, the contacts lists and the snapshots. Names are
+// paths relative to the store, like "snapshots/snapshot-1.json".
+pub trait Store: Send + Sync {
+    // None if there is no such file.
+    fn read(&self, name: &str) -> io::Result<Option<Vec<u8>>>;
+    fn write(&self, name: &str, contents: &[u8]) -> io::Result<()>;
+    fn remove(&self, name: &str) -> io::Result<()>;
+    // Names of the files in `dir`, without the directory.
+    fn list(&self, dir: &str) -> io::Result<Vec<String>>;
+}
+
+// Files in a directory on disk, encrypted with the server keys, so `crypto::init` must have been
+// called first. Files are written whole to a temporary file and renamed over the old one.
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        FileStore {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    // The one time plain files are read: encrypts the files among `names` that were written
+    // before encryption was added. Refused once any of them is encrypted, a plain file next to
+    // encrypted ones wasn't written by the server. Returns the names of the files encrypted.
+    pub fn encrypt_plaintext(&self, names: &[String]) -> io::Result<Vec<String>> {
+        let mut plain = Vec::new();
+        let mut sealed = Vec::new();
+        for name in names {
+            match fs::read(self.dir.join(name)) {
+                Ok(contents) if crypto::is_sealed(&contents) => sealed.push(name),
+                Ok(contents) => plain.push((name, contents)),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if !sealed.is_empty() && !plain.is_empty() {
+            let plain: Vec<&str> = plain.iter().map(|(name, _)| name.as_str()).collect();
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "the store is already encrypted, but {} isn't",
+                    plain.join(", ")
+                ),
+            ));
+        }
+        for (name, contents) in plain.iter() {
+            self.write(name, contents)?;
+        }
+        Ok(plain.into_iter().map(|(name, _)| name.clone()).collect())
+    }
+}
+
+impl Store for FileStore {
+    fn read(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(self.dir.join(name)) {
+            Ok(contents) => crypto::open(&contents).map(Some),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write(&self, name: &str, contents: &[u8]) -> io::Result<()> {
+        let path = self.dir.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut partial = path.clone().into_os_string();
+        partial.push(".partial");
+        fs::write(&partial, crypto::seal(contents))?;
+        fs::rename(&partial, &path)
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        fs::remove_file(self.dir.join(name))
+    }
+
+    fn list(&self, dir: &str) -> io::Result<Vec<String>> {
+        match fs::read_dir(self.dir.join(dir)) {
+            Ok(entries) => Ok(entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .filter(|name| !name.ends_with(".partial"))
+                .collect()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+// Keeps everything in memory and loses it with the process, for tests.
+#[derive(Default)]
+pub struct MemoryStore {
+    files: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+}
+
+impl Store for MemoryStore {
+    fn read(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.files.lock().unwrap().get(name).cloned())
+    }
+
+    fn write(&self, name: &str, contents: &[u8]) -> io::Result<()> {
+        let mut files = self.files.lock().unwrap();
+        files.insert(name.to_owned(), contents.to_vec());
+        Ok(())
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        match self.files.lock().unwrap().remove(name) {
+            Some(_) => Ok(()),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn list(&self, dir: &str) -> io::Result<Vec<String>> {
+        let prefix = format!("{}/", dir);
+        Ok(self
+            .files
+            .lock()
+            .unwrap()
+            .keys()
+            .filter_map(|name| name.strip_prefix(&prefix))
+            .filter(|name| !name.contains('/'))
+            .map(str::to_owned)
+            .collect())
+    }
+}
@@ -0,0 +1,69 @@
+use crate::net::Stream;
+use crate::{Change, Feedback};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+pub type SharedStream = Arc<Mutex<Stream>>;
+
+#[derive(Default)]
+pub struct Subscriptions {
+    next_id: usize,
+    subscribers: HashMap<String, Vec<(usize, SharedStream)>>, // <account name, subscribed connections>
+}
+
+impl Subscriptions {
+    pub fn new() -> Self {
+        Subscriptions::default()
+    }
+
+    pub fn subscribe(&mut self, account: &str, stream: SharedStream) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.subscribers
+            .entry(account.to_owned())
+            .or_default()
+            .push((id, stream));
+        id
+    }
+
+    pub fn unsubscribe(&mut self, account: &str, id: usize) {
+        if let Some(list) = self.subscribers.get_mut(account) {
+            list.retain(|(sub_id, _)| *sub_id != id);
+            if list.is_empty() {
+                self.subscribers.remove(account);
+            }
+        }
+    }
+
+    // Moves the subscription `id` over to the account's new name. Other sessions of the
+    // renamed account are being ended, so their subscriptions are dropped.
+    pub fn rename(&mut self, account: &str, new_name: &str, id: Option<usize>) {
+        let list = self.subscribers.remove(account).unwrap_or_default();
+        let kept: Vec<(usize, SharedStream)> = list
+            .into_iter()
+            .filter(|(sub_id, _)| Some(*sub_id) == id)
+            .collect();
+        if !kept.is_empty() {
+            self.subscribers.insert(new_name.to_owned(), kept);
+        }
+    }
+
+    pub fn remove_account(&mut self, account: &str) {
+        self.subscribers.remove(account);
+    }
+
+    // Pushes `change` to every connection subscribed to `account` except the one that made it.
+    // Connections that can't be written to anymore are dropped.
+    pub fn publish(&mut self, account: &str, origin: Option<usize>, change: &Change) {
+        if let Some(list) = self.subscribers.get_mut(account) {
+            list.retain(|(id, stream)| {
+                if Some(*id) == origin {
+                    return true;
+                }
+                let mut stream = stream.lock().unwrap();
+                serde_json::to_writer(&mut *stream, &Feedback::new_event(change.clone())).is_ok()
+            });
+        }
+    }
+}
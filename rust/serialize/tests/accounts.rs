@@ -0,0 +1,309 @@
+// What clients can do with their own account, and what admins can do with everyone's.
+use serde_json::{json, Value};
+use serialize::{Config, MemoryStore, Server, Store};
+
+use std::io::Write;
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+
+const ADMIN: &str = "admin";
+const PASSWORD: &str = "secret";
+// Failed logins in a row that lock an account.
+const MAX_FAILED_LOGINS: usize = 5;
+
+fn start() -> SocketAddr {
+    start_with(MemoryStore::new())
+}
+
+// The first admin is made by hand in the accounts file, like on a real server.
+fn start_with_admin() -> SocketAddr {
+    let store = MemoryStore::new();
+    let data = json!({
+        "version": 2,
+        "clients": [
+            {"name": ADMIN, "password": PASSWORD, "role": "Admin", "failed_logins": 0, "locked": false}
+        ]
+    });
+    store
+        .write("Data.json", data.to_string().as_bytes())
+        .unwrap();
+    start_with(store)
+}
+
+fn start_with(store: MemoryStore) -> SocketAddr {
+    let config = Config {
+        log_requests: false,
+        ..Config::default()
+    };
+    let server = Server::bind_with("127.0.0.1:0", store, config).unwrap();
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.run());
+    addr
+}
+
+struct Client {
+    stream: TcpStream,
+    responses: serde_json::StreamDeserializer<'static, serde_json::de::IoRead<TcpStream>, Value>,
+}
+
+impl Client {
+    fn connect(addr: SocketAddr) -> Self {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_nodelay(true).unwrap();
+        let reader = stream.try_clone().unwrap();
+        Client {
+            stream,
+            responses: serde_json::Deserializer::from_reader(reader).into_iter(),
+        }
+    }
+
+    fn send(&mut self, message: Value) {
+        serde_json::to_writer(&mut self.stream, &message).unwrap();
+        self.stream.flush().unwrap();
+    }
+
+    fn receive(&mut self) -> Result<Value, Value> {
+        let feedback = self.responses.next().unwrap().unwrap();
+        match feedback["result"].as_object().unwrap().iter().next() {
+            Some((result, value)) if result == "Ok" => Ok(value.clone()),
+            Some((_, value)) => Err(value.clone()),
+            None => panic!("malformed feedback {}", feedback),
+        }
+    }
+
+    fn request(&mut self, ans: Value) -> Result<Value, Value> {
+        self.send(json!({ "result": { "Ok": ans } }));
+        self.receive()
+    }
+
+    // Logs in, creates or deletes an account, returns the query the server answered with.
+    fn authenticate(&mut self, query: &str, name: &str, password: &str) -> Result<Value, Value> {
+        self.send(json!({"result": {"Ok": {"query": query}}}));
+        self.send(json!({"name": name, "password": password}));
+        self.receive().map(|ans| ans["query"].clone())
+    }
+
+    // The command's reply, Ok or Err, or Forbidden for sessions that aren't an admin's.
+    fn admin(&mut self, command: Value) -> Result<Value, Value> {
+        self.request(json!({"query": "Admin", "admin": command}))
+            .map(|ans| ans["admin_reply"].clone())
+    }
+
+    // Whether the server hung up.
+    fn closed(&mut self) -> bool {
+        !matches!(self.responses.next(), Some(Ok(_)))
+    }
+
+    fn add(&mut self, name: &str, phone: u64) {
+        self.request(json!({"query": "Add", "name": name, "phone": phone}))
+            .unwrap();
+    }
+
+    // Sorted phone numbers of the account's contacts. Sessions whose account was renamed or
+    // deleted by another one are told to log in again.
+    fn phones(&mut self) -> Result<Vec<u64>, Value> {
+        self.send(json!({"result": {"Ok": {"query": "ShowList"}}}));
+        let mut phones = Vec::new();
+        loop {
+            let ans = self.receive()?;
+            if ans["query"] == "Done" {
+                phones.sort_unstable();
+                return Ok(phones);
+            }
+            phones.push(ans["phone"].as_u64().unwrap());
+        }
+    }
+}
+
+fn logged_in(addr: SocketAddr, name: &str) -> Option<Client> {
+    let mut client = Client::connect(addr);
+    client.authenticate("Login", name, PASSWORD).ok()?;
+    Some(client)
+}
+
+fn signed_up(addr: SocketAddr, name: &str) -> Client {
+    let mut client = Client::connect(addr);
+    assert_eq!(
+        client.authenticate("CreateAccount", name, PASSWORD),
+        Ok(json!("CreateAccount"))
+    );
+    client
+}
+
+#[test]
+fn deleting_an_account_takes_its_contacts() {
+    let addr = start();
+    let mut alice = signed_up(addr, "alice");
+    let mut other_session = logged_in(addr, "alice").unwrap();
+    alice.add("Bob", 1);
+
+    // The password is asked for again, and only the session's own account can be deleted
+    assert_eq!(
+        alice.authenticate("DeleteAccount", "alice", "wrong"),
+        Err(json!("DeleteAccount"))
+    );
+    drop(signed_up(addr, "carol"));
+    assert_eq!(
+        alice.authenticate("DeleteAccount", "carol", PASSWORD),
+        Err(json!("DeleteAccount"))
+    );
+    assert_eq!(alice.phones(), Ok(vec![1]));
+    assert_eq!(
+        alice.authenticate("DeleteAccount", "alice", PASSWORD),
+        Ok(json!("DeleteAccount"))
+    );
+
+    assert_eq!(other_session.phones(), Err(json!("Login")));
+    assert!(logged_in(addr, "alice").is_none());
+    assert!(logged_in(addr, "carol").is_some());
+    // The name is free again, without the old contacts
+    assert_eq!(signed_up(addr, "alice").phones(), Ok(Vec::<u64>::new()));
+}
+
+#[test]
+fn renaming_an_account_keeps_its_contacts() {
+    let addr = start();
+    let mut alice = signed_up(addr, "alice");
+    let mut other_session = logged_in(addr, "alice").unwrap();
+    alice.add("Bob", 1);
+    drop(signed_up(addr, "carol"));
+
+    for taken in ["carol", ""] {
+        let rename = json!({"query": "RenameAccount", "name": taken});
+        assert_eq!(alice.request(rename), Err(json!("RenameAccount")));
+    }
+    let rename = json!({"query": "RenameAccount", "name": "alicia"});
+    assert_eq!(alice.request(rename).unwrap()["name"], "alicia");
+    assert_eq!(alice.phones(), Ok(vec![1]));
+
+    // Other sessions of the account have to log in again, under the new name
+    assert_eq!(other_session.phones(), Err(json!("Login")));
+    assert!(logged_in(addr, "alice").is_none());
+    assert_eq!(logged_in(addr, "alicia").unwrap().phones(), Ok(vec![1]));
+}
+
+#[test]
+fn export_has_everything_about_the_account() {
+    let addr = start();
+    let mut alice = signed_up(addr, "alice");
+    let bob = json!({"query": "Add", "name": "Bob", "phone": 2, "email": "bob@example.com"});
+    alice.request(bob).unwrap();
+    alice.add("Carol", 1);
+    signed_up(addr, "dave").add("Erin", 3);
+
+    let ans = alice.request(json!({"query": "ExportData"})).unwrap();
+    let export = &ans["export"];
+    assert_eq!(export["account"], "alice");
+    assert_eq!(export["revision"], 2);
+    assert_eq!(ans["revision"], 2);
+    assert!(export["exported_at"].as_u64().unwrap() > 0);
+    let contacts: Vec<(u64, &str, &Value)> = export["contacts"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| {
+            (
+                c["phone"].as_u64().unwrap(),
+                c["name"].as_str().unwrap(),
+                &c["email"],
+            )
+        })
+        .collect();
+    assert_eq!(
+        contacts,
+        vec![
+            (1, "Carol", &Value::Null),
+            (2, "Bob", &json!("bob@example.com"))
+        ]
+    );
+}
+
+fn login(addr: SocketAddr, name: &str, password: &str) -> Result<Value, Value> {
+    Client::connect(addr).authenticate("Login", name, password)
+}
+
+#[test]
+fn too_many_failed_logins_lock_the_account() {
+    let addr = start_with_admin();
+    drop(signed_up(addr, "alice"));
+    drop(signed_up(addr, "bob"));
+
+    // Logging in resets the count
+    for _ in 0..MAX_FAILED_LOGINS - 1 {
+        assert_eq!(login(addr, "bob", "wrong"), Err(json!("Login")));
+    }
+    assert!(login(addr, "bob", PASSWORD).is_ok());
+    for _ in 0..MAX_FAILED_LOGINS - 1 {
+        assert_eq!(login(addr, "bob", "wrong"), Err(json!("Login")));
+    }
+    assert!(login(addr, "bob", PASSWORD).is_ok());
+
+    // Attempts on the same connection count too
+    let mut client = Client::connect(addr);
+    for _ in 0..MAX_FAILED_LOGINS - 1 {
+        assert_eq!(
+            client.authenticate("Login", "alice", "wrong"),
+            Err(json!("Login"))
+        );
+    }
+    assert_eq!(
+        client.authenticate("Login", "alice", "wrong"),
+        Err(json!("AccountLocked"))
+    );
+    assert_eq!(
+        client.authenticate("Login", "alice", PASSWORD),
+        Err(json!("AccountLocked"))
+    );
+    // Unknown accounts aren't told apart from a wrong password
+    assert_eq!(login(addr, "nobody", "wrong"), Err(json!("Login")));
+
+    let mut admin = logged_in(addr, ADMIN).unwrap();
+    let accounts = admin.admin(json!("Accounts")).unwrap()["Ok"]["Accounts"].clone();
+    let locked: Vec<(&str, bool)> = accounts
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|acc| (acc["name"].as_str().unwrap(), acc["locked"] == true))
+        .collect();
+    assert_eq!(locked, [("admin", false), ("alice", true), ("bob", false)]);
+    assert!(admin.admin(json!({"Unlock": "alice"})).unwrap()["Ok"].is_object());
+    assert!(login(addr, "alice", PASSWORD).is_ok());
+}
+
+#[test]
+fn admin_commands_are_for_admins_only() {
+    let addr = start_with_admin();
+    let mut alice = signed_up(addr, "alice");
+    alice.add("Bob", 1);
+    assert_eq!(alice.admin(json!("Stats")), Err(json!("Forbidden")));
+
+    let mut admin = logged_in(addr, ADMIN).unwrap();
+    let stats = admin.admin(json!("Stats")).unwrap()["Ok"]["Stats"].clone();
+    assert_eq!(
+        (&stats["accounts"], &stats["contacts"], &stats["sessions"]),
+        (&json!(2), &json!(1), &json!(2))
+    );
+    let accounts = admin.admin(json!("Accounts")).unwrap()["Ok"]["Accounts"].clone();
+    assert_eq!(accounts[1]["name"], "alice");
+    assert_eq!(accounts[1]["role"], "User");
+    assert_eq!(accounts[1]["contacts"], 1);
+    assert!(admin.admin(json!({"Unlock": "nobody"})).unwrap()["Err"].is_string());
+
+    // The role is checked on every request
+    let make_admin = json!({"SetRole": ["alice", "Admin"]});
+    assert!(admin.admin(make_admin).unwrap()["Ok"].is_object());
+    assert!(alice.admin(json!("Stats")).unwrap()["Ok"].is_object());
+    let make_user = json!({"SetRole": ["alice", "User"]});
+    assert!(admin.admin(make_user).unwrap()["Ok"].is_object());
+    assert_eq!(alice.admin(json!("Stats")), Err(json!("Forbidden")));
+
+    let reset = json!({"ResetPassword": ["alice", "new secret"]});
+    assert!(admin.admin(reset).unwrap()["Ok"].is_object());
+    assert_eq!(login(addr, "alice", PASSWORD), Err(json!("Login")));
+    assert!(login(addr, "alice", "new secret").is_ok());
+
+    // Every session of the account is ended
+    let reply = admin.admin(json!({"Logout": "alice"})).unwrap();
+    assert!(reply["Ok"]["Done"].is_string());
+    assert!(alice.closed());
+}
@@ -0,0 +1,214 @@
+// Data files and snapshots on disk, sealed with the server keys.
+use serde_json::{json, Value};
+use serialize::{crypto, offline, Config, FileStore, Server, Store};
+
+use std::fs;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+use std::thread;
+
+const PASSWORD: &str = "secret";
+
+// The keys are loaded once per process, from a key file generated in the test's temporary
+// directory so rotating it doesn't touch anything else.
+fn init_keys() {
+    static KEYS: Once = Once::new();
+    KEYS.call_once(|| {
+        let dir = temp_dir("keys");
+        std::env::remove_var("SERIALIZE_KEYS");
+        std::env::set_var("SERIALIZE_KEY_FILE", dir.join("server.key"));
+        crypto::init().unwrap();
+    });
+}
+
+fn temp_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "serialize-encryption-{}-{}",
+        std::process::id(),
+        test
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn start(dir: &Path) -> io::Result<SocketAddr> {
+    let config = Config {
+        log_requests: false,
+        ..Config::default()
+    };
+    let server = Server::bind_with("127.0.0.1:0", FileStore::new(dir), config)?;
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.run());
+    Ok(addr)
+}
+
+struct Client {
+    stream: TcpStream,
+    responses: serde_json::StreamDeserializer<'static, serde_json::de::IoRead<TcpStream>, Value>,
+}
+
+impl Client {
+    fn authenticated(addr: SocketAddr, query: &str, name: &str) -> Self {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_nodelay(true).unwrap();
+        let reader = stream.try_clone().unwrap();
+        let mut client = Client {
+            stream,
+            responses: serde_json::Deserializer::from_reader(reader).into_iter(),
+        };
+        client.send(json!({"result": {"Ok": {"query": query}}}));
+        client.send(json!({"name": name, "password": PASSWORD}));
+        client.receive().unwrap();
+        client
+    }
+
+    fn send(&mut self, message: Value) {
+        serde_json::to_writer(&mut self.stream, &message).unwrap();
+        self.stream.flush().unwrap();
+    }
+
+    fn receive(&mut self) -> Result<Value, Value> {
+        let feedback = self.responses.next().unwrap().unwrap();
+        match feedback["result"].as_object().unwrap().iter().next() {
+            Some((result, value)) if result == "Ok" => Ok(value.clone()),
+            Some((_, value)) => Err(value.clone()),
+            None => panic!("malformed feedback {}", feedback),
+        }
+    }
+
+    fn request(&mut self, ans: Value) -> Value {
+        self.send(json!({ "result": { "Ok": ans } }));
+        self.receive().unwrap()
+    }
+
+    fn names(&mut self) -> Vec<String> {
+        self.send(json!({"result": {"Ok": {"query": "ShowList"}}}));
+        let mut names = Vec::new();
+        loop {
+            let ans = self.receive().unwrap();
+            if ans["query"] == "Done" {
+                return names;
+            }
+            names.push(ans["name"].as_str().unwrap().to_owned());
+        }
+    }
+}
+
+// Signs up and adds a contact called Bob, then saves.
+fn saved_contact(addr: SocketAddr) {
+    let mut client = Client::authenticated(addr, "CreateAccount", "alice");
+    client.request(json!({"query": "Add", "name": "Bob", "phone": 1}));
+    client.request(json!({"query": "Save"}));
+}
+
+// The id of the key a file was sealed with, from its header.
+fn key_id(file: &Path) -> u32 {
+    let contents = fs::read(file).unwrap();
+    assert!(
+        crypto::is_sealed(&contents),
+        "{} isn't sealed",
+        file.display()
+    );
+    let mut id = [0; 4];
+    id.copy_from_slice(&contents[4..8]);
+    u32::from_le_bytes(id)
+}
+
+#[test]
+fn data_files_are_sealed_and_tampering_is_caught() {
+    init_keys();
+    let dir = temp_dir("sealed");
+    saved_contact(start(&dir).unwrap());
+
+    let contacts = dir.join("Contacts_list.json");
+    let mut sealed = fs::read(&contacts).unwrap();
+    assert!(crypto::is_sealed(&sealed));
+    assert!(!String::from_utf8_lossy(&sealed).contains("Bob"));
+    let opened = crypto::open(&sealed).unwrap();
+    assert!(String::from_utf8(opened).unwrap().contains("Bob"));
+    // Another server reads them back
+    let addr = start(&dir).unwrap();
+    assert_eq!(
+        Client::authenticated(addr, "Login", "alice").names(),
+        ["Bob"]
+    );
+
+    // A single changed byte, in the contents or the header, and the file isn't read
+    for at in [sealed.len() - 1, 10] {
+        sealed[at] ^= 1;
+        assert!(crypto::open(&sealed).is_err());
+        fs::write(&contacts, &sealed).unwrap();
+        let error = start(&dir).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        sealed[at] ^= 1;
+    }
+}
+
+#[test]
+fn plain_files_are_only_read_to_encrypt_them() {
+    init_keys();
+    let dir = temp_dir("plain");
+    let data = json!({"version": 3, "clients": [
+        {"name": "alice", "password": PASSWORD, "role": "User", "failed_logins": 0, "locked": false}
+    ]});
+    fs::write(dir.join("Data.json"), data.to_string()).unwrap();
+    assert!(start(&dir).is_err());
+
+    let store = FileStore::new(&dir);
+    assert_eq!(offline::encrypt(&store), Ok(vec!["Data.json".to_owned()]));
+    assert!(crypto::is_sealed(&fs::read(dir.join("Data.json")).unwrap()));
+    assert_eq!(offline::encrypt(&store), Ok(vec![]));
+    let addr = start(&dir).unwrap();
+    assert!(Client::authenticated(addr, "Login", "alice")
+        .names()
+        .is_empty());
+
+    // A plain file next to sealed ones wasn't written by the server
+    fs::write(dir.join("Contacts_list.json"), b"{}").unwrap();
+    assert!(offline::encrypt(&store).is_err());
+}
+
+#[test]
+fn rotating_the_key_reseals_everything() {
+    init_keys();
+    let dir = temp_dir("rotate");
+    let admin = json!({"version": 3, "clients": [
+        {"name": "admin", "password": PASSWORD, "role": "Admin", "failed_logins": 0, "locked": false}
+    ]});
+    let store = FileStore::new(&dir);
+    store
+        .write("Data.json", admin.to_string().as_bytes())
+        .unwrap();
+    let addr = start(&dir).unwrap();
+    saved_contact(addr);
+    let mut admin = Client::authenticated(addr, "Login", "admin");
+    admin.request(json!({"query": "Admin", "admin": "Snapshot"}));
+    let snapshot = fs::read_dir(dir.join("snapshots"))
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+
+    let old_id = key_id(&dir.join("Data.json"));
+    let old_contacts = fs::read(dir.join("Contacts_list.json")).unwrap();
+    let new_id = offline::rotate_key(FileStore::new(&dir)).unwrap();
+    assert!(new_id > old_id);
+    for file in [
+        dir.join("Data.json"),
+        dir.join("Contacts_list.json"),
+        snapshot,
+    ] {
+        assert_eq!(key_id(&file), new_id);
+    }
+    // The old key is kept, for backups sealed with it
+    assert!(crypto::open(&old_contacts).is_ok());
+    let addr = start(&dir).unwrap();
+    assert_eq!(
+        Client::authenticated(addr, "Login", "alice").names(),
+        ["Bob"]
+    );
+}
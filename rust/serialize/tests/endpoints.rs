@@ -0,0 +1,160 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+use serialize::{Config, Endpoint, MemoryStore, Server};
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+// A directory of its own for every test's sockets, emptied first in case an earlier run crashed.
+fn socket_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "serialize-endpoints-{}-{}",
+        std::process::id(),
+        test
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn unix(path: &Path) -> Endpoint {
+    Endpoint::Unix(path.to_owned())
+}
+
+fn start(endpoints: &[Endpoint], config: Config) -> Vec<Endpoint> {
+    let config = Config {
+        log_requests: false,
+        ..config
+    };
+    let server = Server::listen(endpoints, MemoryStore::new(), config).unwrap();
+    let endpoints = server.endpoints().unwrap();
+    thread::spawn(move || server.run());
+    endpoints
+}
+
+// Sends credentials with `query`, returns whether the server accepted them.
+fn authenticate<S: Read + Write>(mut stream: S, query: &str, name: &str) -> bool {
+    serde_json::to_writer(&mut stream, &json!({"result": {"Ok": {"query": query}}})).unwrap();
+    serde_json::to_writer(&mut stream, &json!({"name": name, "password": "secret"})).unwrap();
+    let mut de = serde_json::Deserializer::from_reader(&mut stream);
+    Value::deserialize(&mut de).unwrap()["result"]["Ok"]["query"] == query
+}
+
+fn tcp(endpoint: &Endpoint) -> TcpStream {
+    match endpoint {
+        Endpoint::Tcp(addr) => TcpStream::connect(addr.as_str()).unwrap(),
+        Endpoint::Unix(_) => panic!("{} is not a TCP endpoint", endpoint),
+    }
+}
+
+#[test]
+fn addresses_parse() {
+    assert_eq!(
+        "tcp://127.0.0.1:54321".parse(),
+        Ok(Endpoint::Tcp("127.0.0.1:54321".to_owned()))
+    );
+    assert_eq!(
+        "tcp://[::]:54321".parse(),
+        Ok(Endpoint::Tcp("[::]:54321".to_owned()))
+    );
+    assert_eq!(
+        "unix:///run/serialize.sock".parse(),
+        Ok(Endpoint::Unix(PathBuf::from("/run/serialize.sock")))
+    );
+    // Addresses without a scheme are TCP, as they always were
+    assert_eq!(
+        "localhost:54321".parse(),
+        Ok(Endpoint::Tcp("localhost:54321".to_owned()))
+    );
+    assert!("http://localhost:54321".parse::<Endpoint>().is_err());
+    assert!("unix://".parse::<Endpoint>().is_err());
+    assert!("tcp://".parse::<Endpoint>().is_err());
+
+    let endpoint = Endpoint::Unix(PathBuf::from("/run/serialize.sock"));
+    assert_eq!(endpoint.to_string().parse(), Ok(endpoint));
+}
+
+#[test]
+fn serves_the_same_accounts_on_every_endpoint() {
+    let dir = socket_dir("every");
+    let socket = dir.join("serialize.sock");
+    let endpoints = start(
+        &[
+            "tcp://127.0.0.1:0".parse().unwrap(),
+            "tcp://[::1]:0".parse().unwrap(),
+            unix(&socket),
+        ],
+        Config::default(),
+    );
+    assert_eq!(endpoints.len(), 3);
+    assert_eq!(endpoints[2], unix(&socket));
+
+    assert!(authenticate(
+        UnixStream::connect(&socket).unwrap(),
+        "CreateAccount",
+        "alice"
+    ));
+    assert!(authenticate(tcp(&endpoints[0]), "Login", "alice"));
+    assert!(authenticate(tcp(&endpoints[1]), "Login", "alice"));
+    assert!(authenticate(tcp(&endpoints[1]), "CreateAccount", "bob"));
+    assert!(authenticate(
+        UnixStream::connect(&socket).unwrap(),
+        "Login",
+        "bob"
+    ));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn unix_socket_permissions() {
+    let dir = socket_dir("permissions");
+    let private = dir.join("private.sock");
+    let shared = dir.join("shared.sock");
+    start(&[unix(&private)], Config::default());
+    start(
+        &[unix(&shared)],
+        Config {
+            socket_mode: 0o660,
+            ..Config::default()
+        },
+    );
+    let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+    assert_eq!(mode(&private), 0o600);
+    assert_eq!(mode(&shared), 0o660);
+    // They were bound somewhere private and moved in, which left nothing else behind
+    let mut files: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    files.sort();
+    assert_eq!(files, ["private.sock", "shared.sock"]);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn stale_socket_is_replaced_but_a_live_one_is_not() {
+    let dir = socket_dir("stale");
+    let socket = dir.join("serialize.sock");
+    // What a server that was killed leaves behind
+    drop(std::os::unix::net::UnixListener::bind(&socket).unwrap());
+    start(&[unix(&socket)], Config::default());
+    assert!(authenticate(
+        UnixStream::connect(&socket).unwrap(),
+        "CreateAccount",
+        "alice"
+    ));
+
+    let taken = Server::listen(&[unix(&socket)], MemoryStore::new(), Config::default());
+    assert!(taken.is_err());
+
+    let file = dir.join("not-a-socket");
+    fs::write(&file, "keep me").unwrap();
+    assert!(Server::listen(&[unix(&file)], MemoryStore::new(), Config::default()).is_err());
+    assert_eq!(fs::read_to_string(&file).unwrap(), "keep me");
+    fs::remove_dir_all(dir).unwrap();
+}
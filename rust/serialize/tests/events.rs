@@ -0,0 +1,240 @@
+use serde_json::{json, Value};
+use serialize::{MemoryStore, Server};
+
+use std::io::Write;
+use std::net::TcpStream;
+use std::thread;
+
+struct Client {
+    stream: TcpStream,
+    responses: serde_json::StreamDeserializer<'static, serde_json::de::IoRead<TcpStream>, Value>,
+}
+
+impl Client {
+    // Signs up to a new server with an empty in-memory store.
+    fn start() -> Self {
+        let server = Server::bind("127.0.0.1:0", MemoryStore::new()).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let reader = stream.try_clone().unwrap();
+        let mut client = Client {
+            stream,
+            responses: serde_json::Deserializer::from_reader(reader).into_iter(),
+        };
+        client.send(json!({"result": {"Ok": {"query": "CreateAccount"}}}));
+        client.send(json!({"name": "alice", "password": "secret"}));
+        client.receive().unwrap();
+        client
+    }
+
+    fn send(&mut self, message: Value) {
+        serde_json::to_writer(&mut self.stream, &message).unwrap();
+        self.stream.flush().unwrap();
+    }
+
+    fn receive(&mut self) -> Result<Value, Value> {
+        let feedback = self.responses.next().unwrap().unwrap();
+        match feedback["result"].as_object().unwrap().iter().next() {
+            Some((result, value)) if result == "Ok" => Ok(value.clone()),
+            Some((_, value)) => Err(value.clone()),
+            None => panic!("malformed feedback {}", feedback),
+        }
+    }
+
+    fn request(&mut self, ans: Value) -> Result<Value, Value> {
+        self.send(json!({ "result": { "Ok": ans } }));
+        self.receive()
+    }
+
+    fn upcoming(&mut self, from: &str, days: u32) -> Result<Value, Value> {
+        self.request(json!({"query": "UpcomingEvents", "from": from, "days": days}))
+            .map(|ans| ans["events"].clone())
+    }
+
+    fn calendar(&mut self) -> String {
+        let ans = self.request(json!({"query": "ExportCalendar"})).unwrap();
+        ans["calendar"].as_str().unwrap().to_owned()
+    }
+}
+
+#[test]
+fn dates_are_stored_and_synced() {
+    let mut client = Client::start();
+    let added = client
+        .request(json!({"query": "Add", "name": "Bob", "phone": 1, "birthday": "1990-05-12"}))
+        .unwrap();
+    assert_eq!(added["birthday"], "1990-05-12");
+    assert_eq!(added["anniversary"], Value::Null);
+
+    // Dates that aren't sent are kept, an empty one is cleared
+    let updated = client
+        .request(json!({"query": "Update", "name": "Bob", "phone": 1, "anniversary": "06-01"}))
+        .unwrap();
+    assert_eq!(updated["birthday"], "1990-05-12");
+    assert_eq!(updated["anniversary"], "--06-01");
+    let updated = client
+        .request(json!({"query": "Update", "name": "Bob", "phone": 1, "birthday": ""}))
+        .unwrap();
+    assert_eq!(updated["birthday"], Value::Null);
+    assert_eq!(updated["anniversary"], "--06-01");
+
+    client.send(json!({"result": {"Ok": {"query": "SyncSince", "revision": 0}}}));
+    let change = client.receive().unwrap()["change"].clone();
+    assert_eq!(change["anniversary"], "--06-01");
+    assert_eq!(change["birthday"], Value::Null);
+    assert_eq!(client.receive().unwrap()["query"], "Done");
+}
+
+#[test]
+fn merging_keeps_the_dates_and_email() {
+    let mut client = Client::start();
+    for ans in [
+        json!({"query": "Add", "name": "Bob", "phone": 1, "email": "bob@example.com", "anniversary": "06-01"}),
+        json!({"query": "Add", "name": "Bob S", "phone": 2, "birthday": "1990-05-12", "anniversary": "07-01"}),
+    ] {
+        client.request(ans).unwrap();
+    }
+
+    // The kept contact's anniversary wins, its missing birthday comes from the other one
+    let merge =
+        json!({"query": "Merge", "phones": [1, 2], "phone": 1, "name": "Bob", "preview": false});
+    let merged = client.request(merge).unwrap();
+    assert_eq!(merged["email"], "bob@example.com");
+    assert_eq!(merged["birthday"], "1990-05-12");
+    assert_eq!(merged["anniversary"], "--06-01");
+
+    client.send(json!({"result": {"Ok": {"query": "SyncSince", "revision": 0}}}));
+    let change = client.receive().unwrap()["change"].clone();
+    assert_eq!(change["phone"], 1);
+    assert_eq!(change["email"], "bob@example.com");
+    assert_eq!(change["birthday"], "1990-05-12");
+    assert_eq!(change["anniversary"], "--06-01");
+    assert_eq!(client.receive().unwrap()["query"], "Done");
+}
+
+#[test]
+fn malformed_dates_are_refused() {
+    let mut client = Client::start();
+    for date in [
+        "2023-02-29",
+        "1990-13-01",
+        "--04-31",
+        "1990-5",
+        "tomorrow",
+        "0000-01-01",
+    ] {
+        assert_eq!(
+            client.request(json!({"query": "Add", "name": "Bob", "phone": 1, "birthday": date})),
+            Err(json!("InvalidDate")),
+            "{}",
+            date
+        );
+    }
+    // February 29th is fine in leap years, and without a year
+    for (phone, date) in [(1, "2024-02-29"), (2, "--02-29")] {
+        let ans = json!({"query": "Add", "name": "Bob", "phone": phone, "anniversary": date});
+        assert!(client.request(ans).is_ok());
+    }
+}
+
+#[test]
+fn upcoming_events_in_a_window() {
+    let mut client = Client::start();
+    for (name, phone, birthday, anniversary) in [
+        ("Bob", 1, "1990-05-12", Value::Null),
+        ("Carol", 2, "--06-01", json!("2015-05-20")),
+        ("Dave", 3, "1985-01-05", Value::Null),
+        ("Erin", 4, "2000-02-29", Value::Null),
+    ] {
+        let ans = json!({
+            "query": "Add",
+            "name": name,
+            "phone": phone,
+            "birthday": birthday,
+            "anniversary": anniversary,
+        });
+        client.request(ans).unwrap();
+    }
+
+    let events = client.upcoming("2026-05-01", 45).unwrap();
+    let summary: Vec<(&str, &str, &str, Value)> = events
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|event| {
+            (
+                event["kind"].as_str().unwrap(),
+                event["name"].as_str().unwrap(),
+                event["date"].as_str().unwrap(),
+                event["years"].clone(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            ("Birthday", "Bob", "2026-05-12", json!(36)),
+            ("Anniversary", "Carol", "2026-05-20", json!(11)),
+            ("Birthday", "Carol", "2026-06-01", Value::Null),
+        ]
+    );
+
+    // The window runs into the next year
+    let events = client.upcoming("2026-12-20", 30).unwrap();
+    assert_eq!(events[0]["name"], "Dave");
+    assert_eq!(events[0]["date"], "2027-01-05");
+    assert_eq!(events[0]["years"], 42);
+    // Leap day birthdays fall on the 28th in other years
+    let events = client.upcoming("2027-02-01", 28).unwrap();
+    assert_eq!(events[0]["date"], "2027-02-28");
+    assert_eq!(client.upcoming("2028-02-29", 1).unwrap()[0]["years"], 28);
+    // The window ends before its last day
+    assert_eq!(client.upcoming("2026-05-01", 11).unwrap(), json!([]));
+    assert_eq!(client.upcoming("2026-05-01", 12).unwrap()[0]["name"], "Bob");
+
+    for (from, days) in [("2026-05-01", 0), ("2026-05-01", 367), ("--05-01", 30)] {
+        assert_eq!(client.upcoming(from, days), Err(json!("UpcomingEvents")));
+    }
+    // Today and the next 30 days by default
+    let ans = client.request(json!({"query": "UpcomingEvents"})).unwrap();
+    assert!(ans["events"].is_array());
+}
+
+#[test]
+fn calendar_export() {
+    let mut client = Client::start();
+    assert!(!client.calendar().contains("BEGIN:VEVENT"));
+
+    let ans = json!({
+        "query": "Add",
+        "name": "Smith, Bob; Jr",
+        "phone": 1,
+        "birthday": "1990-05-12",
+        "anniversary": "--02-29",
+    });
+    client.request(ans).unwrap();
+    let long_name = "A very long name that goes on and on, well past what fits on a line";
+    let ans = json!({"query": "Add", "name": long_name, "phone": 2, "birthday": "--12-24"});
+    client.request(ans).unwrap();
+
+    let calendar = client.calendar();
+    assert!(calendar.starts_with("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n"));
+    assert!(calendar.ends_with("END:VCALENDAR\r\n"));
+    assert_eq!(calendar.matches("BEGIN:VEVENT").count(), 3);
+    for line in calendar.split("\r\n") {
+        assert!(line.len() <= 75, "line too long: {}", line);
+    }
+    let unfolded = calendar.replace("\r\n ", "");
+    assert!(unfolded.contains(
+        "DTSTART;VALUE=DATE:19900512\r\nRRULE:FREQ=YEARLY\r\nSUMMARY:Smith\\, Bob\\; Jr's birthday\r\n"
+    ));
+    // No year is known, and in years without February 29th it's on the 28th
+    assert!(unfolded
+        .contains("DTSTART;VALUE=DATE:20000229\r\nRRULE:FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=-1\r\n"));
+    assert!(unfolded.contains(&format!(
+        "SUMMARY:{}'s birthday\r\n",
+        long_name.replace(',', "\\,")
+    )));
+}
@@ -0,0 +1,364 @@
+// Fuzzing of the request decoder and the session state machine. Arbitrary bytes and arbitrary
+// sequences of requests are thrown at a live server, which must never panic and must keep serving
+// other clients afterwards. Crashers found this way are kept at the bottom as regression tests.
+use proptest::prelude::*;
+use proptest::sample::Index;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use serialize::{MemoryStore, Server};
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::panic;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, Once, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+// Panics of the threads serving clients, which the server names "client N".
+static PANICS: AtomicUsize = AtomicUsize::new(0);
+static ACCOUNTS: AtomicUsize = AtomicUsize::new(0);
+
+// A fresh server for every case, so that shrinking sees each input on its own instead of the
+// poisoned locks an earlier one left behind. Cases run one at a time, since panics are only told
+// apart by when they happen.
+struct Case {
+    addr: SocketAddr,
+    panics: usize,
+    _serial: MutexGuard<'static, ()>,
+}
+
+impl Case {
+    fn start() -> Self {
+        static HOOK: Once = Once::new();
+        static SERIAL: Mutex<()> = Mutex::new(());
+        HOOK.call_once(|| {
+            let default_hook = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                let name = thread::current().name().unwrap_or("").to_owned();
+                if name.starts_with("client ") {
+                    PANICS.fetch_add(1, Ordering::SeqCst);
+                }
+                default_hook(info);
+            }));
+        });
+        let serial = SERIAL.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let server = Server::bind("127.0.0.1:0", MemoryStore::new()).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+        Case {
+            addr,
+            panics: PANICS.load(Ordering::SeqCst),
+            _serial: serial,
+        }
+    }
+
+    fn connect(&self) -> TcpStream {
+        let stream = TcpStream::connect(self.addr).unwrap();
+        stream.set_nodelay(true).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        stream
+    }
+
+    // A connection logged in to a new account of its own.
+    fn signed_up(&self) -> TcpStream {
+        let mut stream = self.connect();
+        let name = format!("fuzz{}", ACCOUNTS.fetch_add(1, Ordering::SeqCst));
+        serde_json::to_writer(&mut stream, &request("CreateAccount")).unwrap();
+        serde_json::to_writer(&mut stream, &json!({"name": name, "password": "secret"})).unwrap();
+        assert_eq!(
+            receive(&stream)["result"]["Ok"]["query"],
+            "CreateAccount",
+            "couldn't create an account, the server is broken"
+        );
+        stream
+    }
+
+    // No handler panicked, and a new client can still sign up, add a contact and list it.
+    fn assert_healthy(&self) {
+        assert_eq!(
+            PANICS.load(Ordering::SeqCst),
+            self.panics,
+            "a client handler panicked"
+        );
+        let mut stream = self.signed_up();
+        serde_json::to_writer(
+            &mut stream,
+            &json!({"result": {"Ok": {"query": "Add", "name": "Bob", "phone": 5550001}}}),
+        )
+        .unwrap();
+        assert_eq!(receive(&stream)["result"]["Ok"]["query"], "Add");
+        serde_json::to_writer(&mut stream, &request("ShowList")).unwrap();
+        assert_eq!(receive(&stream)["result"]["Ok"]["name"], "Bob");
+        assert_eq!(receive(&stream)["result"]["Ok"]["query"], "Done");
+    }
+}
+
+fn receive(stream: &TcpStream) -> Value {
+    let mut de = serde_json::Deserializer::from_reader(stream);
+    Value::deserialize(&mut de).unwrap()
+}
+
+fn request(query: &str) -> Value {
+    json!({"result": {"Ok": {"query": query}}})
+}
+
+// Sends `bytes` and hangs up the sending side, then waits for the server to close its side. A
+// server that neither answers nor closes the connection is stuck.
+fn session(mut stream: TcpStream, bytes: &[u8]) {
+    // The server may close the connection half way through malformed input
+    let _ = stream.write_all(bytes);
+    let _ = stream.shutdown(Shutdown::Write);
+    match stream.read_to_end(&mut Vec::new()) {
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
+            panic!("the server didn't close the connection")
+        }
+        _ => {}
+    }
+}
+
+const QUERIES: &[&str] = &[
+    "Save",
+    "Add",
+    "Remove",
+    "SearchByName",
+    "SearchByPhone",
+    "ShowList",
+    "Done",
+    "Login",
+    "CreateAccount",
+    "Update",
+    "Subscribe",
+    "Changed",
+    "SyncSince",
+    "Conflict",
+    "DeleteAccount",
+    "RenameAccount",
+    "ExportData",
+    "FindDuplicates",
+    "Merge",
+    "QuotaExceeded",
+    "FieldTooLong",
+    "MessageTooLarge",
+    "AccountLocked",
+    "Admin",
+    "Forbidden",
+    "Replicate",
+    "ReadOnly",
+    "UpcomingEvents",
+    "ExportCalendar",
+    "InvalidDate",
+    "NotAQuery",
+];
+
+fn text() -> impl Strategy<Value = String> {
+    prop_oneof!["[a-c]{0,2}", ".{0,16}"]
+}
+
+// Small numbers so requests often hit contacts that earlier ones added.
+fn phone() -> impl Strategy<Value = u64> {
+    prop_oneof![0..4u64, any::<u64>()]
+}
+
+// Dates both well formed and not, some of them on days that don't exist.
+fn date() -> impl Strategy<Value = String> {
+    prop_oneof![
+        "[0-9]{4}-[0-1][0-9]-[0-3][0-9]",
+        "--[0-1][0-9]-[0-3][0-9]",
+        text()
+    ]
+}
+
+fn admin_command() -> impl Strategy<Value = Value> {
+    prop_oneof![
+        Just(json!("Stats")),
+        Just(json!("Accounts")),
+        text().prop_map(|account| json!({ "Unlock": account })),
+        Just(json!(["not", "a", "command"])),
+    ]
+}
+
+// Requests with any query and any combination of fields, a few of them not even sent as Ok.
+fn request_message() -> impl Strategy<Value = Value> {
+    (
+        prop::sample::select(QUERIES),
+        prop::option::of(text()),
+        prop::option::of(phone()),
+        prop::option::of(any::<u64>()),
+        prop::option::of(text()),
+        prop::option::of(prop::collection::vec(phone(), 0..4)),
+        prop::option::of(any::<bool>()),
+        prop::option::of(admin_command()),
+        prop::option::of(date()),
+        prop::option::of(any::<u32>()),
+        prop::bool::weighted(0.9),
+    )
+        .prop_map(
+            |(query, name, phone, revision, email, phones, preview, admin, date, days, ok)| {
+                let ans = json!({
+                    "query": query,
+                    "name": name,
+                    "phone": phone,
+                    "revision": revision,
+                    "email": email,
+                    "phones": phones,
+                    "preview": preview,
+                    "admin": admin,
+                    "birthday": date,
+                    "from": date,
+                    "days": days,
+                });
+                if ok {
+                    json!({"result": {"Ok": ans}})
+                } else {
+                    json!({"result": {"Err": query}})
+                }
+            },
+        )
+}
+
+// Whatever may follow a request: credentials, as sent at login and to delete an account, or any
+// other JSON.
+fn message() -> impl Strategy<Value = Value> {
+    prop_oneof![
+        6 => request_message(),
+        2 => (text(), text()).prop_map(|(name, password)| json!({"name": name, "password": password})),
+        1 => any::<i64>().prop_map(Value::from),
+        1 => text().prop_map(Value::from),
+    ]
+}
+
+fn encode(messages: &[Value]) -> Vec<u8> {
+    messages
+        .iter()
+        .flat_map(|message| serde_json::to_vec(message).unwrap())
+        .collect()
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn arbitrary_bytes_before_login(bytes in prop::collection::vec(any::<u8>(), 0..512)) {
+        let case = Case::start();
+        session(case.connect(), &bytes);
+        case.assert_healthy();
+    }
+
+    #[test]
+    fn arbitrary_bytes_after_login(bytes in prop::collection::vec(any::<u8>(), 0..512)) {
+        let case = Case::start();
+        session(case.signed_up(), &bytes);
+        case.assert_healthy();
+    }
+
+    #[test]
+    fn requests_before_login(messages in prop::collection::vec(message(), 0..8)) {
+        let case = Case::start();
+        session(case.connect(), &encode(&messages));
+        case.assert_healthy();
+    }
+
+    #[test]
+    fn requests_after_login(messages in prop::collection::vec(message(), 0..16)) {
+        let case = Case::start();
+        session(case.signed_up(), &encode(&messages));
+        case.assert_healthy();
+    }
+
+    #[test]
+    fn truncated_requests(messages in prop::collection::vec(message(), 1..8), cut in any::<Index>()) {
+        let case = Case::start();
+        let bytes = encode(&messages);
+        session(case.signed_up(), &bytes[..cut.index(bytes.len())]);
+        case.assert_healthy();
+    }
+}
+
+// Regression tests for crashers the fuzzer found.
+
+fn answer(stream: &mut TcpStream, message: Value) -> Value {
+    serde_json::to_writer(&mut *stream, &message).unwrap();
+    receive(stream)
+}
+
+#[test]
+fn requests_missing_fields_their_query_needs() {
+    let case = Case::start();
+    let mut stream = case.signed_up();
+    for (query, ans) in [
+        ("Add", json!({"query": "Add", "name": "Bob"})),
+        ("Add", json!({"query": "Add", "phone": 1})),
+        ("Update", json!({"query": "Update", "phone": 1})),
+        ("Remove", json!({"query": "Remove"})),
+        ("SearchByName", json!({"query": "SearchByName"})),
+        ("SearchByPhone", json!({"query": "SearchByPhone"})),
+        ("RenameAccount", json!({"query": "RenameAccount"})),
+        ("Merge", json!({"query": "Merge", "phones": [1, 2]})),
+    ] {
+        let feedback = answer(&mut stream, json!({ "result": { "Ok": ans } }));
+        assert_eq!(feedback["result"]["Err"], query);
+    }
+    case.assert_healthy();
+}
+
+#[test]
+fn request_sent_as_an_error() {
+    let case = Case::start();
+    session(case.connect(), br#"{"result":{"Err":"Login"}}"#);
+    session(case.signed_up(), br#"{"result":{"Err":"ShowList"}}"#);
+    case.assert_healthy();
+}
+
+#[test]
+fn other_queries_before_login() {
+    let case = Case::start();
+    let mut stream = case.connect();
+    serde_json::to_writer(&mut stream, &request("ShowList")).unwrap();
+    let feedback = answer(&mut stream, json!({"name": "a", "password": "b"}));
+    assert_eq!(feedback["result"]["Err"], "Login");
+    case.assert_healthy();
+}
+
+#[test]
+fn client_hangs_up_without_reading_responses() {
+    let case = Case::start();
+    let mut stream = case.signed_up();
+    let add = json!({"result": {"Ok": {"query": "Add", "name": "Bob", "phone": 1}}});
+    serde_json::to_writer(&mut stream, &add).unwrap();
+    receive(&stream);
+    let requests: Vec<Value> = (0..200).map(|_| request("ShowList")).collect();
+    stream.write_all(&encode(&requests)).unwrap();
+    drop(stream);
+    // Nothing tells when the server is done writing to the closed connection
+    thread::sleep(Duration::from_millis(500));
+    case.assert_healthy();
+}
+
+#[test]
+fn refused_delete_still_reads_its_password() {
+    let case = Case::start();
+    let mut stream = case.signed_up();
+    let long = "x".repeat(1024);
+    for (query, ans) in [
+        (
+            "FieldTooLong",
+            json!({"query": "DeleteAccount", "name": long}),
+        ),
+        (
+            "InvalidDate",
+            json!({"query": "DeleteAccount", "birthday": "tomorrow"}),
+        ),
+    ] {
+        serde_json::to_writer(&mut stream, &json!({ "result": { "Ok": ans } })).unwrap();
+        let feedback = answer(&mut stream, json!({"name": "a", "password": "b"}));
+        assert_eq!(feedback["result"]["Err"], query);
+        // The password wasn't taken for the next request
+        let feedback = answer(&mut stream, request("ExportData"));
+        assert_eq!(feedback["result"]["Ok"]["query"], "ExportData");
+    }
+    case.assert_healthy();
+}
@@ -0,0 +1,162 @@
+// Limits on what a single account or request may use.
+use serde_json::{json, Value};
+use serialize::{Config, Limits, MemoryStore, Server};
+
+use std::io::Write;
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+
+const LIMITS: Limits = Limits {
+    max_contacts: 3,
+    max_field_length: 16,
+    max_message_size: 1024,
+};
+
+fn start() -> SocketAddr {
+    let config = Config {
+        limits: LIMITS,
+        log_requests: false,
+        ..Config::default()
+    };
+    let server = Server::bind_with("127.0.0.1:0", MemoryStore::new(), config).unwrap();
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.run());
+    addr
+}
+
+struct Client {
+    stream: TcpStream,
+    responses: serde_json::StreamDeserializer<'static, serde_json::de::IoRead<TcpStream>, Value>,
+}
+
+impl Client {
+    fn connect(addr: SocketAddr) -> Self {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_nodelay(true).unwrap();
+        let reader = stream.try_clone().unwrap();
+        Client {
+            stream,
+            responses: serde_json::Deserializer::from_reader(reader).into_iter(),
+        }
+    }
+
+    fn send(&mut self, message: Value) {
+        serde_json::to_writer(&mut self.stream, &message).unwrap();
+        self.stream.flush().unwrap();
+    }
+
+    fn receive(&mut self) -> Result<Value, Value> {
+        let feedback = self.responses.next().unwrap().unwrap();
+        match feedback["result"].as_object().unwrap().iter().next() {
+            Some((result, value)) if result == "Ok" => Ok(value.clone()),
+            Some((_, value)) => Err(value.clone()),
+            None => panic!("malformed feedback {}", feedback),
+        }
+    }
+
+    // Whether the server hung up.
+    fn closed(&mut self) -> bool {
+        !matches!(self.responses.next(), Some(Ok(_)))
+    }
+
+    fn request(&mut self, ans: Value) -> Result<Value, Value> {
+        self.send(json!({ "result": { "Ok": ans } }));
+        self.receive()
+    }
+
+    fn authenticate(&mut self, query: &str, name: &str, password: &str) -> Result<Value, Value> {
+        self.send(json!({"result": {"Ok": {"query": query}}}));
+        self.send(json!({"name": name, "password": password}));
+        self.receive().map(|ans| ans["query"].clone())
+    }
+
+    fn add(&mut self, name: &str, phone: u64) -> Result<Value, Value> {
+        self.request(json!({"query": "Add", "name": name, "phone": phone}))
+    }
+}
+
+fn signed_up(addr: SocketAddr, name: &str) -> Client {
+    let mut client = Client::connect(addr);
+    assert_eq!(
+        client.authenticate("CreateAccount", name, "secret"),
+        Ok(json!("CreateAccount"))
+    );
+    client
+}
+
+#[test]
+fn contacts_are_capped_per_account() {
+    let addr = start();
+    let mut alice = signed_up(addr, "alice");
+    for phone in 0..LIMITS.max_contacts as u64 {
+        assert!(alice.add("Bob", phone).is_ok());
+    }
+    assert_eq!(alice.add("Bob", 100), Err(json!("QuotaExceeded")));
+    // A number that's already there is still told apart from a full list
+    assert_eq!(alice.add("Bob", 0), Err(json!("Add")));
+    // Updates don't add anything, and removing makes room again
+    let update = json!({"query": "Update", "name": "Robert", "phone": 0});
+    assert!(alice.request(update).is_ok());
+    alice
+        .request(json!({"query": "Remove", "phone": 0}))
+        .unwrap();
+    assert!(alice.add("Bob", 100).is_ok());
+
+    // Other accounts have their own quota
+    assert!(signed_up(addr, "carol").add("Bob", 100).is_ok());
+}
+
+#[test]
+fn long_fields_are_refused() {
+    let addr = start();
+    let long = "x".repeat(LIMITS.max_field_length + 1);
+    let fits = "x".repeat(LIMITS.max_field_length);
+
+    let mut client = Client::connect(addr);
+    for (name, password) in [(long.as_str(), "secret"), ("alice", long.as_str())] {
+        assert_eq!(
+            client.authenticate("CreateAccount", name, password),
+            Err(json!("FieldTooLong"))
+        );
+    }
+    // The connection is still usable
+    assert_eq!(
+        client.authenticate("CreateAccount", &fits, &fits),
+        Ok(json!("CreateAccount"))
+    );
+
+    assert_eq!(client.add(&long, 1), Err(json!("FieldTooLong")));
+    let email = json!({"query": "Add", "name": "Bob", "phone": 1, "email": long});
+    assert_eq!(client.request(email), Err(json!("FieldTooLong")));
+    let rename = json!({"query": "RenameAccount", "name": long});
+    assert_eq!(client.request(rename), Err(json!("FieldTooLong")));
+    assert!(client.add(&fits, 1).is_ok());
+}
+
+#[test]
+fn oversized_messages_end_the_connection() {
+    let addr = start();
+    // Too large for the limit, even though every field would fit if it weren't padded out
+    let padding = " ".repeat(LIMITS.max_message_size);
+    let oversized = format!(
+        r#"{{"result": {{"Ok": {{"query": "Add",{}"name": "Bob", "phone": 1}}}}}}"#,
+        padding
+    );
+
+    let mut client = signed_up(addr, "alice");
+    client.stream.write_all(oversized.as_bytes()).unwrap();
+    assert_eq!(client.receive(), Err(json!("MessageTooLarge")));
+    assert!(client.closed());
+
+    // Before logging in too
+    let mut client = Client::connect(addr);
+    client.stream.write_all(oversized.as_bytes()).unwrap();
+    assert_eq!(client.receive(), Err(json!("MessageTooLarge")));
+    assert!(client.closed());
+
+    // Nothing from the oversized request was added, and other connections are fine
+    let mut client = Client::connect(addr);
+    client.authenticate("Login", "alice", "secret").unwrap();
+    client.send(json!({"result": {"Ok": {"query": "ShowList"}}}));
+    assert_eq!(client.receive().unwrap()["query"], "Done");
+}
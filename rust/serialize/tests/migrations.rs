@@ -0,0 +1,171 @@
+// Files written by older servers are upgraded when they're loaded, newer ones are refused.
+use serde_json::{json, Value};
+use serialize::{offline, Config, MemoryStore, Server, Store};
+
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::thread;
+
+// Lets the test look at the files a server writes.
+#[derive(Clone)]
+struct Shared(Arc<MemoryStore>);
+
+impl Store for Shared {
+    fn read(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        self.0.read(name)
+    }
+
+    fn write(&self, name: &str, contents: &[u8]) -> io::Result<()> {
+        self.0.write(name, contents)
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        self.0.remove(name)
+    }
+
+    fn list(&self, dir: &str) -> io::Result<Vec<String>> {
+        self.0.list(dir)
+    }
+}
+
+// The files as the first server wrote them, before the format had a version.
+fn version_0() -> (Value, Value) {
+    let data = json!({"clients": [{"name": "alice", "password": "secret"}]});
+    let contacts = json!({"contacts_list": {"alice": {"5": {"name": "Bob", "phone": 5}}}});
+    (data, contacts)
+}
+
+fn store_with(data: &Value, contacts: &Value) -> Shared {
+    let store = Shared(Arc::new(MemoryStore::new()));
+    for (file, contents) in [("Data.json", data), ("Contacts_list.json", contacts)] {
+        store.write(file, contents.to_string().as_bytes()).unwrap();
+    }
+    store
+}
+
+fn start(store: &Shared) -> io::Result<SocketAddr> {
+    let config = Config {
+        log_requests: false,
+        ..Config::default()
+    };
+    let server = Server::bind_with("127.0.0.1:0", store.clone(), config)?;
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.run());
+    Ok(addr)
+}
+
+fn version(store: &Shared, file: &str) -> Value {
+    let contents = store.read(file).unwrap().unwrap();
+    serde_json::from_slice::<Value>(&contents).unwrap()["version"].clone()
+}
+
+struct Client {
+    stream: TcpStream,
+    responses: serde_json::StreamDeserializer<'static, serde_json::de::IoRead<TcpStream>, Value>,
+}
+
+impl Client {
+    fn logged_in(addr: SocketAddr) -> Self {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_nodelay(true).unwrap();
+        let reader = stream.try_clone().unwrap();
+        let mut client = Client {
+            stream,
+            responses: serde_json::Deserializer::from_reader(reader).into_iter(),
+        };
+        client.send(json!({"result": {"Ok": {"query": "Login"}}}));
+        client.send(json!({"name": "alice", "password": "secret"}));
+        assert_eq!(client.receive().unwrap()["query"], "Login");
+        client
+    }
+
+    fn send(&mut self, message: Value) {
+        serde_json::to_writer(&mut self.stream, &message).unwrap();
+        self.stream.flush().unwrap();
+    }
+
+    fn receive(&mut self) -> Result<Value, Value> {
+        let feedback = self.responses.next().unwrap().unwrap();
+        match feedback["result"].as_object().unwrap().iter().next() {
+            Some((result, value)) if result == "Ok" => Ok(value.clone()),
+            Some((_, value)) => Err(value.clone()),
+            None => panic!("malformed feedback {}", feedback),
+        }
+    }
+
+    fn request(&mut self, ans: Value) -> Result<Value, Value> {
+        self.send(json!({ "result": { "Ok": ans } }));
+        self.receive()
+    }
+
+    // Every contact as a change, the way a client syncs from scratch.
+    fn sync(&mut self) -> Vec<Value> {
+        self.send(json!({"result": {"Ok": {"query": "SyncSince", "revision": 0}}}));
+        let mut changes = Vec::new();
+        loop {
+            let ans = self.receive().unwrap();
+            if ans["query"] == "Done" {
+                return changes;
+            }
+            changes.push(ans["change"].clone());
+        }
+    }
+}
+
+#[test]
+fn files_from_before_versioning_are_migrated() {
+    let (data, contacts) = version_0();
+    let store = store_with(&data, &contacts);
+    let mut client = Client::logged_in(start(&store).unwrap());
+
+    let changes = client.sync();
+    assert_eq!(changes.len(), 1);
+    let bob = &changes[0];
+    assert_eq!((&bob["name"], &bob["phone"]), (&json!("Bob"), &json!(5)));
+    assert_eq!(bob["revision"], 0);
+    assert_eq!(bob["email"], Value::Null);
+    assert_eq!(bob["birthday"], Value::Null);
+    let updated = json!({"query": "Update", "name": "Bob", "phone": 5, "birthday": "1990-05-12"});
+    assert_eq!(client.request(updated).unwrap()["revision"], 1);
+
+    // They're written in the current format on the next save
+    assert_eq!(version(&store, "Data.json"), Value::Null);
+    client.request(json!({"query": "Save"})).unwrap();
+    let current = version(&store, "Data.json");
+    assert!(current.as_u64().unwrap() > 0);
+    assert_eq!(version(&store, "Contacts_list.json"), current);
+    // Which loads as it is
+    let mut client = Client::logged_in(start(&store).unwrap());
+    assert_eq!(client.sync()[0]["birthday"], "1990-05-12");
+}
+
+#[test]
+fn snapshots_from_before_versioning_are_migrated() {
+    let (data, contacts) = version_0();
+    let store = store_with(&json!({"clients": []}), &json!({"contacts_list": {}}));
+    let snapshot = json!({"taken_at": 1, "data": data, "contacts": contacts});
+    store
+        .write("snapshots/snapshot-1.json", snapshot.to_string().as_bytes())
+        .unwrap();
+    assert_eq!(offline::restore(store.clone(), "snapshot-1", None), Ok(1));
+
+    let mut client = Client::logged_in(start(&store).unwrap());
+    assert_eq!(client.sync()[0]["name"], "Bob");
+}
+
+#[test]
+fn files_from_a_newer_server_are_refused() {
+    let (mut data, contacts) = version_0();
+    data["version"] = json!(1000);
+    let store = store_with(&data, &contacts);
+    let error = start(&store).err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error.to_string().contains("newer server"), "{}", error);
+    // Left as they were, for the newer server to read
+    assert_eq!(version(&store, "Data.json"), 1000);
+
+    let (data, mut contacts) = version_0();
+    contacts["version"] = json!("two");
+    assert!(start(&store_with(&data, &contacts)).is_err());
+}
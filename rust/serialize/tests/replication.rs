@@ -0,0 +1,272 @@
+// A primary and a standby in the same process, talking over local TCP like two servers would.
+use serde_json::{json, Value};
+use serialize::{Config, Endpoint, MemoryStore, Server, Store, Upstream};
+
+use std::io::Write;
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Standbys log in with this account, which is also used to send admin commands.
+const ADMIN: &str = "admin";
+const PASSWORD: &str = "secret";
+
+fn start_primary() -> SocketAddr {
+    let store = MemoryStore::new();
+    let data = json!({
+        "version": 2,
+        "clients": [
+            {"name": ADMIN, "password": PASSWORD, "role": "Admin", "failed_logins": 0, "locked": false}
+        ]
+    });
+    store
+        .write("Data.json", data.to_string().as_bytes())
+        .unwrap();
+    start(store, Config::default())
+}
+
+fn start_standby(primary: SocketAddr) -> SocketAddr {
+    let config = Config {
+        standby_of: Some(Upstream {
+            primary: Endpoint::Tcp(primary.to_string()),
+            account: ADMIN.to_owned(),
+            password: PASSWORD.to_owned(),
+        }),
+        ..Config::default()
+    };
+    start(MemoryStore::new(), config)
+}
+
+fn start(store: MemoryStore, config: Config) -> SocketAddr {
+    let config = Config {
+        log_requests: false,
+        ..config
+    };
+    let server = Server::bind_with("127.0.0.1:0", store, config).unwrap();
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.run());
+    addr
+}
+
+// Waits for a change to reach the standby.
+fn eventually(mut condition: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !condition() {
+        assert!(Instant::now() < deadline, "the standby never caught up");
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+struct Client {
+    stream: TcpStream,
+    responses: serde_json::StreamDeserializer<'static, serde_json::de::IoRead<TcpStream>, Value>,
+}
+
+impl Client {
+    fn connect(addr: SocketAddr) -> Self {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_nodelay(true).unwrap();
+        let reader = stream.try_clone().unwrap();
+        Client {
+            stream,
+            responses: serde_json::Deserializer::from_reader(reader).into_iter(),
+        }
+    }
+
+    fn send(&mut self, message: Value) {
+        serde_json::to_writer(&mut self.stream, &message).unwrap();
+        self.stream.flush().unwrap();
+    }
+
+    fn request(&mut self, ans: Value) -> Result<Value, Value> {
+        self.send(json!({ "result": { "Ok": ans } }));
+        self.receive()
+    }
+
+    fn receive(&mut self) -> Result<Value, Value> {
+        let feedback = self.responses.next().unwrap().unwrap();
+        match feedback["result"].as_object().unwrap().iter().next() {
+            Some((result, value)) if result == "Ok" => Ok(value.clone()),
+            Some((_, value)) => Err(value.clone()),
+            None => panic!("malformed feedback {}", feedback),
+        }
+    }
+
+    fn authenticate(&mut self, query: &str, name: &str, password: &str) -> Result<Value, Value> {
+        self.send(json!({"result": {"Ok": {"query": query}}}));
+        self.send(json!({"name": name, "password": password}));
+        self.receive().map(|ans| ans["query"].clone())
+    }
+
+    fn add(&mut self, name: &str, phone: u64) -> Result<Value, Value> {
+        self.request(json!({"query": "Add", "name": name, "phone": phone}))
+    }
+
+    fn search_by_phone(&mut self, phone: u64) -> Option<String> {
+        let ans = self
+            .request(json!({"query": "SearchByPhone", "phone": phone}))
+            .ok()?;
+        Some(ans["name"].as_str().unwrap().to_owned())
+    }
+
+    fn admin(&mut self, command: Value) -> Value {
+        let ans = self
+            .request(json!({"query": "Admin", "admin": command}))
+            .unwrap();
+        ans["admin_reply"]["Ok"].clone()
+    }
+}
+
+fn logged_in(addr: SocketAddr, name: &str) -> Option<Client> {
+    let mut client = Client::connect(addr);
+    match client.authenticate("Login", name, PASSWORD) {
+        Ok(_) => Some(client),
+        Err(_) => None,
+    }
+}
+
+fn signed_up(addr: SocketAddr, name: &str) -> Client {
+    let mut client = Client::connect(addr);
+    assert_eq!(
+        client.authenticate("CreateAccount", name, PASSWORD),
+        Ok(json!("CreateAccount"))
+    );
+    client
+}
+
+// Server stats, as seen by the admin. Logging in fails until the standby has its snapshot.
+fn stats(addr: SocketAddr) -> Option<Value> {
+    let mut admin = logged_in(addr, ADMIN)?;
+    Some(admin.admin(json!("Stats"))["Stats"].clone())
+}
+
+fn wait_for_snapshot(standby: SocketAddr, accounts: u64) {
+    eventually(|| stats(standby).is_some_and(|stats| stats["accounts"] == accounts));
+}
+
+#[test]
+fn standby_gets_a_snapshot_then_every_change() {
+    let primary = start_primary();
+    let mut alice = signed_up(primary, "alice");
+    assert!(alice.add("Bob", 5550001).is_ok());
+
+    let standby = start_standby(primary);
+    wait_for_snapshot(standby, 2);
+    assert_eq!(stats(primary).unwrap()["replicas"], 1);
+    let mut on_standby = logged_in(standby, "alice").unwrap();
+    assert_eq!(on_standby.search_by_phone(5550001).as_deref(), Some("Bob"));
+
+    assert!(alice.add("Carol", 5550002).is_ok());
+    alice
+        .request(json!({"query": "Update", "name": "Robert", "phone": 5550001}))
+        .unwrap();
+    alice
+        .request(json!({"query": "Remove", "phone": 5550002}))
+        .unwrap();
+    assert!(alice.add("Dave", 5550003).is_ok());
+    // Changes arrive in order, so once the last one is there all of them are
+    eventually(|| on_standby.search_by_phone(5550003).is_some());
+    assert_eq!(
+        on_standby.search_by_phone(5550001).as_deref(),
+        Some("Robert")
+    );
+    assert_eq!(on_standby.search_by_phone(5550002), None);
+
+    // New accounts are replicated too
+    signed_up(primary, "bob");
+    eventually(|| logged_in(standby, "bob").is_some());
+}
+
+#[test]
+fn standby_refuses_writes() {
+    let primary = start_primary();
+    let mut alice = signed_up(primary, "alice");
+    assert!(alice.add("Bob", 5550001).is_ok());
+    let standby = start_standby(primary);
+    wait_for_snapshot(standby, 2);
+
+    let mut client = Client::connect(standby);
+    assert_eq!(
+        client.authenticate("CreateAccount", "carol", PASSWORD),
+        Err(json!("ReadOnly"))
+    );
+    let mut client = logged_in(standby, "alice").unwrap();
+    assert_eq!(client.add("Carol", 5550002), Err(json!("ReadOnly")));
+    assert_eq!(
+        client.request(json!({"query": "Remove", "phone": 5550001})),
+        Err(json!("ReadOnly"))
+    );
+    assert_eq!(
+        client.request(json!({"query": "RenameAccount", "name": "alicia"})),
+        Err(json!("ReadOnly"))
+    );
+    // Reads are served from the standby's copy
+    assert_eq!(client.search_by_phone(5550001).as_deref(), Some("Bob"));
+
+    let mut admin = logged_in(standby, ADMIN).unwrap();
+    assert_eq!(
+        admin.request(json!({"query": "Admin", "admin": {"Unlock": "alice"}})),
+        Err(json!("ReadOnly"))
+    );
+}
+
+#[test]
+fn renamed_and_deleted_accounts_are_replicated() {
+    let primary = start_primary();
+    let mut alice = signed_up(primary, "alice");
+    assert!(alice.add("Bob", 5550001).is_ok());
+    signed_up(primary, "bob");
+    let standby = start_standby(primary);
+    wait_for_snapshot(standby, 3);
+
+    alice
+        .request(json!({"query": "RenameAccount", "name": "alicia"}))
+        .unwrap();
+    eventually(|| logged_in(standby, "alicia").is_some());
+    let mut alicia = logged_in(standby, "alicia").unwrap();
+    assert_eq!(alicia.search_by_phone(5550001).as_deref(), Some("Bob"));
+
+    let mut bob = logged_in(primary, "bob").unwrap();
+    bob.send(json!({"result": {"Ok": {"query": "DeleteAccount"}}}));
+    bob.send(json!({"name": "bob", "password": PASSWORD}));
+    assert!(bob.receive().is_ok());
+    eventually(|| logged_in(standby, "bob").is_none());
+}
+
+#[test]
+fn only_admins_can_replicate() {
+    let primary = start_primary();
+    let mut alice = signed_up(primary, "alice");
+    assert_eq!(
+        alice.request(json!({"query": "Replicate"})),
+        Err(json!("Forbidden"))
+    );
+}
+
+#[test]
+fn promoted_standby_takes_writes() {
+    let primary = start_primary();
+    let mut alice = signed_up(primary, "alice");
+    assert!(alice.add("Bob", 5550001).is_ok());
+    let standby = start_standby(primary);
+    wait_for_snapshot(standby, 2);
+
+    let mut admin = logged_in(standby, ADMIN).unwrap();
+    assert_eq!(admin.admin(json!("Stats"))["Stats"]["standby"], true);
+    assert!(admin.admin(json!("Promote"))["Done"].is_string());
+    assert_eq!(admin.admin(json!("Stats"))["Stats"]["standby"], false);
+    assert_eq!(
+        admin
+            .request(json!({"query": "Admin", "admin": "Promote"}))
+            .unwrap()["admin_reply"]["Err"],
+        "This server is the primary already"
+    );
+
+    let mut client = logged_in(standby, "alice").unwrap();
+    assert!(client.add("Carol", 5550002).is_ok());
+    assert_eq!(client.search_by_phone(5550001).as_deref(), Some("Bob"));
+    // It no longer follows the old primary
+    assert!(alice.add("Dave", 5550003).is_ok());
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(client.search_by_phone(5550003), None);
+}
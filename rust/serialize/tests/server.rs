@@ -0,0 +1,296 @@
+use serde_json::{json, Value};
+use serialize::{MemoryStore, Server};
+
+use std::io::Write;
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+
+// Starts a server with an empty in-memory store on a free port.
+fn start() -> SocketAddr {
+    let server = Server::bind("127.0.0.1:0", MemoryStore::new()).unwrap();
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.run());
+    addr
+}
+
+// Speaks the wire protocol with plain JSON values, the way any client would.
+struct Client {
+    stream: TcpStream,
+    responses: serde_json::StreamDeserializer<'static, serde_json::de::IoRead<TcpStream>, Value>,
+}
+
+impl Client {
+    fn connect(addr: SocketAddr) -> Self {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_nodelay(true).unwrap();
+        let reader = stream.try_clone().unwrap();
+        Client {
+            stream,
+            responses: serde_json::Deserializer::from_reader(reader).into_iter(),
+        }
+    }
+
+    fn send(&mut self, message: Value) {
+        serde_json::to_writer(&mut self.stream, &message).unwrap();
+        self.stream.flush().unwrap();
+    }
+
+    fn request(&mut self, query: &str, name: Option<&str>, phone: Option<u64>) {
+        self.send(json!({"result": {"Ok": {"query": query, "name": name, "phone": phone}}}));
+    }
+
+    fn receive(&mut self) -> Result<Value, Value> {
+        let feedback = self.responses.next().unwrap().unwrap();
+        match feedback["result"].as_object().unwrap().iter().next() {
+            Some((result, value)) if result == "Ok" => Ok(value.clone()),
+            Some((_, value)) => Err(value.clone()),
+            None => panic!("malformed feedback {}", feedback),
+        }
+    }
+
+    // Logs in or creates an account, returns the query the server answered with.
+    fn authenticate(&mut self, query: &str, name: &str, password: &str) -> Result<Value, Value> {
+        self.request(query, None, None);
+        self.send(json!({"name": name, "password": password}));
+        self.receive().map(|ans| ans["query"].clone())
+    }
+
+    fn add(&mut self, name: &str, phone: u64) -> Result<Value, Value> {
+        self.request("Add", Some(name), Some(phone));
+        self.receive()
+    }
+
+    // Contacts of the account as (name, phone), sorted by phone.
+    fn list(&mut self) -> Vec<(String, u64)> {
+        self.request("ShowList", None, None);
+        let mut list = Vec::new();
+        loop {
+            let ans = self.receive().unwrap();
+            if ans["query"] == "Done" {
+                break;
+            }
+            let name = ans["name"].as_str().unwrap().to_owned();
+            list.push((name, ans["phone"].as_u64().unwrap()));
+        }
+        list.sort_by_key(|contact| contact.1);
+        list
+    }
+}
+
+fn signed_up(addr: SocketAddr, name: &str) -> Client {
+    let mut client = Client::connect(addr);
+    assert_eq!(
+        client.authenticate("CreateAccount", name, "secret"),
+        Ok(json!("CreateAccount"))
+    );
+    client
+}
+
+#[test]
+fn create_account_then_login() {
+    let addr = start();
+    drop(signed_up(addr, "alice"));
+
+    let mut client = Client::connect(addr);
+    assert_eq!(
+        client.authenticate("Login", "alice", "secret"),
+        Ok(json!("Login"))
+    );
+}
+
+#[test]
+fn login_with_wrong_password_or_unknown_account() {
+    let addr = start();
+    drop(signed_up(addr, "alice"));
+
+    let mut client = Client::connect(addr);
+    assert_eq!(
+        client.authenticate("Login", "alice", "wrong"),
+        Err(json!("Login"))
+    );
+    assert_eq!(
+        client.authenticate("Login", "bob", "secret"),
+        Err(json!("Login"))
+    );
+    // The connection stays usable after a failed attempt
+    assert_eq!(
+        client.authenticate("Login", "alice", "secret"),
+        Ok(json!("Login"))
+    );
+}
+
+#[test]
+fn account_names_are_unique() {
+    let addr = start();
+    drop(signed_up(addr, "alice"));
+
+    let mut client = Client::connect(addr);
+    assert_eq!(
+        client.authenticate("CreateAccount", "alice", "other"),
+        Err(json!("CreateAccount"))
+    );
+}
+
+#[test]
+fn add_search_list_and_remove() {
+    let addr = start();
+    let mut client = signed_up(addr, "alice");
+
+    assert!(client.add("Bob", 5550001).is_ok());
+    assert!(client.add("Carol", 5550002).is_ok());
+    assert!(client.add("Bob", 5550003).is_ok());
+    assert_eq!(
+        client.list(),
+        vec![
+            ("Bob".to_owned(), 5550001),
+            ("Carol".to_owned(), 5550002),
+            ("Bob".to_owned(), 5550003),
+        ]
+    );
+
+    client.request("SearchByName", Some("Bob"), None);
+    let mut phones = vec![
+        client.receive().unwrap()["phone"].as_u64().unwrap(),
+        client.receive().unwrap()["phone"].as_u64().unwrap(),
+    ];
+    phones.sort_unstable();
+    assert_eq!(phones, vec![5550001, 5550003]);
+    assert_eq!(client.receive().unwrap()["query"], "Done");
+
+    client.request("SearchByName", Some("Dave"), None);
+    assert_eq!(client.receive(), Err(json!("SearchByName")));
+
+    client.request("SearchByPhone", None, Some(5550002));
+    assert_eq!(client.receive().unwrap()["name"], "Carol");
+
+    client.request("Remove", None, Some(5550002));
+    let removed = client.receive().unwrap();
+    assert_eq!(removed["query"], "Remove");
+    assert_eq!(removed["name"], "Carol");
+
+    client.request("SearchByPhone", None, Some(5550002));
+    assert_eq!(client.receive(), Err(json!("SearchByPhone")));
+    client.request("Remove", None, Some(5550002));
+    assert_eq!(client.receive(), Err(json!("Remove")));
+    assert_eq!(client.list().len(), 2);
+}
+
+#[test]
+fn phone_numbers_are_unique_per_account() {
+    let addr = start();
+    let mut alice = signed_up(addr, "alice");
+    let mut bob = signed_up(addr, "bob");
+
+    assert!(alice.add("Carol", 5550002).is_ok());
+    assert_eq!(alice.add("Someone else", 5550002), Err(json!("Add")));
+    // Other accounts have their own lists
+    assert!(bob.add("Carol", 5550002).is_ok());
+    assert!(bob.list().len() == 1 && alice.list().len() == 1);
+}
+
+#[test]
+fn duplicates_by_phone_email_and_name() {
+    let addr = start();
+    let mut client = signed_up(addr, "alice");
+    for (name, phone, email) in [
+        ("Bob", 5550001u64, None),
+        ("Robert", 3515550001, None),
+        ("Carol", 1234567, Some("carol@example.com")),
+        ("Dana", 9999999, Some("Carol@Example.com")),
+        ("Ana Silva", 2222222, None),
+        ("Silva, Ana", 3333333, None),
+        // Too short to be matched by the end of the number
+        ("Eve", 123456, None),
+        ("Zed", 10123456, None),
+    ] {
+        let ans = json!({"query": "Add", "name": name, "phone": phone, "email": email});
+        client.send(json!({"result": {"Ok": ans}}));
+        assert!(client.receive().is_ok());
+    }
+
+    client.request("FindDuplicates", None, None);
+    let clusters: Vec<Vec<u64>> = client.receive().unwrap()["clusters"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|cluster| {
+            let contacts = cluster.as_array().unwrap();
+            contacts
+                .iter()
+                .map(|c| c["phone"].as_u64().unwrap())
+                .collect()
+        })
+        .collect();
+    assert_eq!(
+        clusters,
+        vec![
+            vec![1234567, 9999999],
+            vec![2222222, 3333333],
+            vec![5550001, 3515550001],
+        ]
+    );
+}
+
+#[test]
+fn contacts_survive_logging_in_again() {
+    let addr = start();
+    let mut client = signed_up(addr, "alice");
+    assert!(client.add("Bob", 5550001).is_ok());
+    drop(client);
+
+    let mut client = Client::connect(addr);
+    assert_eq!(
+        client.authenticate("Login", "alice", "secret"),
+        Ok(json!("Login"))
+    );
+    assert_eq!(client.list(), vec![("Bob".to_owned(), 5550001)]);
+}
+
+#[test]
+fn concurrent_clients_with_their_own_accounts() {
+    let addr = start();
+    let clients: Vec<_> = (0..8)
+        .map(|i| {
+            thread::spawn(move || {
+                let mut client = signed_up(addr, &format!("user{}", i));
+                for phone in 0..25 {
+                    assert!(client.add(&format!("contact {}", phone), phone).is_ok());
+                }
+                client.list().len()
+            })
+        })
+        .collect();
+    for client in clients {
+        assert_eq!(client.join().unwrap(), 25);
+    }
+}
+
+#[test]
+fn concurrent_clients_sharing_an_account() {
+    let addr = start();
+    drop(signed_up(addr, "shared"));
+
+    let clients: Vec<_> = (0..8u64)
+        .map(|i| {
+            thread::spawn(move || {
+                let mut client = Client::connect(addr);
+                assert_eq!(
+                    client.authenticate("Login", "shared", "secret"),
+                    Ok(json!("Login"))
+                );
+                for phone in 0..25 {
+                    assert!(client.add("contact", i * 100 + phone).is_ok());
+                }
+                // Every client also tries a number another one owns, exactly one of them wins
+                let contested = client.add("contested", 9999).is_ok();
+                (client, contested)
+            })
+        })
+        .collect();
+    let results: Vec<_> = clients.into_iter().map(|c| c.join().unwrap()).collect();
+    assert_eq!(results.iter().filter(|(_, won)| *won).count(), 1);
+
+    let mut client = Client::connect(addr);
+    client.authenticate("Login", "shared", "secret").unwrap();
+    assert_eq!(client.list().len(), 8 * 25 + 1);
+}
@@ -0,0 +1,229 @@
+// Snapshots taken by an admin over the protocol, restored offline and served again.
+use serde_json::{json, Value};
+use serialize::{offline, Config, MemoryStore, Server, Store};
+
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const ADMIN: &str = "admin";
+const PASSWORD: &str = "secret";
+const KEEP_SNAPSHOTS: usize = 2;
+
+// Lets the test look at the files a server writes, and start another server on them.
+#[derive(Clone)]
+struct Shared(Arc<MemoryStore>);
+
+impl Store for Shared {
+    fn read(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        self.0.read(name)
+    }
+
+    fn write(&self, name: &str, contents: &[u8]) -> io::Result<()> {
+        self.0.write(name, contents)
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        self.0.remove(name)
+    }
+
+    fn list(&self, dir: &str) -> io::Result<Vec<String>> {
+        self.0.list(dir)
+    }
+}
+
+fn store_with_admin() -> Shared {
+    let store = Shared(Arc::new(MemoryStore::new()));
+    let data = json!({
+        "version": 2,
+        "clients": [
+            {"name": ADMIN, "password": PASSWORD, "role": "Admin", "failed_logins": 0, "locked": false}
+        ]
+    });
+    store
+        .write("Data.json", data.to_string().as_bytes())
+        .unwrap();
+    store
+}
+
+fn start(store: &Shared) -> SocketAddr {
+    let config = Config {
+        keep_snapshots: KEEP_SNAPSHOTS,
+        log_requests: false,
+        ..Config::default()
+    };
+    let server = Server::bind_with("127.0.0.1:0", store.clone(), config).unwrap();
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.run());
+    addr
+}
+
+struct Client {
+    stream: TcpStream,
+    responses: serde_json::StreamDeserializer<'static, serde_json::de::IoRead<TcpStream>, Value>,
+}
+
+impl Client {
+    fn connect(addr: SocketAddr) -> Self {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_nodelay(true).unwrap();
+        let reader = stream.try_clone().unwrap();
+        Client {
+            stream,
+            responses: serde_json::Deserializer::from_reader(reader).into_iter(),
+        }
+    }
+
+    fn send(&mut self, message: Value) {
+        serde_json::to_writer(&mut self.stream, &message).unwrap();
+        self.stream.flush().unwrap();
+    }
+
+    fn receive(&mut self) -> Result<Value, Value> {
+        let feedback = self.responses.next().unwrap().unwrap();
+        match feedback["result"].as_object().unwrap().iter().next() {
+            Some((result, value)) if result == "Ok" => Ok(value.clone()),
+            Some((_, value)) => Err(value.clone()),
+            None => panic!("malformed feedback {}", feedback),
+        }
+    }
+
+    fn request(&mut self, ans: Value) -> Result<Value, Value> {
+        self.send(json!({ "result": { "Ok": ans } }));
+        self.receive()
+    }
+
+    fn authenticate(&mut self, query: &str, name: &str) -> Result<Value, Value> {
+        self.send(json!({"result": {"Ok": {"query": query}}}));
+        self.send(json!({"name": name, "password": PASSWORD}));
+        self.receive().map(|ans| ans["query"].clone())
+    }
+
+    fn add(&mut self, name: &str, phone: u64) {
+        self.request(json!({"query": "Add", "name": name, "phone": phone}))
+            .unwrap();
+    }
+
+    fn remove(&mut self, phone: u64) {
+        self.request(json!({"query": "Remove", "phone": phone}))
+            .unwrap();
+    }
+
+    fn phones(&mut self) -> Vec<u64> {
+        self.send(json!({"result": {"Ok": {"query": "ShowList"}}}));
+        let mut phones = Vec::new();
+        loop {
+            let ans = self.receive().unwrap();
+            if ans["query"] == "Done" {
+                break;
+            }
+            phones.push(ans["phone"].as_u64().unwrap());
+        }
+        phones.sort_unstable();
+        phones
+    }
+
+    fn admin(&mut self, command: Value) -> String {
+        let ans = self
+            .request(json!({"query": "Admin", "admin": command}))
+            .unwrap();
+        ans["admin_reply"]["Ok"]["Done"]
+            .as_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    // Returns the snapshot's name.
+    fn snapshot(&mut self) -> String {
+        let reply = self.admin(json!("Snapshot"));
+        reply.strip_prefix("Took snapshot ").unwrap().to_owned()
+    }
+}
+
+fn logged_in(addr: SocketAddr, name: &str) -> Option<Client> {
+    let mut client = Client::connect(addr);
+    client.authenticate("Login", name).ok()?;
+    Some(client)
+}
+
+fn signed_up(addr: SocketAddr, name: &str) -> Client {
+    let mut client = Client::connect(addr);
+    assert_eq!(
+        client.authenticate("CreateAccount", name),
+        Ok(json!("CreateAccount"))
+    );
+    client
+}
+
+fn snapshots(store: &Shared) -> Vec<String> {
+    let mut names = store.list("snapshots").unwrap();
+    names.sort();
+    names
+}
+
+#[test]
+fn only_the_newest_snapshots_are_kept() {
+    let store = store_with_admin();
+    let mut admin = logged_in(start(&store), ADMIN).unwrap();
+    let mut taken = Vec::new();
+    for _ in 0..KEEP_SNAPSHOTS + 2 {
+        taken.push(format!("{}.json", admin.snapshot()));
+        // Snapshots are named after the millisecond they were taken in
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(snapshots(&store), taken[taken.len() - KEEP_SNAPSHOTS..]);
+}
+
+// alice and bob each have a contact when the snapshot is taken, then alice's list and bob's
+// list change and carol signs up. Returns the snapshot, with everything saved after it.
+fn changed_after_snapshot(store: &Shared) -> String {
+    let addr = start(store);
+    let mut alice = signed_up(addr, "alice");
+    let mut bob = signed_up(addr, "bob");
+    alice.add("Dave", 1);
+    bob.add("Erin", 2);
+    let mut admin = logged_in(addr, ADMIN).unwrap();
+    let snapshot = admin.snapshot();
+
+    alice.remove(1);
+    alice.add("Frank", 3);
+    bob.add("Grace", 4);
+    signed_up(addr, "carol").add("Heidi", 5);
+    admin.admin(json!("Save"));
+    snapshot
+}
+
+#[test]
+fn restoring_the_whole_server() {
+    let store = store_with_admin();
+    let snapshot = changed_after_snapshot(&store);
+    assert_eq!(offline::restore(store.clone(), &snapshot, None), Ok(3));
+
+    let addr = start(&store);
+    assert_eq!(logged_in(addr, "alice").unwrap().phones(), vec![1]);
+    assert_eq!(logged_in(addr, "bob").unwrap().phones(), vec![2]);
+    // carol signed up after the snapshot, the account is gone along with its contacts
+    assert!(logged_in(addr, "carol").is_none());
+    assert_eq!(signed_up(addr, "carol").phones(), Vec::<u64>::new());
+}
+
+#[test]
+fn restoring_one_account() {
+    let store = store_with_admin();
+    let snapshot = changed_after_snapshot(&store);
+    assert_eq!(
+        offline::restore(store.clone(), &snapshot, Some("alice")),
+        Ok(2)
+    );
+
+    let addr = start(&store);
+    assert_eq!(logged_in(addr, "alice").unwrap().phones(), vec![1]);
+    // Everyone else is left as they were
+    assert_eq!(logged_in(addr, "bob").unwrap().phones(), vec![2, 4]);
+    assert_eq!(logged_in(addr, "carol").unwrap().phones(), vec![5]);
+
+    assert!(offline::restore(store.clone(), &snapshot, Some("nobody")).is_err());
+    assert!(offline::restore(store.clone(), "snapshot-0", None).is_err());
+}
@@ -0,0 +1,267 @@
+// Change events pushed to subscribed sessions, and delta sync from a revision.
+use serde_json::{json, Value};
+use serialize::{Config, MemoryStore, Server};
+
+use std::io::Write;
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+fn start() -> SocketAddr {
+    let config = Config {
+        log_requests: false,
+        ..Config::default()
+    };
+    let server = Server::bind_with("127.0.0.1:0", MemoryStore::new(), config).unwrap();
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.run());
+    addr
+}
+
+// The changes after a revision as (kind, phone, revision), and the revision the client is now at.
+type Synced = (Vec<(String, u64, u64)>, u64);
+
+struct Client {
+    stream: TcpStream,
+    responses: serde_json::StreamDeserializer<'static, serde_json::de::IoRead<TcpStream>, Value>,
+}
+
+impl Client {
+    // Logs in, creating the account if it's not there yet.
+    fn logged_in(addr: SocketAddr, name: &str) -> Self {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_nodelay(true).unwrap();
+        // A server that stopped answering fails the test instead of hanging it
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        let reader = stream.try_clone().unwrap();
+        let mut client = Client {
+            stream,
+            responses: serde_json::Deserializer::from_reader(reader).into_iter(),
+        };
+        for query in ["Login", "CreateAccount"] {
+            client.send(json!({"result": {"Ok": {"query": query}}}));
+            client.send(json!({"name": name, "password": "secret"}));
+            if client.receive().is_ok() {
+                return client;
+            }
+        }
+        panic!("couldn't log in to {}", name);
+    }
+
+    fn send(&mut self, message: Value) {
+        serde_json::to_writer(&mut self.stream, &message).unwrap();
+        self.stream.flush().unwrap();
+    }
+
+    fn receive(&mut self) -> Result<Value, Value> {
+        let feedback = self.responses.next().unwrap().unwrap();
+        match feedback["result"].as_object().unwrap().iter().next() {
+            Some((result, value)) if result == "Ok" => Ok(value.clone()),
+            Some((_, value)) => Err(value.clone()),
+            None => panic!("malformed feedback {}", feedback),
+        }
+    }
+
+    fn request(&mut self, ans: Value) -> Result<Value, Value> {
+        self.send(json!({ "result": { "Ok": ans } }));
+        self.receive()
+    }
+
+    // Events can come in before the answer, they are skipped. Returns the account's revision.
+    fn subscribe(&mut self) -> u64 {
+        self.send(json!({"result": {"Ok": {"query": "Subscribe"}}}));
+        loop {
+            let ans = self.receive().unwrap();
+            if ans["query"] == "Subscribe" {
+                return ans["revision"].as_u64().unwrap();
+            }
+            assert_eq!(ans["query"], "Changed");
+        }
+    }
+
+    // The next pushed change as (kind, name, phone, revision).
+    fn event(&mut self) -> (String, String, u64, u64) {
+        let ans = self.receive().unwrap();
+        assert_eq!(ans["query"], "Changed");
+        let change = &ans["change"];
+        (
+            change["kind"].as_str().unwrap().to_owned(),
+            change["name"].as_str().unwrap().to_owned(),
+            change["phone"].as_u64().unwrap(),
+            ans["revision"].as_u64().unwrap(),
+        )
+    }
+
+    fn sync(&mut self, since: u64) -> Result<Synced, Value> {
+        self.send(json!({"result": {"Ok": {"query": "SyncSince", "revision": since}}}));
+        let mut changes = Vec::new();
+        loop {
+            let ans = self.receive()?;
+            if ans["query"] == "Done" {
+                return Ok((changes, ans["revision"].as_u64().unwrap()));
+            }
+            let change = &ans["change"];
+            changes.push((
+                change["kind"].as_str().unwrap().to_owned(),
+                change["phone"].as_u64().unwrap(),
+                change["revision"].as_u64().unwrap(),
+            ));
+        }
+    }
+}
+
+fn event(kind: &str, name: &str, phone: u64, revision: u64) -> (String, String, u64, u64) {
+    (kind.to_owned(), name.to_owned(), phone, revision)
+}
+
+#[test]
+fn changes_are_pushed_to_other_subscribed_sessions() {
+    let addr = start();
+    let mut watcher = Client::logged_in(addr, "alice");
+    let mut editor = Client::logged_in(addr, "alice");
+    let mut other = Client::logged_in(addr, "bob");
+    assert_eq!(watcher.subscribe(), 0);
+
+    editor
+        .request(json!({"query": "Add", "name": "Carol", "phone": 1}))
+        .unwrap();
+    assert_eq!(watcher.event(), event("Added", "Carol", 1, 1));
+    editor
+        .request(json!({"query": "Update", "name": "Carol S", "phone": 1}))
+        .unwrap();
+    assert_eq!(watcher.event(), event("Updated", "Carol S", 1, 2));
+    editor
+        .request(json!({"query": "Remove", "phone": 1}))
+        .unwrap();
+    assert_eq!(watcher.event(), event("Removed", "Carol S", 1, 3));
+
+    // Neither its own changes nor other accounts' are pushed to a session
+    watcher
+        .request(json!({"query": "Add", "name": "Dave", "phone": 2}))
+        .unwrap();
+    other
+        .request(json!({"query": "Add", "name": "Erin", "phone": 3}))
+        .unwrap();
+    editor
+        .request(json!({"query": "Add", "name": "Frank", "phone": 4}))
+        .unwrap();
+    assert_eq!(watcher.event(), event("Added", "Frank", 4, 5));
+
+    // Refused changes aren't pushed either
+    assert!(editor
+        .request(json!({"query": "Add", "name": "Again", "phone": 4}))
+        .is_err());
+    editor
+        .request(json!({"query": "Add", "name": "Grace", "phone": 5}))
+        .unwrap();
+    assert_eq!(watcher.event(), event("Added", "Grace", 5, 6));
+}
+
+#[test]
+fn subscribing_while_changes_are_published() {
+    // Subscribing and publishing both take a stream lock and the subscriptions lock, in an order
+    // that must never let them wait on each other
+    let addr = start();
+    let mut editor = Client::logged_in(addr, "alice");
+    let watchers: Vec<_> = (0..8)
+        .map(|_| {
+            thread::spawn(move || {
+                // Kept connected, so changes are published to them while the next ones subscribe
+                let mut subscribed = Vec::new();
+                for _ in 0..10 {
+                    let mut client = Client::logged_in(addr, "alice");
+                    client.subscribe();
+                    subscribed.push(client);
+                }
+            })
+        })
+        .collect();
+    for phone in 0..100 {
+        editor
+            .request(json!({"query": "Add", "name": "contact", "phone": phone}))
+            .unwrap();
+    }
+    for watcher in watchers {
+        watcher.join().unwrap();
+    }
+}
+
+fn change(kind: &str, phone: u64, revision: u64) -> (String, u64, u64) {
+    (kind.to_owned(), phone, revision)
+}
+
+#[test]
+fn sync_sends_the_changes_since_a_revision() {
+    let mut client = Client::logged_in(start(), "alice");
+    assert_eq!(client.sync(0), Ok((vec![], 0)));
+    for phone in 1..=3 {
+        client
+            .request(json!({"query": "Add", "name": "Bob", "phone": phone}))
+            .unwrap();
+    }
+    client
+        .request(json!({"query": "Remove", "phone": 1}))
+        .unwrap();
+    client
+        .request(json!({"query": "Update", "name": "Bob S", "phone": 2}))
+        .unwrap();
+
+    // From scratch only what's there now comes, as added
+    assert_eq!(
+        client.sync(0),
+        Ok((vec![change("Added", 3, 3), change("Added", 2, 5)], 5))
+    );
+    // From a revision, what changed after it in order, removals included
+    assert_eq!(
+        client.sync(2),
+        Ok((
+            vec![
+                change("Added", 3, 3),
+                change("Removed", 1, 4),
+                change("Added", 2, 5)
+            ],
+            5
+        ))
+    );
+    assert_eq!(client.sync(5), Ok((vec![], 5)));
+    // A client ahead of the server has to start over
+    assert_eq!(client.sync(6), Err(json!("SyncSince")));
+}
+
+#[test]
+fn edits_against_an_old_revision_conflict() {
+    let addr = start();
+    let mut client = Client::logged_in(addr, "alice");
+    let mut other = Client::logged_in(addr, "alice");
+    for phone in 1..=2 {
+        client
+            .request(json!({"query": "Add", "name": "Bob", "phone": phone}))
+            .unwrap();
+    }
+    let (_, base) = client.sync(0).unwrap();
+    other
+        .request(json!({"query": "Update", "name": "Bob S", "phone": 1}))
+        .unwrap();
+    other
+        .request(json!({"query": "Remove", "phone": 2}))
+        .unwrap();
+
+    // Both contacts changed after the base revision the client edits against
+    let update = json!({"query": "Update", "name": "Robert", "phone": 1, "revision": base});
+    assert_eq!(client.request(update), Err(json!("Conflict")));
+    let update_removed = json!({"query": "Update", "name": "Robert", "phone": 2, "revision": base});
+    assert_eq!(client.request(update_removed), Err(json!("Conflict")));
+    let remove = json!({"query": "Remove", "phone": 1, "revision": base});
+    assert_eq!(client.request(remove), Err(json!("Conflict")));
+
+    // After syncing again the edit goes through, and without a base it isn't checked at all
+    let (_, base) = client.sync(base).unwrap();
+    let update = json!({"query": "Update", "name": "Robert", "phone": 1, "revision": base});
+    assert_eq!(client.request(update).unwrap()["name"], "Robert");
+    let update = json!({"query": "Update", "name": "Rob", "phone": 1, "revision": 1});
+    assert_eq!(client.request(update), Err(json!("Conflict")));
+    let update = json!({"query": "Update", "name": "Rob", "phone": 1});
+    assert_eq!(client.request(update).unwrap()["name"], "Rob");
+}
@@ -0,0 +1,153 @@
+use crate::{Account, Ans, Cache, Connection, Feedback, Query};
+
+use serde::{Deserialize, Serialize};
+
+use std::sync::{Arc, Mutex};
+
+const USAGE: &str = "usage: serialize_client admin <command>
+commands: accounts | stats | logout <account> | reset-password <account> <password>
+          unlock <account> | role <account> admin|user | save | snapshot | promote";
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Role {
+    User,
+    Admin,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Command {
+    Accounts,
+    Stats,
+    Logout(String),
+    ResetPassword(String, String),
+    Unlock(String),
+    SetRole(String, Role),
+    Save,
+    Snapshot,
+    Promote,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccountInfo {
+    name: String,
+    role: Role,
+    locked: bool,
+    contacts: usize,
+    sessions: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Stats {
+    uptime: u64, // seconds
+    accounts: usize,
+    locked: usize,
+    contacts: usize,
+    sessions: usize,
+    requests: u64,
+    standby: bool,
+    replicas: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Reply {
+    Accounts(Vec<AccountInfo>),
+    Stats(Stats),
+    Done(String),
+}
+
+fn parse(args: &[&str]) -> Option<Command> {
+    Some(match args {
+        ["accounts"] => Command::Accounts,
+        ["stats"] => Command::Stats,
+        ["logout", account] => Command::Logout(account.to_string()),
+        ["reset-password", account, password] => {
+            Command::ResetPassword(account.to_string(), password.to_string())
+        }
+        ["unlock", account] => Command::Unlock(account.to_string()),
+        ["role", account, "admin"] => Command::SetRole(account.to_string(), Role::Admin),
+        ["role", account, "user"] => Command::SetRole(account.to_string(), Role::User),
+        ["save"] => Command::Save,
+        ["snapshot"] => Command::Snapshot,
+        ["promote"] => Command::Promote,
+        _ => return None,
+    })
+}
+
+fn print(reply: Result<Reply, String>) {
+    match reply {
+        Ok(Reply::Accounts(accounts)) => {
+            println!("<name> (<role>): <contacts>, <sessions>");
+            for acc in accounts {
+                println!(
+                    "{} ({:?}{}): {} contact(s), {} session(s)",
+                    acc.name,
+                    acc.role,
+                    if acc.locked { ", locked" } else { "" },
+                    acc.contacts,
+                    acc.sessions
+                );
+            }
+        }
+        Ok(Reply::Stats(stats)) => {
+            println!(
+                "role: {}",
+                if stats.standby { "standby" } else { "primary" }
+            );
+            println!("uptime: {}s", stats.uptime);
+            println!("accounts: {} ({} locked)", stats.accounts, stats.locked);
+            println!("contacts: {}", stats.contacts);
+            println!("sessions: {}", stats.sessions);
+            println!("requests served: {}", stats.requests);
+            println!("standbys: {}", stats.replicas);
+        }
+        Ok(Reply::Done(message)) => println!("{}", message),
+        Err(e) => println!("{}", e),
+    }
+}
+
+// Runs a single admin command against the server, logging in with an admin account first.
+pub fn run(args: &[&str]) {
+    let command = match parse(args) {
+        Some(_command) => _command,
+        None => {
+            eprintln!("{}", USAGE);
+            return;
+        }
+    };
+    let cache = Arc::new(Mutex::new(Cache::default()));
+    let mut connection = match Connection::open(&cache) {
+        Some(_connection) => _connection,
+        None => {
+            println!("Couldn't reach the server");
+            return;
+        }
+    };
+
+    let name = crate::get_input("admin name: ");
+    let pass = rpassword::prompt_password_stdout("password: ").unwrap();
+    connection.send(&Feedback::new_ok(Query::Login, None, None));
+    connection.send(&Account::new(&name, &pass));
+    match connection.responses.recv().map(|feedback| feedback.result) {
+        Ok(Ok(_)) => {}
+        Ok(Err(Query::AccountLocked)) => {
+            println!("{}", crate::ACCOUNT_LOCKED);
+            return;
+        }
+        _ => {
+            println!("Credentials are incorrect!");
+            return;
+        }
+    }
+
+    let request = Feedback {
+        result: Ok(Ans {
+            admin: Some(command),
+            ..Ans::new(Query::Admin)
+        }),
+    };
+    match connection.request(&request).map(|feedback| feedback.result) {
+        Some(Ok(ans)) => print(ans.admin_reply.unwrap()),
+        Some(Err(_)) => println!("\"{}\" is not an admin account", name),
+        None => println!("The server closed the connection"),
+    }
+}
@@ -0,0 +1,260 @@
+use crate::{Change, ChangeKind, Query};
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::PathBuf;
+
+const CACHE_DIR: &str = "cache";
+
+// Birthday and anniversary of a contact, as YYYY-MM-DD or --MM-DD when the year isn't known.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Dates {
+    #[serde(default)]
+    pub birthday: Option<String>,
+    #[serde(default)]
+    pub anniversary: Option<String>,
+}
+
+impl Dates {
+    // Dates sent with an edit replace the current ones, empty ones clear them and the ones not
+    // sent are kept.
+    fn apply(&mut self, edit: &Dates) {
+        let set = |date: &mut Option<String>, edit: &Option<String>| {
+            if let Some(edit) = edit {
+                *date = Some(edit.clone()).filter(|date| !date.is_empty());
+            }
+        };
+        set(&mut self.birthday, &edit.birthday);
+        set(&mut self.anniversary, &edit.anniversary);
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Contact {
+    pub name: String,
+    pub phone: u64,
+    pub revision: u64,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(flatten)]
+    pub dates: Dates,
+}
+
+// An edit made while offline, replayed against the server once it can be reached again.
+// `base` is the revision of the contact the edit was made against, so the server can tell if
+// someone else changed it in the meantime.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Edit {
+    pub query: Query,
+    pub name: Option<String>,
+    pub phone: u64,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(flatten)]
+    pub dates: Dates,
+    pub base: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Cache {
+    account: String,
+    pub revision: u64, // last revision fully synced with the server
+    contacts: HashMap<u64, Contact>,
+    removed: HashMap<u64, u64>, // <phone, revision it was removed at>
+    pending: Vec<Edit>,
+}
+
+impl Cache {
+    fn path(account: &str) -> PathBuf {
+        PathBuf::from(CACHE_DIR).join(Cache::file_name(account))
+    }
+
+    // Account names can hold anything the server accepts, separators and `..` included, so
+    // every byte other than a letter, a digit, `-` or `_` is written as `%XX`. The name can't
+    // leave the cache directory, and two accounts never share a file.
+    pub fn file_name(account: &str) -> String {
+        let mut name = String::with_capacity(account.len() + 5);
+        for byte in account.bytes() {
+            match byte {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' => name.push(byte as char),
+                _ => name.push_str(&format!("%{:02X}", byte)),
+            }
+        }
+        name.push_str(".json");
+        name
+    }
+
+    pub fn exists(account: &str) -> bool {
+        Cache::path(account).exists()
+    }
+
+    pub fn load(account: &str) -> Self {
+        match File::open(Cache::path(account)) {
+            Ok(file) => match serde_json::from_reader(BufReader::new(file)) {
+                Ok(cache) => cache,
+                Err(e) => {
+                    println!("Local cache is unreadable, starting a new one: {}", e);
+                    Cache::new(account)
+                }
+            },
+            Err(_) => Cache::new(account),
+        }
+    }
+
+    fn new(account: &str) -> Self {
+        Cache {
+            account: account.to_owned(),
+            ..Cache::default()
+        }
+    }
+
+    pub fn save(&self) {
+        if let Err(e) = fs::create_dir_all(CACHE_DIR) {
+            println!("Couldn't create the cache directory: {}", e);
+            return;
+        }
+        let serialized = serde_json::to_string(&self).unwrap();
+        if let Err(e) = fs::write(Cache::path(&self.account), serialized) {
+            println!("Couldn't save the local cache: {}", e);
+        }
+    }
+
+    pub fn rename(&mut self, new_name: &str) {
+        let _ = fs::rename(Cache::path(&self.account), Cache::path(new_name));
+        self.account = new_name.to_owned();
+    }
+
+    pub fn delete(&mut self) {
+        let _ = fs::remove_file(Cache::path(&self.account));
+        *self = Cache::new(&self.account);
+    }
+
+    // Forgets everything known about the server state, keeping only the pending edits.
+    pub fn reset(&mut self) {
+        self.revision = 0;
+        self.contacts.clear();
+        self.removed.clear();
+    }
+
+    fn known_revision(&self, phone: u64) -> u64 {
+        match self.contacts.get(&phone) {
+            Some(contact) => contact.revision,
+            None => self.removed.get(&phone).copied().unwrap_or(0),
+        }
+    }
+
+    pub fn base(&self, phone: u64) -> u64 {
+        self.known_revision(phone).max(self.revision)
+    }
+
+    // Changes can arrive both as pushed events and as sync results, in any order, so anything
+    // older than what is already known about that phone number is ignored.
+    pub fn apply(&mut self, change: &Change) {
+        if change.revision < self.known_revision(change.phone) {
+            return;
+        }
+        match change.kind {
+            ChangeKind::Added | ChangeKind::Updated => {
+                self.removed.remove(&change.phone);
+                self.contacts.insert(
+                    change.phone,
+                    Contact {
+                        name: change.name.clone(),
+                        phone: change.phone,
+                        revision: change.revision,
+                        email: change.email.clone(),
+                        dates: change.dates.clone(),
+                    },
+                );
+            }
+            ChangeKind::Removed => {
+                self.contacts.remove(&change.phone);
+                self.removed.insert(change.phone, change.revision);
+            }
+        }
+    }
+
+    // Applies an edit locally and keeps it to be replayed later. Returns false if the edit
+    // can't apply to the cached contacts, the same way the server would reject it.
+    pub fn queue(
+        &mut self,
+        query: Query,
+        name: Option<String>,
+        phone: u64,
+        email: Option<String>,
+        dates: Dates,
+    ) -> bool {
+        let base = self.base(phone);
+        match query {
+            Query::Add => {
+                if self.contacts.contains_key(&phone) {
+                    return false;
+                }
+                let mut contact = Contact {
+                    name: name.clone().unwrap(),
+                    phone,
+                    revision: base,
+                    email: email.clone().filter(|email| !email.is_empty()),
+                    dates: Dates::default(),
+                };
+                contact.dates.apply(&dates);
+                self.contacts.insert(phone, contact);
+            }
+            Query::Update => match self.contacts.get_mut(&phone) {
+                Some(contact) => {
+                    contact.name = name.clone().unwrap();
+                    if let Some(email) = &email {
+                        contact.email = Some(email.clone()).filter(|email| !email.is_empty());
+                    }
+                    contact.dates.apply(&dates);
+                }
+                None => return false,
+            },
+            Query::Remove => {
+                if self.contacts.remove(&phone).is_none() {
+                    return false;
+                }
+                self.removed.insert(phone, base);
+            }
+            _ => unreachable!(),
+        }
+        self.pending.push(Edit {
+            query,
+            name,
+            phone,
+            email,
+            dates,
+            base,
+        });
+        true
+    }
+
+    pub fn take_pending(&mut self) -> Vec<Edit> {
+        std::mem::take(&mut self.pending)
+    }
+
+    pub fn requeue(&mut self, mut edits: Vec<Edit>) {
+        edits.append(&mut self.pending);
+        self.pending = edits;
+    }
+
+    pub fn get(&self, phone: u64) -> Option<&Contact> {
+        self.contacts.get(&phone)
+    }
+
+    pub fn find_by_name(&self, name: &str) -> Vec<&Contact> {
+        self.contacts
+            .values()
+            .filter(|contact| contact.name == name)
+            .collect()
+    }
+
+    pub fn contacts(&self) -> Vec<&Contact> {
+        let mut contacts: Vec<&Contact> = self.contacts.values().collect();
+        contacts.sort_by(|a, b| a.name.cmp(&b.name).then(a.phone.cmp(&b.phone)));
+        contacts
+    }
+}
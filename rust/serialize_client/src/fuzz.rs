@@ -0,0 +1,176 @@
+// Fuzzing of the response loop: whatever the server sends, the listen thread must not panic, and
+// only well-formed responses reach the main loop. The main loop in turn must not panic on any
+// well-formed response, whatever it leaves out. Nor can any account name put its cache outside
+// the cache directory.
+use crate::{applied_change, events_of, listen, merge_preview, sync_step, Cache, Feedback};
+
+use proptest::prelude::*;
+use serde_json::{json, Value};
+
+use std::path::{Component, Path};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+
+// Runs the response loop over `bytes` and returns how many responses it handed on.
+fn responses(bytes: &[u8]) -> usize {
+    let (sender, responses) = mpsc::channel();
+    listen(bytes, sender, Arc::new(Mutex::new(Cache::default())));
+    responses.try_iter().count()
+}
+
+fn change() -> impl Strategy<Value = Value> {
+    (
+        any::<u64>(),
+        prop::sample::select(&["Added", "Updated", "Removed", "Renamed"][..]),
+        ".{0,8}",
+        0..4u64,
+        prop::option::of(".{0,8}"),
+    )
+        .prop_map(|(revision, kind, name, phone, email)| {
+            json!({"revision": revision, "kind": kind, "name": name, "phone": phone, "email": email})
+        })
+}
+
+// Responses and events the way the server sends them, with any of the fields missing.
+fn feedback() -> impl Strategy<Value = Value> {
+    (
+        prop::sample::select(&["Changed", "Add", "ShowList", "Done", "Login", "Nonsense"][..]),
+        prop::option::of(".{0,8}"),
+        prop::option::of(any::<u64>()),
+        prop::option::of(change()),
+        any::<bool>(),
+    )
+        .prop_map(|(query, name, phone, change, ok)| {
+            if ok {
+                let ans = json!({"query": query, "name": name, "phone": phone, "change": change});
+                json!({"result": {"Ok": ans}})
+            } else {
+                json!({"result": {"Err": query}})
+            }
+        })
+}
+
+// Hands the response to everything in the main loop that reads one.
+fn consume(message: &Value) {
+    let feedback = || serde_json::from_value::<Feedback>(message.clone());
+    if feedback().is_err() {
+        return;
+    }
+    if let Ok(ans) = feedback().unwrap().result {
+        applied_change(&ans);
+        merge_preview(ans);
+    }
+    sync_step(&Mutex::new(Cache::default()), feedback().unwrap().result);
+    feedback().unwrap().answer(|ans| ans.export);
+    feedback().unwrap().answer(|ans| ans.calendar);
+    feedback().unwrap().answer(|ans| ans.clusters);
+    let _ = events_of(feedback().unwrap().result);
+}
+
+// Queries of the answers the main loop reads.
+const ANSWERED: &[&str] = &[
+    "Add",
+    "Update",
+    "Remove",
+    "SyncSince",
+    "Done",
+    "Merge",
+    "ExportData",
+    "ExportCalendar",
+    "FindDuplicates",
+    "UpcomingEvents",
+];
+
+// Answers to the main loop's requests, with any of the fields missing.
+fn answer() -> impl Strategy<Value = Value> {
+    (
+        prop::sample::select(ANSWERED),
+        prop::option::of(".{0,8}"),
+        prop::option::of(0..4u64),
+        prop::option::of(any::<u64>()),
+        prop::option::of(change()),
+        prop::option::of(prop::collection::vec(0..4u64, 0..4)),
+        any::<bool>(),
+    )
+        .prop_map(|(query, name, phone, revision, change, phones, ok)| {
+            if !ok {
+                return json!({"result": {"Err": query}});
+            }
+            let contact = json!({"name": name.clone().unwrap_or_default(), "phone": 0, "revision": 0});
+            let ans = json!({
+                "query": query,
+                "name": name,
+                "phone": phone,
+                "revision": revision,
+                "change": change,
+                "phones": phones,
+                "email": name,
+                "birthday": name,
+                "calendar": name,
+                "clusters": phones.as_ref().map(|phones| vec![vec![contact; phones.len()]]),
+                "events": phones.as_ref().map(|_| json!([])),
+                "export": revision.map(|revision| {
+                    json!({"account": "alice", "exported_at": 0, "revision": revision, "contacts": []})
+                }),
+            });
+            json!({"result": {"Ok": ans}})
+        })
+}
+
+proptest! {
+    #[test]
+    fn arbitrary_bytes(bytes in prop::collection::vec(any::<u8>(), 0..512)) {
+        responses(&bytes);
+    }
+
+    #[test]
+    fn arbitrary_feedback(messages in prop::collection::vec(feedback(), 0..16), cut in any::<prop::sample::Index>()) {
+        let bytes: Vec<u8> = messages
+            .iter()
+            .flat_map(|message| serde_json::to_vec(message).unwrap())
+            .collect();
+        prop_assert!(responses(&bytes) <= messages.len());
+        responses(&bytes[..cut.index(bytes.len() + 1)]);
+    }
+
+    #[test]
+    fn arbitrary_answers(message in answer()) {
+        consume(&message);
+    }
+
+    #[test]
+    fn arbitrary_account_names(account in ".{0,16}", other in ".{0,16}") {
+        let name = Cache::file_name(&account);
+        let mut components = Path::new(&name).components();
+        prop_assert!(matches!(components.next(), Some(Component::Normal(_))));
+        prop_assert!(components.next().is_none());
+        prop_assert_eq!(name == Cache::file_name(&other), account == other);
+    }
+}
+
+// Regression tests for crashers the fuzzer found.
+
+#[test]
+fn event_without_a_change() {
+    let event = json!({"result": {"Ok": {"query": "Changed"}}});
+    assert_eq!(responses(event.to_string().as_bytes()), 0);
+}
+
+#[test]
+fn answers_without_their_fields() {
+    for query in [
+        "Add",
+        "SyncSince",
+        "Done",
+        "Merge",
+        "ExportData",
+        "UpcomingEvents",
+    ] {
+        let ans = json!({"result": {"Ok": {"query": query}}});
+        let feedback: Feedback = serde_json::from_value(ans.clone()).unwrap();
+        assert!(applied_change(feedback.result.as_ref().unwrap()).is_none());
+        let feedback: Feedback = serde_json::from_value(ans.clone()).unwrap();
+        assert!(sync_step(&Mutex::new(Cache::default()), feedback.result).is_none());
+        consume(&ans);
+    }
+}
@@ -1,15 +1,38 @@
 extern crate rpassword;
 
 use serde::{Deserialize, Serialize};
-use serde_json;
 
+mod admin;
+mod cache;
+#[cfg(test)]
+mod fuzz;
+mod net;
 mod simple_user_input;
+use cache::{Cache, Contact, Dates, Edit};
+use net::Stream;
 use simple_user_input::get_input;
 
-use std::io::BufReader;
-use std::net::TcpStream;
+use std::fs;
+use std::io::{BufReader, Read};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
 
-#[derive(PartialEq, Debug, Serialize, Deserialize)]
+const SERVER_ADDRESS: &str = "3.17.149.107:54321";
+const CONFLICT: &str =
+    "The contact was changed by another session in the meantime, check it and try again";
+const QUOTA_EXCEEDED: &str = "This account has reached its limit of contacts";
+const FIELD_TOO_LONG: &str = "The name or email is longer than the server allows";
+const ACCOUNT_LOCKED: &str =
+    "The account is locked after too many failed logins, ask an administrator to unlock it";
+const READ_ONLY: &str =
+    "The server is a read-only standby, changes can only be made on the primary";
+const INVALID_DATE: &str = "Dates are written as YYYY-MM-DD, or --MM-DD when the year isn't known";
+const UNEXPECTED: &str = "The server sent an answer this client doesn't understand";
+// Reminders shown after logging in cover this many days.
+const REMINDER_DAYS: u32 = 7;
+
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
 enum Query {
     Save,
     Add,
@@ -20,6 +43,61 @@ enum Query {
     Done,
     Login,
     CreateAccount,
+    Update,
+    Subscribe,
+    Changed,
+    SyncSince,
+    Conflict,
+    DeleteAccount,
+    RenameAccount,
+    ExportData,
+    FindDuplicates,
+    Merge,
+    QuotaExceeded,
+    FieldTooLong,
+    MessageTooLarge,
+    AccountLocked,
+    Admin,
+    Forbidden,
+    Replicate,
+    ReadOnly,
+    UpcomingEvents,
+    ExportCalendar,
+    InvalidDate,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+enum ChangeKind {
+    Added,
+    Updated,
+    Removed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Change {
+    revision: u64,
+    kind: ChangeKind,
+    name: String,
+    phone: u64,
+    #[serde(default)]
+    email: Option<String>,
+    #[serde(flatten)]
+    dates: Dates,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+enum EventKind {
+    Birthday,
+    Anniversary,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Event {
+    kind: EventKind,
+    name: String,
+    phone: u64,
+    date: String,
+    years: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -27,6 +105,46 @@ struct Ans {
     query: Query,
     name: Option<String>,
     phone: Option<u64>,
+    revision: Option<u64>,
+    change: Option<Change>,
+    export: Option<Export>,
+    email: Option<String>,
+    phones: Option<Vec<u64>>,
+    preview: Option<bool>,
+    clusters: Option<Vec<Vec<Contact>>>,
+    admin: Option<admin::Command>,
+    admin_reply: Option<Result<admin::Reply, String>>,
+    birthday: Option<String>,
+    anniversary: Option<String>,
+    from: Option<String>,
+    days: Option<u32>,
+    events: Option<Vec<Event>>,
+    calendar: Option<String>,
+}
+
+impl Ans {
+    fn new(query: Query) -> Self {
+        Ans {
+            query,
+            name: None,
+            phone: None,
+            revision: None,
+            change: None,
+            export: None,
+            email: None,
+            phones: None,
+            preview: None,
+            clusters: None,
+            admin: None,
+            admin_reply: None,
+            birthday: None,
+            anniversary: None,
+            from: None,
+            days: None,
+            events: None,
+            calendar: None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -37,9 +155,69 @@ struct Feedback {
 impl Feedback {
     fn new_ok(query: Query, name: Option<String>, phone: Option<u64>) -> Self {
         Feedback {
-            result: Ok(Ans { query, name, phone }),
+            result: Ok(Ans {
+                name,
+                phone,
+                ..Ans::new(query)
+            }),
+        }
+    }
+
+    fn new_revision(query: Query, revision: u64) -> Self {
+        Feedback {
+            result: Ok(Ans {
+                revision: Some(revision),
+                ..Ans::new(query)
+            }),
         }
     }
+
+    fn new_edit(edit: &Edit) -> Self {
+        Feedback {
+            result: Ok(Ans {
+                name: edit.name.clone(),
+                phone: Some(edit.phone),
+                revision: Some(edit.base),
+                email: edit.email.clone(),
+                birthday: edit.dates.birthday.clone(),
+                anniversary: edit.dates.anniversary.clone(),
+                ..Ans::new(edit.query)
+            }),
+        }
+    }
+
+    fn new_merge(phones: &[u64], keep: u64, name: &str, email: &str, preview: bool) -> Self {
+        Feedback {
+            result: Ok(Ans {
+                name: Some(name.to_owned()),
+                phone: Some(keep),
+                email: Some(email.to_owned()),
+                phones: Some(phones.to_vec()),
+                preview: Some(preview),
+                ..Ans::new(Query::Merge)
+            }),
+        }
+    }
+
+    // What the server answered with, None if it refused the request or left it out.
+    fn answer<T>(self, field: impl FnOnce(Ans) -> Option<T>) -> Option<T> {
+        self.result.ok().and_then(field)
+    }
+
+    fn is_event(&self) -> bool {
+        match &self.result {
+            Ok(ans) => ans.query == Query::Changed,
+            Err(_) => false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Export {
+    account: String,
+    exported_at: u64,
+    revision: u64,
+    contacts: Vec<Contact>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -57,218 +235,880 @@ impl Account {
     }
 }
 
-fn login(mut stream: &mut TcpStream, mut reader: &mut BufReader<TcpStream>) -> bool {
+struct Connection {
+    stream: Stream,
+    responses: Receiver<Feedback>,
+}
+
+impl Connection {
+    fn open(cache: &Arc<Mutex<Cache>>) -> Option<Self> {
+        let stream = Stream::connect_to_server(SERVER_ADDRESS).ok()?;
+        let reader = BufReader::new(stream.try_clone().ok()?);
+        let (sender, responses) = mpsc::channel();
+        let cache = Arc::clone(cache);
+        thread::spawn(move || listen(reader, sender, cache));
+
+        Some(Connection { stream, responses })
+    }
+
+    fn send<T: Serialize>(&mut self, value: &T) -> bool {
+        serde_json::to_writer(&mut self.stream, value).is_ok()
+    }
+
+    fn request(&mut self, feedback: &Feedback) -> Option<Feedback> {
+        if !self.send(feedback) {
+            return None;
+        }
+        self.receive()
+    }
+
+    fn receive(&self) -> Option<Feedback> {
+        let feedback = self.responses.recv().ok()?;
+        if let Err(Query::Login) = feedback.result {
+            println!(
+                "\nThe account was renamed or deleted by another session, please log in again"
+            );
+            std::process::exit(1);
+        }
+        Some(feedback)
+    }
+}
+
+// Reads everything the server sends. Change events pushed by the server are applied to the cache
+// and shown as soon as they arrive, anything else is a response to the last request and is
+// handed to the main loop.
+fn listen<R: Read>(mut reader: R, responses: Sender<Feedback>, cache: Arc<Mutex<Cache>>) {
+    loop {
+        let mut de = serde_json::Deserializer::from_reader(&mut reader);
+        let feedback = match Feedback::deserialize(&mut de) {
+            Ok(_feedback) => _feedback,
+            Err(_) => break,
+        };
+        if feedback.is_event() {
+            // An event without a change is malformed and has nothing to apply
+            if let Ok(Ans {
+                change: Some(change),
+                ..
+            }) = &feedback.result
+            {
+                cache.lock().unwrap().apply(change);
+                print_change(change);
+            }
+        } else if responses.send(feedback).is_err() {
+            break;
+        }
+    }
+}
+
+fn print_change(change: &Change) {
+    let what = match change.kind {
+        ChangeKind::Added => "added",
+        ChangeKind::Updated => "updated",
+        ChangeKind::Removed => "removed",
+    };
+    println!(
+        "\n[revision {}] Contact \"{}\" ({}) was {} from another session",
+        change.revision, change.name, change.phone, what
+    );
+}
+
+// The answer to a login or sign up. There is nothing to do offline before logging in, so a lost
+// connection ends the client.
+fn login_response(connection: &Connection) -> Result<Ans, Query> {
+    match connection.responses.recv() {
+        Ok(feedback) => feedback.result,
+        Err(_) => {
+            println!("Lost connection to the server");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn login(connection: &mut Connection) -> Option<String> {
     loop {
         let name = get_input("name: ");
         let pass = rpassword::prompt_password_stdout("password: ").unwrap();
 
-        serde_json::to_writer(&mut stream, &Feedback::new_ok(Query::Login, None, None)).unwrap();
-        serde_json::to_writer(&mut stream, &Account::new(&name, &pass)).unwrap();
+        connection.send(&Feedback::new_ok(Query::Login, None, None));
+        connection.send(&Account::new(&name, &pass));
 
-        let mut de = serde_json::Deserializer::from_reader(&mut reader);
-        if let Ok(_) = Feedback::deserialize(&mut de).unwrap().result {
-            println!("Logged in successfully");
-            break true;
-        } else {
-            println!("Credentials are incorrect!");
-            println!("0 - Exit\n1 - Try to login again");
-            match get_input("──> ").as_str() {
-                "0" => break false,
-                "1" => continue,
-                _ => unreachable!(),
+        match login_response(connection) {
+            Ok(_) => {
+                println!("Logged in successfully");
+                break Some(name);
             }
+            Err(Query::AccountLocked) => println!("{}", ACCOUNT_LOCKED),
+            Err(_) => println!("Credentials are incorrect!"),
+        }
+        println!("0 - Exit\n1 - Try to login again");
+        match get_input("──> ").as_str() {
+            "0" => break None,
+            "1" => continue,
+            _ => unreachable!(),
         }
     }
 }
 
-fn register(mut stream: &mut TcpStream, mut reader: &mut BufReader<TcpStream>) -> bool {
+fn register(connection: &mut Connection) -> Option<String> {
     loop {
         let name = get_input("name: ");
         let pass = rpassword::prompt_password_stdout("password: ").unwrap();
 
-        serde_json::to_writer(
-            &mut stream,
-            &Feedback::new_ok(Query::CreateAccount, None, None),
-        )
-        .unwrap();
-        serde_json::to_writer(&mut stream, &Account::new(&name, &pass)).unwrap();
+        connection.send(&Feedback::new_ok(Query::CreateAccount, None, None));
+        connection.send(&Account::new(&name, &pass));
 
-        let mut de = serde_json::Deserializer::from_reader(&mut reader);
-        if let Ok(_) = Feedback::deserialize(&mut de).unwrap().result {
-            println!("Account created successfully");
-            break true;
-        } else {
-            println!("Account with name {} already exists!", name);
-            println!("0 - Exit\n1 - Try to register again");
-            match get_input("──> ").as_str() {
-                "0" => break false,
-                "1" => continue,
-                _ => unreachable!(),
+        match login_response(connection) {
+            Ok(_) => {
+                println!("Account created successfully");
+                break Some(name);
             }
+            Err(Query::FieldTooLong) => {
+                println!("The account name or password is longer than the server allows")
+            }
+            Err(Query::ReadOnly) => println!("{}", READ_ONLY),
+            Err(_) => println!("Account with name {} already exists!", name),
+        }
+        println!("0 - Exit\n1 - Try to register again");
+        match get_input("──> ").as_str() {
+            "0" => break None,
+            "1" => continue,
+            _ => unreachable!(),
         }
     }
 }
 
-fn main() {
-    let mut stream = TcpStream::connect("3.17.149.107:54321").unwrap();
-    let reader = stream.try_clone().unwrap();
-    let mut reader = BufReader::new(reader);
-
+fn authenticate(connection: &mut Connection) -> Option<String> {
     loop {
-        println!("");
+        println!();
         println!("0 - Exit\n1 - Login\n2 - Create account",);
 
         let input = get_input("──> ");
-        println!("");
-        match input.as_str() {
-            "0" => return,
-            "1" => {
-                if login(&mut stream, &mut reader) {
+        println!();
+        let account = match input.as_str() {
+            "0" => return None,
+            "1" => login(connection),
+            "2" => register(connection),
+            _ => {
+                println!("Option not supported!");
+                continue;
+            }
+        };
+        if account.is_some() {
+            return account;
+        }
+    }
+}
+
+fn disconnect(connection: &mut Option<Connection>) {
+    if connection.take().is_some() {
+        println!("Lost connection to the server, continuing offline");
+    }
+}
+
+// The change an accepted edit made, as reported back by the server. None if the answer isn't
+// one to an edit.
+fn applied_change(ans: &Ans) -> Option<Change> {
+    let kind = match ans.query {
+        Query::Add => ChangeKind::Added,
+        Query::Update => ChangeKind::Updated,
+        Query::Remove => ChangeKind::Removed,
+        _ => return None,
+    };
+    Some(Change {
+        revision: ans.revision?,
+        kind,
+        name: ans.name.clone().unwrap_or_default(),
+        phone: ans.phone?,
+        email: ans.email.clone(),
+        dates: Dates {
+            birthday: ans.birthday.clone(),
+            anniversary: ans.anniversary.clone(),
+        },
+    })
+}
+
+// Brings the cache up to date with the server: subscribes to live changes, replays the edits
+// queued while offline and then fetches everything that changed since the last sync.
+fn synchronize(connection: &mut Connection, cache: &Arc<Mutex<Cache>>) -> bool {
+    if connection
+        .request(&Feedback::new_ok(Query::Subscribe, None, None))
+        .is_none()
+    {
+        return false;
+    }
+
+    let mut pending = cache.lock().unwrap().take_pending();
+    if !pending.is_empty() {
+        println!("Sending {} change(s) made while offline", pending.len());
+    }
+    while !pending.is_empty() {
+        let edit = pending.remove(0);
+        let feedback = match connection.request(&Feedback::new_edit(&edit)) {
+            Some(_feedback) => _feedback,
+            None => {
+                pending.insert(0, edit);
+                cache.lock().unwrap().requeue(pending);
+                return false;
+            }
+        };
+        match feedback.result {
+            Ok(ans) => match applied_change(&ans) {
+                Some(change) => cache.lock().unwrap().apply(&change),
+                // The server took it, the sync below brings in what it made of it
+                None => println!("{}", UNEXPECTED),
+            },
+            // Kept for when the server takes writes again
+            Err(Query::ReadOnly) => {
+                println!("{}", READ_ONLY);
+                pending.insert(0, edit);
+                cache.lock().unwrap().requeue(pending);
+                break;
+            }
+            Err(Query::Conflict) => println!(
+                "Contact with phone number {} was changed by another session while you were offline, your {:?} was discarded",
+                edit.phone, edit.query
+            ),
+            Err(_) => println!(
+                "Your {:?} of contact with phone number {} no longer applies and was discarded",
+                edit.query, edit.phone
+            ),
+        }
+    }
+
+    sync(connection, cache)
+}
+
+enum SyncStep {
+    Changed,
+    Done,
+    // The server is behind the cache and the sync has to start over
+    Behind,
+}
+
+// Applies one answer to SyncSince to the cache, None if it isn't one.
+fn sync_step(cache: &Mutex<Cache>, result: Result<Ans, Query>) -> Option<SyncStep> {
+    match result {
+        Ok(ans) => match (ans.query, ans.change, ans.revision) {
+            (Query::SyncSince, Some(change), _) => {
+                cache.lock().unwrap().apply(&change);
+                Some(SyncStep::Changed)
+            }
+            (Query::Done, _, Some(revision)) => {
+                cache.lock().unwrap().revision = revision;
+                Some(SyncStep::Done)
+            }
+            _ => None,
+        },
+        Err(_) => {
+            cache.lock().unwrap().reset();
+            Some(SyncStep::Behind)
+        }
+    }
+}
+
+// Returns false if the connection is lost or the server's answers make no sense, either way
+// it shouldn't be used anymore.
+fn sync(connection: &mut Connection, cache: &Arc<Mutex<Cache>>) -> bool {
+    loop {
+        let since = cache.lock().unwrap().revision;
+        if !connection.send(&Feedback::new_revision(Query::SyncSince, since)) {
+            return false;
+        }
+        loop {
+            let feedback = match connection.receive() {
+                Some(_feedback) => _feedback,
+                None => return false,
+            };
+            match sync_step(cache, feedback.result) {
+                Some(SyncStep::Changed) => {}
+                Some(SyncStep::Done) => return true,
+                Some(SyncStep::Behind) => {
+                    println!("The server is behind the local cache, reloading all contacts");
                     break;
                 }
+                None => {
+                    println!("{}", UNEXPECTED);
+                    return false;
+                }
             }
+        }
+    }
+}
+
+// Sends an edit to the server, or queues it in the cache to be replayed later if the server
+// can't be reached.
+fn edit(
+    connection: &mut Option<Connection>,
+    cache: &Arc<Mutex<Cache>>,
+    query: Query,
+    name: Option<String>,
+    phone: u64,
+    email: Option<String>,
+    dates: Dates,
+) -> Result<(), Query> {
+    if let Some(conn) = connection.as_mut() {
+        let base = cache.lock().unwrap().base(phone);
+        let edit = Edit {
+            query,
+            name: name.clone(),
+            phone,
+            email: email.clone(),
+            dates: dates.clone(),
+            base,
+        };
+        match conn.request(&Feedback::new_edit(&edit)) {
+            Some(feedback) => {
+                let ans = feedback.result?;
+                match applied_change(&ans) {
+                    Some(change) => cache.lock().unwrap().apply(&change),
+                    // The edit was made, the cache catches up with it from the server instead
+                    None => {
+                        println!("{}", UNEXPECTED);
+                        if !sync(conn, cache) {
+                            disconnect(connection);
+                        }
+                    }
+                }
+                return Ok(());
+            }
+            None => disconnect(connection),
+        }
+    }
+
+    if cache
+        .lock()
+        .unwrap()
+        .queue(query, name, phone, email, dates)
+    {
+        println!("(offline) The change will be sent once the server can be reached");
+        Ok(())
+    } else {
+        Err(query)
+    }
+}
+
+fn rename_account(connection: &mut Connection, cache: &Arc<Mutex<Cache>>, account: &mut String) {
+    let new_name = get_input("new account name: ");
+    match connection.request(&Feedback::new_ok(
+        Query::RenameAccount,
+        Some(new_name.clone()),
+        None,
+    )) {
+        Some(feedback) if feedback.result.is_ok() => {
+            cache.lock().unwrap().rename(&new_name);
+            println!("Account \"{}\" is now called \"{}\"", account, new_name);
+            *account = new_name;
+        }
+        Some(Feedback {
+            result: Err(Query::FieldTooLong),
+        }) => println!("The account name is longer than the server allows"),
+        Some(Feedback {
+            result: Err(Query::ReadOnly),
+        }) => println!("{}", READ_ONLY),
+        Some(_) => println!("Account with name {} already exists!", new_name),
+        None => println!("Lost connection to the server, the account was not renamed"),
+    }
+}
+
+fn delete_account(connection: &mut Connection, cache: &Arc<Mutex<Cache>>, account: &str) -> bool {
+    println!("This deletes the account and all of its contacts for good");
+    let pass = rpassword::prompt_password_stdout("confirm password: ").unwrap();
+
+    connection.send(&Feedback::new_ok(Query::DeleteAccount, None, None));
+    connection.send(&Account::new(account, &pass));
+    match connection.receive() {
+        Some(feedback) if feedback.result.is_ok() => {
+            cache.lock().unwrap().delete();
+            println!("Account \"{}\" was deleted", account);
+            true
+        }
+        Some(Feedback {
+            result: Err(Query::ReadOnly),
+        }) => {
+            println!("{}", READ_ONLY);
+            false
+        }
+        Some(_) => {
+            println!("Password is incorrect!");
+            false
+        }
+        None => {
+            println!("Lost connection to the server, the account was not deleted");
+            false
+        }
+    }
+}
+
+fn export_data(connection: &mut Connection, account: &str) {
+    let export = match connection.request(&Feedback::new_ok(Query::ExportData, None, None)) {
+        Some(feedback) => match feedback.answer(|ans| ans.export) {
+            Some(_export) => _export,
+            None => {
+                println!("The server didn't send the export");
+                return;
+            }
+        },
+        None => {
+            println!("Lost connection to the server, nothing was exported");
+            return;
+        }
+    };
+    let path = format!("{}-export.json", account);
+    match fs::write(&path, serde_json::to_string_pretty(&export).unwrap()) {
+        Ok(_) => println!("Exported {} contact(s) to {}", export.contacts.len(), path),
+        Err(e) => println!("Couldn't write {}: {}", path, e),
+    }
+}
+
+fn export_calendar(connection: &mut Connection, account: &str) {
+    let calendar = match connection.request(&Feedback::new_ok(Query::ExportCalendar, None, None)) {
+        Some(feedback) => match feedback.answer(|ans| ans.calendar) {
+            Some(_calendar) => _calendar,
+            None => {
+                println!("The server didn't send the calendar");
+                return;
+            }
+        },
+        None => {
+            println!("Lost connection to the server, nothing was exported");
+            return;
+        }
+    };
+    let path = format!("{}.ics", account);
+    match fs::write(&path, &calendar) {
+        Ok(_) => println!(
+            "Exported {} birthday(s) and anniversary(ies) to {}",
+            calendar.matches("BEGIN:VEVENT").count(),
+            path
+        ),
+        Err(e) => println!("Couldn't write {}: {}", path, e),
+    }
+}
+
+// Birthdays and anniversaries in the next `days` days, from today on the server.
+fn upcoming_events(connection: &mut Connection, days: u32) -> Option<Result<Vec<Event>, Query>> {
+    let feedback = connection.request(&Feedback {
+        result: Ok(Ans {
+            days: Some(days),
+            ..Ans::new(Query::UpcomingEvents)
+        }),
+    })?;
+    Some(events_of(feedback.result))
+}
+
+fn events_of(result: Result<Ans, Query>) -> Result<Vec<Event>, Query> {
+    match result? {
+        Ans {
+            events: Some(events),
+            ..
+        } => Ok(events),
+        ans => Err(ans.query),
+    }
+}
+
+fn print_event(event: &Event) {
+    let what = match (event.kind, event.years) {
+        (EventKind::Birthday, Some(years)) => format!("turns {}", years),
+        (EventKind::Birthday, None) => "birthday".to_owned(),
+        (EventKind::Anniversary, Some(years)) => format!("{} year anniversary", years),
+        (EventKind::Anniversary, None) => "anniversary".to_owned(),
+    };
+    println!("{}: {} ({}) {}", event.date, event.name, event.phone, what);
+}
+
+// Shown after logging in, nothing is printed when there's nothing coming up.
+fn show_reminders(connection: &mut Connection) {
+    if let Some(Ok(events)) = upcoming_events(connection, REMINDER_DAYS) {
+        if !events.is_empty() {
+            println!("\nComing up in the next {} days:", REMINDER_DAYS);
+            for event in &events {
+                print_event(event);
+            }
+        }
+    }
+}
+
+fn print_contact(contact: &Contact) {
+    match &contact.email {
+        Some(email) => print!("{}: {} <{}>", contact.name, contact.phone, email),
+        None => print!("{}: {}", contact.name, contact.phone),
+    }
+    if let Some(birthday) = &contact.dates.birthday {
+        print!(", birthday {}", birthday);
+    }
+    if let Some(anniversary) = &contact.dates.anniversary {
+        print!(", anniversary {}", anniversary);
+    }
+    println!();
+}
+
+// Reads the email to keep for an edited contact: empty keeps the current one, "-" clears it.
+fn read_email(prompt: &str) -> Option<String> {
+    match get_input(prompt).as_str() {
+        "" => None,
+        "-" => Some(String::new()),
+        email => Some(email.to_owned()),
+    }
+}
+
+// Reads a birthday or anniversary for an edited contact, the same way as emails.
+fn read_date(prompt: &str) -> Option<String> {
+    match get_input(prompt).as_str() {
+        "" => None,
+        "-" => Some(String::new()),
+        date => Some(date.to_owned()),
+    }
+}
+
+// Lists the clusters of contacts that look like the same person and lets the user merge one of
+// them, showing the merged contact before anything is changed on the server.
+fn find_duplicates(connection: &mut Connection, cache: &Arc<Mutex<Cache>>) {
+    let clusters = match connection.request(&Feedback::new_ok(Query::FindDuplicates, None, None)) {
+        Some(feedback) => match feedback.answer(|ans| ans.clusters) {
+            Some(_clusters) => _clusters,
+            None => {
+                println!("{}", UNEXPECTED);
+                return;
+            }
+        },
+        None => {
+            println!("Lost connection to the server");
+            return;
+        }
+    };
+    if clusters.is_empty() {
+        println!("No likely duplicates found");
+        return;
+    }
+    for (i, cluster) in clusters.iter().enumerate() {
+        println!("{} -", i + 1);
+        for contact in cluster {
+            print!("    ");
+            print_contact(contact);
+        }
+    }
+
+    let cluster = match get_input("cluster to merge (0 to go back): ").parse::<usize>() {
+        Ok(i) if i >= 1 && i <= clusters.len() => &clusters[i - 1],
+        _ => return,
+    };
+    let phones: Vec<u64> = cluster.iter().map(|contact| contact.phone).collect();
+    let kept = loop {
+        let phone = read_phone();
+        match cluster.iter().find(|contact| contact.phone == phone) {
+            Some(contact) => break contact,
+            None => println!("Phone number {} is not in this cluster", phone),
+        }
+    };
+    let name = match get_input(&format!("name (empty keeps \"{}\"): ", kept.name)).as_str() {
+        "" => kept.name.clone(),
+        name => name.to_owned(),
+    };
+    let email = match read_email("email (empty keeps the current one, - for none): ") {
+        Some(email) => email,
+        None => kept
+            .email
+            .clone()
+            .or_else(|| cluster.iter().find_map(|contact| contact.email.clone()))
+            .unwrap_or_default(),
+    };
+
+    for preview in [true, false] {
+        let feedback = connection.request(&Feedback::new_merge(
+            &phones, kept.phone, &name, &email, preview,
+        ));
+        let ans = match feedback.map(|feedback| feedback.result) {
+            Some(Ok(_ans)) => _ans,
+            Some(Err(_)) => {
+                println!("The contacts changed in the meantime, nothing was merged");
+                return;
+            }
+            None => {
+                println!("Lost connection to the server, nothing was merged");
+                return;
+            }
+        };
+        if preview {
+            let (merged, removed) = match merge_preview(ans) {
+                Some(preview) => preview,
+                None => {
+                    println!("{}, nothing was merged", UNEXPECTED);
+                    return;
+                }
+            };
+            println!("The merged contact will be:");
+            print!("    ");
+            print_contact(&merged);
+            println!("and removed: {:?}", removed);
+            if get_input("merge? (y/n): ") != "y" {
+                return;
+            }
+        }
+    }
+    sync(connection, cache);
+    println!("Contacts merged!");
+}
+
+// The contact a merge would result in and the phone numbers it would remove.
+fn merge_preview(ans: Ans) -> Option<(Contact, Vec<u64>)> {
+    let merged = Contact {
+        name: ans.name?,
+        phone: ans.phone?,
+        revision: 0,
+        email: ans.email,
+        dates: Dates {
+            birthday: ans.birthday,
+            anniversary: ans.anniversary,
+        },
+    };
+    Some((merged, ans.phones?))
+}
+
+// Returns true if the account was deleted and the session is over.
+fn account_menu(
+    connection: &mut Option<Connection>,
+    cache: &Arc<Mutex<Cache>>,
+    account: &mut String,
+) -> bool {
+    let conn = match connection.as_mut() {
+        Some(_conn) => _conn,
+        None => {
+            println!("Account settings need a connection to the server");
+            return false;
+        }
+    };
+    loop {
+        println!(
+            "0 - Back\n1 - Rename account\n2 - Delete account\n3 - Export my data\n4 - Export calendar (.ics)"
+        );
+        let option = get_input("──> ");
+        println!();
+        match option.as_str() {
+            "0" => return false,
+            "1" => rename_account(conn, cache, account),
             "2" => {
-                if register(&mut stream, &mut reader) {
-                    break;
+                if delete_account(conn, cache, account) {
+                    return true;
                 }
             }
-            _ => {
-                println!("Option not supported!");
-                continue;
+            "3" => export_data(conn, account),
+            "4" => export_calendar(conn, account),
+            _ => continue,
+        }
+    }
+}
+
+fn read_phone() -> u64 {
+    loop {
+        match get_input("phone: ").parse() {
+            Ok(_phone) => break _phone,
+            Err(_) => continue,
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    if let ["admin", command @ ..] = args.as_slice() {
+        admin::run(command);
+        return;
+    }
+
+    let cache = Arc::new(Mutex::new(Cache::default()));
+    let mut connection = Connection::open(&cache);
+
+    let mut account = match connection.as_mut() {
+        Some(conn) => match authenticate(conn) {
+            Some(_account) => _account,
+            None => return,
+        },
+        None => {
+            println!("Couldn't reach the server, working offline from the local cache");
+            let name = get_input("name: ");
+            if !Cache::exists(&name) {
+                println!("There is no local cache for account \"{}\"", name);
+                return;
             }
+            name
         }
+    };
+    *cache.lock().unwrap() = Cache::load(&account);
+
+    let synced = match connection.as_mut() {
+        Some(conn) => synchronize(conn, &cache),
+        None => true,
+    };
+    if !synced {
+        disconnect(&mut connection);
+    }
+    if let Some(conn) = connection.as_mut() {
+        show_reminders(conn);
     }
 
     loop {
-        println!("");
+        println!();
         println!(
-            "0 - Exit\n1 - Add contact\n2 - Remove contact\n3 - Search contact\n4 - Show contacts"
+            "0 - Exit\n1 - Add contact\n2 - Remove contact\n3 - Search contact\n4 - Show contacts\n5 - Edit contact\n6 - Account settings\n7 - Find duplicates\n8 - Upcoming birthdays and anniversaries"
         );
         let input = get_input("──> ");
-        println!("");
+        println!();
         match input.as_str() {
             "0" => {
-                serde_json::to_writer(&mut stream, &Feedback::new_ok(Query::Save, None, None))
-                    .unwrap();
-
-                let mut de = serde_json::Deserializer::from_reader(&mut reader);
-                let ans: Ans = Feedback::deserialize(&mut de).unwrap().result.ok().unwrap();
-
-                if ans.query == Query::Done {
-                    break;
-                } else {
-                    unreachable!()
+                if let Some(conn) = connection.as_mut() {
+                    match conn.request(&Feedback::new_ok(Query::Save, None, None)) {
+                        Some(feedback) => {
+                            if feedback.answer(|ans| Some(ans.query)) != Some(Query::Done) {
+                                println!("The server couldn't save the contacts");
+                            }
+                            sync(conn, &cache);
+                        }
+                        None => disconnect(&mut connection),
+                    }
                 }
+                cache.lock().unwrap().save();
+                break;
             }
             "1" => {
                 let name = get_input("name: ");
-                let phone: u64 = loop {
-                    match get_input("phone: ").parse() {
-                        Ok(_phone) => break _phone,
-                        Err(_) => continue,
-                    }
+                let phone = read_phone();
+                let email = get_input("email (optional): ");
+                let dates = Dates {
+                    birthday: Some(get_input(
+                        "birthday (optional, YYYY-MM-DD or --MM-DD without the year): ",
+                    )),
+                    anniversary: Some(get_input("anniversary (optional): ")),
                 };
-                serde_json::to_writer(
-                    &mut stream,
-                    &Feedback::new_ok(Query::Add, Some(name.clone()), Some(phone)),
-                )
-                .unwrap();
-
-                let mut de = serde_json::Deserializer::from_reader(&mut reader);
-                let feedback = Feedback::deserialize(&mut de).unwrap();
-                if let Ok(_) = feedback.result {
-                    println!("Added contact!");
-                } else {
-                    println!("There is already a contact with name \"{}\"", name);
+                match edit(
+                    &mut connection,
+                    &cache,
+                    Query::Add,
+                    Some(name),
+                    phone,
+                    Some(email),
+                    dates,
+                ) {
+                    Ok(_) => println!("Added contact!"),
+                    Err(Query::Conflict) => println!("{}", CONFLICT),
+                    Err(Query::QuotaExceeded) => println!("{}", QUOTA_EXCEEDED),
+                    Err(Query::FieldTooLong) => println!("{}", FIELD_TOO_LONG),
+                    Err(Query::InvalidDate) => println!("{}", INVALID_DATE),
+                    Err(Query::ReadOnly) => println!("{}", READ_ONLY),
+                    Err(_) => println!("There is already a contact with phone number {}", phone),
                 }
             }
             "2" => {
-                let phone: u64 = loop {
-                    match get_input("phone: ").parse() {
-                        Ok(_phone) => break _phone,
-                        Err(_) => continue,
-                    }
-                };
-                serde_json::to_writer(
-                    &mut stream,
-                    &Feedback::new_ok(Query::Remove, None, Some(phone)),
-                )
-                .unwrap();
-
-                let mut de = serde_json::Deserializer::from_reader(&mut reader);
-                let feedback = Feedback::deserialize(&mut de).unwrap();
-                if let Ok(_) = feedback.result {
-                    println!(
+                let phone = read_phone();
+                match edit(
+                    &mut connection,
+                    &cache,
+                    Query::Remove,
+                    None,
+                    phone,
+                    None,
+                    Dates::default(),
+                ) {
+                    Ok(_) => println!(
                         "Contact with phone number {} was removed successfully!",
                         phone
-                    );
-                } else {
-                    println!("Didn't find contact with phone number \"{}\"", phone);
+                    ),
+                    Err(Query::Conflict) => println!("{}", CONFLICT),
+                    Err(Query::ReadOnly) => println!("{}", READ_ONLY),
+                    Err(_) => println!("Didn't find contact with phone number \"{}\"", phone),
                 }
             }
             "3" => loop {
                 println!("0 - Back\n1 - Search by name\n2 - Search by number");
                 let search_option = get_input("──> ");
-                println!("");
+                println!();
                 match search_option.as_str() {
                     "0" => break,
                     "1" => {
                         let name = get_input("name: ");
-                        serde_json::to_writer(
-                            &mut stream,
-                            &Feedback::new_ok(Query::SearchByName, Some(name.clone()), None),
-                        )
-                        .unwrap();
-
-                        let mut de = serde_json::Deserializer::from_reader(&mut reader);
-                        let feedback = Feedback::deserialize(&mut de).unwrap();
-                        if let Ok(ans) = feedback.result {
-                            println!("Found contact with phone number {}", ans.phone.unwrap());
-                        } else {
+                        let cache = cache.lock().unwrap();
+                        let found = cache.find_by_name(&name);
+                        if found.is_empty() {
                             println!("Didn't find any contact with the name \"{}\"", name);
                         }
+                        for contact in found {
+                            println!("Found contact with phone number {}", contact.phone);
+                        }
                     }
                     "2" => {
-                        let phone: u64 = loop {
-                            match get_input("phone: ").parse() {
-                                Ok(_phone) => break _phone,
-                                Err(_) => continue,
+                        let phone = read_phone();
+                        match cache.lock().unwrap().get(phone) {
+                            Some(contact) => {
+                                println!("Found contact with name \"{}\"", contact.name)
                             }
-                        };
-                        serde_json::to_writer(
-                            &mut stream,
-                            &Feedback::new_ok(Query::SearchByPhone, None, Some(phone)),
-                        )
-                        .unwrap();
-
-                        let mut de = serde_json::Deserializer::from_reader(&mut reader);
-                        let feedback = Feedback::deserialize(&mut de).unwrap();
-                        if let Ok(ans) = feedback.result {
-                            println!("Found contact with name \"{}\"", ans.name.unwrap());
-                        } else {
-                            println!("Didn't find contact with phone number {}", phone);
+                            None => println!("Didn't find contact with phone number {}", phone),
                         }
                     }
                     _ => continue,
                 }
             },
             "4" => {
-                serde_json::to_writer(&mut stream, &Feedback::new_ok(Query::ShowList, None, None))
-                    .unwrap();
-
-                println!("<name>: <phone number>");
-                loop {
-                    let mut de = serde_json::Deserializer::from_reader(&mut reader);
-                    let ans: Ans = Feedback::deserialize(&mut de).unwrap().result.ok().unwrap();
-                    match ans.query {
-                        Query::ShowList => {
-                            println!("{}: {}", ans.name.unwrap(), ans.phone.unwrap())
+                println!("<name>: <phone number> <email>");
+                for contact in cache.lock().unwrap().contacts() {
+                    print_contact(contact);
+                }
+            }
+            "5" => {
+                let phone = read_phone();
+                let name = get_input("new name: ");
+                let email = read_email("new email (empty keeps the current one, - for none): ");
+                let dates = Dates {
+                    birthday: read_date("new birthday (empty keeps the current one, - for none): "),
+                    anniversary: read_date(
+                        "new anniversary (empty keeps the current one, - for none): ",
+                    ),
+                };
+                match edit(
+                    &mut connection,
+                    &cache,
+                    Query::Update,
+                    Some(name),
+                    phone,
+                    email,
+                    dates,
+                ) {
+                    Ok(_) => println!("Contact with phone number {} was updated!", phone),
+                    Err(Query::Conflict) => println!("{}", CONFLICT),
+                    Err(Query::FieldTooLong) => println!("{}", FIELD_TOO_LONG),
+                    Err(Query::InvalidDate) => println!("{}", INVALID_DATE),
+                    Err(Query::ReadOnly) => println!("{}", READ_ONLY),
+                    Err(_) => println!("Didn't find contact with phone number \"{}\"", phone),
+                }
+            }
+            "6" => {
+                if account_menu(&mut connection, &cache, &mut account) {
+                    break;
+                }
+            }
+            "7" => match connection.as_mut() {
+                Some(conn) => find_duplicates(conn, &cache),
+                None => println!("Finding duplicates needs a connection to the server"),
+            },
+            "8" => match connection.as_mut() {
+                Some(conn) => {
+                    let days = match get_input("days ahead (empty for 30): ").as_str() {
+                        "" => 30,
+                        days => days.parse().unwrap_or(0),
+                    };
+                    match upcoming_events(conn, days) {
+                        Some(Ok(events)) if events.is_empty() => {
+                            println!("No birthdays or anniversaries in the next {} days", days)
                         }
-                        Query::Done => break,
-                        _ => unreachable!(),
+                        Some(Ok(events)) => {
+                            for event in &events {
+                                print_event(event);
+                            }
+                        }
+                        Some(Err(_)) => println!("The number of days must be between 1 and 366"),
+                        None => println!("Lost connection to the server"),
                     }
                 }
-            }
+                None => println!("Upcoming events need a connection to the server"),
+            },
             _ => unreachable!(),
         }
     }
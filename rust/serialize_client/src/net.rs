@@ -0,0 +1,74 @@
+// Connections to the server over TCP, or a Unix domain socket when the server runs on the same
+// machine.
+use std::io::{self, prelude::*};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+// Set to `tcp://HOST:PORT`, `unix://PATH` or a plain `HOST:PORT` to use another server.
+const SERVER_VAR: &str = "SERIALIZE_SERVER";
+
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    pub fn connect(address: &str) -> io::Result<Self> {
+        match address.split_once("://") {
+            Some(("tcp", addr)) => Ok(Stream::Tcp(TcpStream::connect(addr)?)),
+            #[cfg(unix)]
+            Some(("unix", path)) => Ok(Stream::Unix(UnixStream::connect(path)?)),
+            Some((scheme, _)) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("can't connect to {}:// addresses", scheme),
+            )),
+            None => Ok(Stream::Tcp(TcpStream::connect(address)?)),
+        }
+    }
+
+    // The server set in the environment, or the default one.
+    pub fn connect_to_server(default: &str) -> io::Result<Self> {
+        match std::env::var(SERVER_VAR) {
+            Ok(address) => Stream::connect(&address),
+            Err(_) => Stream::connect(default),
+        }
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            Stream::Tcp(stream) => Stream::Tcp(stream.try_clone()?),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Stream::Unix(stream.try_clone()?),
+        })
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
@@ -1,89 +1,239 @@
 use crate::simple_user_input::*;
+use so_proj_protocol::{LockMode, Request, Whence};
 
-pub fn create_file() -> String {
+use std::fs::File;
+
+// A local file to upload, open for reading, and where it goes.
+pub struct LocalUpload {
+    pub local_path: String,
+    pub local_file: File,
+    pub name: String,
+    pub owner_permission: Permission,
+    pub others_permission: Permission,
+}
+
+pub fn create_file() -> Request {
     let name = read_filename_input("Filename ─> ");
     let content = read_file_content_input("File content ─> ");
     let owner_permission = read_permission_input("Owner permission [ R | W | RW | N ] ─> ");
     let others_permission = read_permission_input("Others permission [ R | W | RW | N ] ─> ");
 
-    format!(
-        "{menu}|{option}|{name}|{content}|{owner_permission:?}|{others_permission:?}\n",
-        menu = "c",
-        option = "c",
-        name = name,
-        content = content,
-        owner_permission = owner_permission,
-        others_permission = others_permission
-    )
+    Request::Create {
+        name,
+        content: content.into_bytes(),
+        owner_permission,
+        others_permission,
+    }
 }
 
-pub fn delete_file() -> String {
+pub fn delete_file() -> Request {
     let name = read_filename_input("Filename -> ");
 
-    format!(
-        "{menu}|{option}|{name}\n",
-        menu = "c",
-        option = "d",
-        name = name
-    )
+    Request::Delete { name }
 }
 
-pub fn rename_file() -> String {
+pub fn rename_file() -> Request {
     let name = read_filename_input("Filename ─> ");
     let new_name = read_filename_input("New filename ─> ");
 
-    format!(
-        "{menu}|{option}|{name}|{new_name}\n",
-        menu = "c",
-        option = "r",
-        name = name,
-        new_name = new_name
-    )
+    Request::Rename { name, new_name }
+}
+
+// Only the owner of a file can change its permissions.
+pub fn change_permissions() -> Request {
+    let name = read_filename_input("Filename ─> ");
+    let owner_permission = read_permission_input("Owner permission [ R | W | RW | N ] ─> ");
+    let others_permission = read_permission_input("Others permission [ R | W | RW | N ] ─> ");
+
+    Request::ChangePermissions {
+        name,
+        owner_permission,
+        others_permission,
+    }
 }
 
-pub fn open_file() -> String {
+pub fn open_file() -> Request {
     let name = read_filename_input("Filename ─> ");
     let open_permission = read_open_permission_input("Open permission [ R | W | RW ] ─> ");
+    // Appending only matters for writes
+    let append = match open_permission {
+        Permission::Write | Permission::ReadWrite => matches!(
+            get_input("Append to the end on every write? [ y | n ] ─> ").as_str(),
+            "y" | "Y"
+        ),
+        _ => false,
+    };
 
-    format!(
-        "{menu}|{option}|{name}|{open_permission:?}\n",
-        menu = "c",
-        option = "o",
-        name = name,
-        open_permission = open_permission
-    )
+    Request::Open {
+        name,
+        permission: open_permission,
+        append,
+    }
 }
 
-pub fn close_file() -> String {
+pub fn close_file() -> Request {
     let fd = read_unsigned_number_input("File descriptor ─> ");
 
-    format!("{menu}|{option}|{fd}", menu = "c", option = "x", fd = fd)
+    Request::Close { fd }
 }
 
-pub fn read_file() -> String {
+pub fn read_file() -> Request {
     let fd = read_file_descriptor_input("File descriptor ─> ");
     let len_to_read = read_unsigned_number_input("Length to read ─> ");
 
-    format!(
-        "{menu}|{option}|{fd}|{len_to_read}\n",
-        menu = "c",
-        option = "l",
-        fd = fd,
-        len_to_read = len_to_read
-    )
+    Request::Read {
+        fd,
+        len: len_to_read,
+    }
 }
 
-pub fn write_to_file() -> String {
+pub fn write_to_file() -> Request {
     let fd = read_file_descriptor_input("File descriptor ─> ");
     let content = read_file_content_input("File content ─> ");
     let len_to_write = read_unsigned_number_input("Length to write ─> ");
 
-    format!(
-        "{menu}|{option}|{fd}|{content}|{len_to_write}\n",
-        menu = "c",
-        option = "w",
-        fd = fd,
-        content = content,
-        len_to_write = len_to_write
-    )
+    Request::Write {
+        fd,
+        content: content.into_bytes(),
+        len: len_to_write,
+    }
+}
+
+pub fn make_directory() -> Request {
+    let name = read_filename_input("Directory ─> ");
+    let owner_permission = read_permission_input("Owner permission [ R | W | RW | N ] ─> ");
+    let others_permission = read_permission_input("Others permission [ R | W | RW | N ] ─> ");
+
+    Request::MakeDirectory {
+        name,
+        owner_permission,
+        others_permission,
+    }
+}
+
+pub fn remove_directory() -> Request {
+    let name = read_filename_input("Directory ─> ");
+
+    Request::RemoveDirectory { name }
+}
+
+pub fn list_directory() -> Request {
+    let name = read_filename_input("Directory (empty for the current one) ─> ");
+
+    Request::ListDirectory {
+        name: Some(name).filter(|name| !name.is_empty()),
+    }
+}
+
+pub fn stat_file() -> Request {
+    let name = read_filename_input("File or directory ─> ");
+
+    Request::Stat { name }
+}
+
+pub fn list_files() -> Request {
+    let name = read_filename_input("Directory (empty for the current one) ─> ");
+    let pattern = get_input("Only names like, e.g. *.txt (empty for all) ─> ");
+
+    Request::List {
+        name: Some(name).filter(|name| !name.is_empty()),
+        pattern: Some(pattern).filter(|pattern| !pattern.is_empty()),
+    }
+}
+
+pub fn change_directory() -> Request {
+    let name = read_filename_input("Directory ─> ");
+
+    Request::ChangeDirectory { name }
+}
+
+pub fn print_working_directory() -> Request {
+    Request::WorkingDirectory
+}
+
+pub fn seek_file() -> Request {
+    let fd = read_file_descriptor_input("File descriptor ─> ");
+    let offset = read_offset_input("Offset (can be negative) ─> ");
+    let whence = loop {
+        match get_input("From [ S (start) | C (current) | E (end) ] ─> ").as_str() {
+            "S" | "s" => break Whence::Set,
+            "C" | "c" => break Whence::Current,
+            "E" | "e" => break Whence::End,
+            _ => {
+                println!("Please input one of the given options!");
+                continue;
+            }
+        }
+    };
+
+    Request::Seek { fd, offset, whence }
+}
+
+// Others can read a file while a shared lock is held on it, but not write it. No one else can
+// touch it while an exclusive lock is held.
+pub fn lock_file() -> Request {
+    let fd = read_file_descriptor_input("File descriptor ─> ");
+    let mode = loop {
+        match get_input("Lock [ S (shared) | E (exclusive) ] ─> ").as_str() {
+            "S" | "s" => break LockMode::Shared,
+            "E" | "e" => break LockMode::Exclusive,
+            _ => {
+                println!("Please input one of the given options!");
+                continue;
+            }
+        }
+    };
+    let timeout = read_unsigned_number_input("Wait for it how many ms (0 not to wait) ─> ");
+
+    Request::Lock {
+        fd,
+        mode,
+        timeout: Some(timeout as u64).filter(|timeout| *timeout > 0),
+    }
+}
+
+pub fn unlock_file() -> Request {
+    let fd = read_file_descriptor_input("File descriptor ─> ");
+
+    Request::Unlock { fd }
+}
+
+fn read_offset_input(form: &str) -> i64 {
+    loop {
+        match get_input(form).parse::<i64>() {
+            Ok(offset) => return offset,
+            Err(_) => println!("You need to type a number!"),
+        }
+    }
+}
+
+// None if the local file can't be opened.
+pub fn upload_file() -> Option<LocalUpload> {
+    let local_path = get_input("Local file ─> ");
+    let local_file = match File::open(&local_path) {
+        Ok(_local_file) => _local_file,
+        Err(e) => {
+            println!("Couldn't read {}: {}", local_path, e);
+            return None;
+        }
+    };
+    let name = read_filename_input("Filename ─> ");
+    let owner_permission = read_permission_input("Owner permission [ R | W | RW | N ] ─> ");
+    let others_permission = read_permission_input("Others permission [ R | W | RW | N ] ─> ");
+
+    Some(LocalUpload {
+        local_path,
+        local_file,
+        name,
+        owner_permission,
+        others_permission,
+    })
+}
+
+// The file to download and where to save it.
+pub fn download_file() -> (String, String) {
+    let name = read_filename_input("Filename ─> ");
+    let local_path = get_input("Save as ─> ");
+
+    (name, local_path)
 }
@@ -0,0 +1,96 @@
+use so_proj_protocol::{FileStat, Permission};
+
+// Like ls -l: read and write for the owner, then for everyone else.
+fn permission_flags(owner_permission: Permission, others_permission: Permission) -> String {
+    let flags = |permission: Permission| match permission {
+        Permission::None => "--",
+        Permission::Write => "-w",
+        Permission::Read => "r-",
+        Permission::ReadWrite => "rw",
+    };
+    format!("{} {}", flags(owner_permission), flags(others_permission))
+}
+
+// 1970-01-01 and on, from days since then (http://howardhinnant.github.io/date_algorithms.html).
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+// In UTC, down to the minute.
+fn format_time(seconds: u64) -> String {
+    let seconds = seconds as i64;
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+    let minutes = seconds.rem_euclid(86_400) / 60;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+fn row(stat: &FileStat) -> Vec<String> {
+    let optional_time = |time: Option<u64>| time.map_or("-".to_string(), format_time);
+    vec![
+        if stat.is_directory {
+            format!("{}/", stat.name)
+        } else {
+            stat.name.clone()
+        },
+        if stat.is_directory {
+            "-".to_string()
+        } else {
+            stat.size.to_string()
+        },
+        stat.owner.clone(),
+        permission_flags(stat.owner_permission, stat.others_permission),
+        format_time(stat.created),
+        optional_time(stat.modified),
+        optional_time(stat.accessed),
+        stat.open_count.to_string(),
+    ]
+}
+
+pub fn print_file_table(stats: &[FileStat]) {
+    if stats.is_empty() {
+        println!("(nothing)");
+        return;
+    }
+    let header: Vec<String> = [
+        "Name", "Size", "Owner", "Perms", "Created", "Modified", "Accessed", "Open",
+    ]
+    .iter()
+    .map(|column| column.to_string())
+    .collect();
+    let rows: Vec<Vec<String>> = stats.iter().map(row).collect();
+
+    let widths: Vec<usize> = (0..header.len())
+        .map(|column| {
+            rows.iter()
+                .chain(std::iter::once(&header))
+                .map(|row| row[column].chars().count())
+                .max()
+                .unwrap()
+        })
+        .collect();
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+}
@@ -1,36 +1,19 @@
-mod simple_user_input;
 mod client_input_handling;
+mod file_table;
+mod simple_user_input;
 
 extern crate rpassword;
-use num_enum::TryFromPrimitive;
 
 use client_input_handling::*;
+use file_table::print_file_table;
 use simple_user_input::get_input;
-use std::convert::TryFrom;
-use std::io::prelude::*;
-use std::io::{BufReader, BufWriter};
-use std::os::unix::net::UnixStream;
+use so_proj_protocol::{receive, send, Request, Response, SystemResult, MAX_CHUNK_LEN};
+use std::fs::File;
+use std::io::{Read, Write};
 use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
 use std::{thread, time};
 
-#[derive(Eq, PartialEq, TryFromPrimitive)]
-#[repr(i32)]
-enum SystemResult {
-    Ok = 0,
-    FileAlreadyExists = -4,
-    FileDoesntExist = -5,
-    FileNotOpen = -8,
-    FileAlreadyOpen = -9,
-    OpenInInvalidMode = -10,
-    PermissionDenied = -6,
-    ReachedMaxOpenFiles = -7,
-    MiscellaneousError = -11,
-    ClientAlreadyExists = -12,
-    ClientDoesntExist = -13,
-    WrongCredentials = -14,
-    ClosedConnection = -30,
-}
-
 struct Menu {
     label: &'static str,
     options_input: Vec<&'static str>,
@@ -72,16 +55,32 @@ fn create_main_menu() -> Menu {
 fn create_client_menu() -> Menu {
     Menu::new(
         "Client Menu",
-        vec!["e", "c", "d", "r", "o", "x", "l", "w"],
+        vec![
+            "e", "c", "d", "r", "m", "o", "x", "l", "w", "j", "k", "q", "s", "g", "p", "u", "n",
+            "y", "z", "i", "t",
+        ],
         vec![
             "...Exit (Logout)",
             "Create file",
             "Delete file",
             "Rename file",
+            "Change file permissions",
             "Open file",
             "Close file",
             "Read file",
             "Write to file",
+            "Seek in file",
+            "Make directory",
+            "Remove directory",
+            "List directory",
+            "Change directory",
+            "Print working directory",
+            "Upload a local file",
+            "Download to a local file",
+            "Lock file",
+            "Unlock file",
+            "File info",
+            "List files in a table",
         ],
     )
 }
@@ -100,7 +99,15 @@ fn print_result(result: SystemResult) {
         SystemResult::ClientAlreadyExists => "Client already exists! Try another name!",
         SystemResult::ClientDoesntExist => "Client doesn't exist! Maybe you got the name wrong!?",
         SystemResult::WrongCredentials => "Wrong credentials!",
-        SystemResult::ClosedConnection => "Connection closed, idkw!?",
+        SystemResult::NotADirectory => "Not a directory!",
+        SystemResult::IsADirectory => "That's a directory!",
+        SystemResult::DirectoryNotEmpty => "Directory isn't empty!",
+        SystemResult::InvalidOffset => "Invalid offset!",
+        SystemResult::AlreadyLoggedIn => "Already logged in!",
+        SystemResult::FileLocked => "File is locked!",
+        SystemResult::Deadlock => "Waiting for that lock would deadlock!",
+        SystemResult::FileTooLarge => "File would be too large!",
+        SystemResult::NotSaved => "Done, but the server couldn't save it!",
     };
 
     println!("{}", report);
@@ -110,18 +117,15 @@ fn register_client(stream: &mut UnixStream) {
     let new_name = get_input("New name ─> ");
     let new_password = match rpassword::read_password_from_tty(Some("New password ─> ")) {
         Ok(_goes_into_password) => _goes_into_password,
-        Err(e) => panic!(e),
+        Err(e) => panic!("{}", e),
     };
 
-    write_str(
+    send_request(
         stream,
-        &format!(
-            "{menu}|{option}|{name}|{password}\n",
-            menu = "m",
-            option = "r",
-            name = new_name,
-            password = new_password
-        ),
+        &Request::Register {
+            name: new_name,
+            password: new_password,
+        },
     )
 }
 
@@ -129,52 +133,136 @@ fn login_client(stream: &mut UnixStream) {
     let name = get_input("Name ─> ");
     let password = match rpassword::read_password_from_tty(Some("Password ─> ")) {
         Ok(_goes_into_password) => _goes_into_password,
-        Err(e) => panic!(e),
+        Err(e) => panic!("{}", e),
     };
-    write_str(
-        stream,
-        &format!(
-            "{menu}|{option}|{name}|{password}\n",
-            menu = "m",
-            option = "l",
-            name = name,
-            password = password
-        ),
-    )
+    send_request(stream, &Request::Login { name, password })
 }
 
-fn write_str(stream: &mut UnixStream, to_write: &str) {
-    match stream.write_all(to_write.as_bytes()) {
+fn send_request(stream: &mut UnixStream, request: &Request) {
+    match send(stream, request) {
         Ok(_) => { /* Successful write to socket! */ }
-        Err(e) => panic!(e),
+        Err(e) => panic!("{}", e),
     }
 }
 
-fn get_result(line: String) -> SystemResult {
-    let num: i32 = match line.trim().parse() {
-        Ok(_goes_into_num) => _goes_into_num,
-        Err(e) => panic!("Wasn't able to parse string to i32!: {}", e),
-    };
-    match SystemResult::try_from(num) {
-        Ok(_fs_result_is_returned) => _fs_result_is_returned,
-        Err(e) => panic!("SystemResult not compatible!: {}", e),
+// None once the server is gone.
+fn receive_response(stream: &mut UnixStream) -> Option<Response> {
+    receive(stream).ok()
+}
+
+// Sends the local file a piece at a time, so it never has to fit in a message or in memory.
+// False once the server is gone.
+fn upload(stream: &mut UnixStream, upload: LocalUpload) -> bool {
+    let LocalUpload {
+        local_path,
+        mut local_file,
+        name,
+        owner_permission,
+        others_permission,
+    } = upload;
+    let mut offset = 0;
+    // A piece the server couldn't save is still uploaded, the rest can follow it
+    let mut saved = true;
+    loop {
+        let mut content = Vec::new();
+        if let Err(e) = (&mut local_file)
+            .take(MAX_CHUNK_LEN as u64)
+            .read_to_end(&mut content)
+        {
+            println!("Couldn't read {}: {}", local_path, e);
+            return true;
+        }
+        let len = content.len();
+        send_request(
+            stream,
+            &Request::Upload {
+                name: name.clone(),
+                offset,
+                content,
+                owner_permission,
+                others_permission,
+            },
+        );
+        match receive_response(stream) {
+            Some(Response::Result(result @ (SystemResult::Ok | SystemResult::NotSaved))) => {
+                saved &= result == SystemResult::Ok;
+                // A full piece, there can be more after it
+                if len == MAX_CHUNK_LEN {
+                    offset += len;
+                    continue;
+                }
+                println!("Uploaded {} byte(s)", offset + len);
+                if !saved {
+                    print_result(SystemResult::NotSaved);
+                }
+                return true;
+            }
+            Some(Response::Result(result)) => {
+                print_result(result);
+                return true;
+            }
+            Some(_) => {
+                print_result(SystemResult::MiscellaneousError);
+                return true;
+            }
+            None => return false,
+        }
     }
 }
 
-fn receive_result(stream: UnixStream) -> SystemResult {
-    let buf_stream = BufReader::new(stream);
-    for line in buf_stream.lines() {
-        return get_result(line.unwrap());
+// Saves the file a piece at a time as it comes in. False once the server is gone.
+fn download(stream: &mut UnixStream, name: String, local_path: &str) -> bool {
+    // Only created once the server sends something, a refused download leaves nothing behind
+    let mut local_file: Option<File> = None;
+    let mut offset = 0;
+    loop {
+        send_request(
+            stream,
+            &Request::Download {
+                name: name.clone(),
+                offset,
+            },
+        );
+        let content = match receive_response(stream) {
+            Some(Response::Data(content)) => content,
+            Some(Response::Result(result)) => {
+                print_result(result);
+                return true;
+            }
+            Some(_) => {
+                print_result(SystemResult::MiscellaneousError);
+                return true;
+            }
+            None => return false,
+        };
+        if local_file.is_none() {
+            match File::create(local_path) {
+                Ok(_local_file) => local_file = Some(_local_file),
+                Err(e) => {
+                    println!("Couldn't write {}: {}", local_path, e);
+                    return true;
+                }
+            }
+        }
+        if let Err(e) = local_file.as_mut().unwrap().write_all(&content) {
+            println!("Couldn't write {}: {}", local_path, e);
+            return true;
+        }
+        offset += content.len();
+        // Only the last piece is short
+        if content.len() < MAX_CHUNK_LEN {
+            println!("Downloaded {} byte(s) to {}", offset, local_path);
+            return true;
+        }
     }
-    SystemResult::ClosedConnection
 }
 
-static SOCKET_PATH: &'static str = "/tmp/fs_socket";
+static SOCKET_PATH: &str = "/tmp/fs_socket";
 
 fn main() {
     let mut stream = match UnixStream::connect(SOCKET_PATH) {
         Ok(_goes_to_stream) => _goes_to_stream,
-        Err(e) => panic!(e),
+        Err(e) => panic!("{}", e),
     };
 
     let main_menu = create_main_menu();
@@ -194,48 +282,105 @@ fn main() {
             }
         };
 
-        let result = receive_result(match stream.try_clone() {
-            Ok(_cloned_stream) => _cloned_stream,
-            Err(e) => panic!("Couldn't clone stream!: {}", e),
-        });
-
-        match result {
-            SystemResult::ClosedConnection => {
+        match receive_response(&mut stream) {
+            None => {
                 eprintln!("Connection forcibly closed!");
                 return;
             }
-            SystemResult::Ok => {
-                loop {
-                    client_menu.print_menu();
-
-                    let to_write: String = match get_input("──> ").as_str() {
-                        "e" | "E" => break,
-                        "c" | "C" => create_file(),
-                        "d" | "D" => delete_file(),
-                        "r" | "R" => rename_file(),
-                        "o" | "O" => open_file(),
-                        "x" | "X" => close_file(),
-                        "l" | "L" => read_file(),
-                        "w" | "W" => write_to_file(),
-                        _ => {
-                            println!("\nType one of the supported options below!");
-                            thread::sleep(time::Duration::new(1, 100_000_000));
-                            continue;
+            Some(Response::Result(SystemResult::Ok)) => loop {
+                client_menu.print_menu();
+
+                let option = get_input("──> ");
+                let request = match option.as_str() {
+                    "e" | "E" => Request::Logout,
+                    "c" | "C" => create_file(),
+                    "d" | "D" => delete_file(),
+                    "r" | "R" => rename_file(),
+                    "m" | "M" => change_permissions(),
+                    "o" | "O" => open_file(),
+                    "x" | "X" => close_file(),
+                    "l" | "L" => read_file(),
+                    "w" | "W" => write_to_file(),
+                    "j" | "J" => seek_file(),
+                    "k" | "K" => make_directory(),
+                    "q" | "Q" => remove_directory(),
+                    "s" | "S" => list_directory(),
+                    "g" | "G" => change_directory(),
+                    "p" | "P" => print_working_directory(),
+                    "y" | "Y" => lock_file(),
+                    "z" | "Z" => unlock_file(),
+                    "i" | "I" => stat_file(),
+                    "t" | "T" => list_files(),
+                    // Transfers take a request for each piece
+                    "u" | "U" => {
+                        if let Some(local_upload) = upload_file() {
+                            if !upload(&mut stream, local_upload) {
+                                eprintln!("Connection forcibly closed!");
+                                return;
+                            }
+                        }
+                        continue;
+                    }
+                    "n" | "N" => {
+                        let (name, local_path) = download_file();
+                        if !download(&mut stream, name, &local_path) {
+                            eprintln!("Connection forcibly closed!");
+                            return;
                         }
-                    };
-                    write_str(&mut stream, &to_write);
-                    let result = receive_result(match stream.try_clone() {
-                        Ok(_cloned_stream) => _cloned_stream,
-                        Err(e) => panic!("Couldn't clone stream!: {}", e),
-                    });
-                    print_result(result)
+                        continue;
+                    }
+                    _ => {
+                        println!("\nType one of the supported options below!");
+                        thread::sleep(time::Duration::new(1, 100_000_000));
+                        continue;
+                    }
+                };
+                send_request(&mut stream, &request);
+                match receive_response(&mut stream) {
+                    Some(Response::Opened(fd)) => {
+                        println!("File opened with file descriptor {}", fd)
+                    }
+                    Some(Response::Names(names)) => {
+                        if names.is_empty() {
+                            println!("(empty)");
+                        }
+                        for name in names {
+                            println!("{}", name);
+                        }
+                    }
+                    Some(Response::WorkingDirectory(cwd)) => println!("{}", cwd),
+                    Some(Response::Stat(stat)) => print_file_table(&[stat]),
+                    Some(Response::Stats(stats)) => print_file_table(&stats),
+                    // Binary content is shown as far as it's text
+                    Some(Response::Data(content)) => {
+                        println!("File content: \"{}\"", String::from_utf8_lossy(&content))
+                    }
+                    Some(Response::Written { len, saved }) => {
+                        println!("Wrote {} byte(s)", len);
+                        if !saved {
+                            print_result(SystemResult::NotSaved);
+                        }
+                    }
+                    Some(Response::Offset(offset)) => println!("Offset is now {}", offset),
+                    // Back to the main menu once logged out
+                    Some(Response::Result(SystemResult::Ok))
+                        if matches!(request, Request::Logout) =>
+                    {
+                        break
+                    }
+                    Some(Response::Result(result)) => print_result(result),
+                    None => {
+                        eprintln!("Connection forcibly closed!");
+                        return;
+                    }
                 }
-            }
-            _ => print_result(result),
+            },
+            Some(Response::Result(result)) => print_result(result),
+            Some(_) => print_result(SystemResult::MiscellaneousError),
         }
     }
     match stream.shutdown(Shutdown::Both) {
-        Ok(_) => { /* UnixStream shutdown Successful! */ },
-        Err(e) => panic!("UnixStream shutdown failed!: {error}", error = e)
+        Ok(_) => { /* UnixStream shutdown Successful! */ }
+        Err(e) => panic!("UnixStream shutdown failed!: {error}", error = e),
     }
 }
@@ -1,13 +1,7 @@
 use std::io::{self, Write};
 use std::{thread, time};
 
-#[derive(Debug)]
-pub enum Permission {
-    None = 0,      // 0b0000
-    Write = 1,     // 0b0001
-    Read = 2,      // 0b0010
-    ReadWrite = 3, // 0b0011
-}
+pub use so_proj_protocol::Permission;
 
 pub fn get_input(prompt: &str) -> String {
     print!("{}", prompt);
@@ -0,0 +1,257 @@
+/*
+ * What so-proj and its client say to each other. Every message is a bincode encoded Request or
+ * Response sent after its length, a big endian u64, so file content can be any bytes. A message
+ * is at most MAX_FRAME_LEN bytes long, larger files go in pieces of MAX_CHUNK_LEN.
+*/
+
+extern crate bincode;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use std::io::{self, Read, Write};
+
+// The longest message receive takes, in bytes, so the other side can't make it buffer forever.
+pub const MAX_FRAME_LEN: u64 = 8 * 1024 * 1024;
+// The most file content a Read or a Download answers with, fewer bytes come back when asked for
+// more. Uploads are sent in pieces this long.
+pub const MAX_CHUNK_LEN: usize = 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SystemResult {
+    Ok = 0,
+    FileAlreadyExists = -4,
+    FileDoesntExist = -5,
+    FileNotOpen = -8,
+    FileAlreadyOpen = -9,
+    OpenInInvalidMode = -10,
+    PermissionDenied = -6,
+    ReachedMaxOpenFiles = -7,
+    MiscellaneousError = -11,
+    ClientAlreadyExists = -12,
+    ClientDoesntExist = -13,
+    WrongCredentials = -14,
+    NotADirectory = -15,
+    IsADirectory = -16,
+    DirectoryNotEmpty = -17,
+    InvalidOffset = -18,
+    AlreadyLoggedIn = -19,
+    FileLocked = -20,
+    Deadlock = -21,
+    FileTooLarge = -22,
+    // Not a failure: the change was made, but the server couldn't save it, so it may not survive
+    // a restart
+    NotSaved = -23,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Permission {
+    None = 0,      // 0b0000
+    Write = 1,     // 0b0001
+    Read = 2,      // 0b0010
+    ReadWrite = 3, // 0b0011
+}
+
+// Where lseek counts the new offset from.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Whence {
+    Set,
+    Current,
+    End,
+}
+
+// Like flock(2): any number of descriptors can hold a shared lock on a file, or one an exclusive.
+// Descriptors belong to the client, not the connection, so every session of a client shares its
+// locks: they never keep the client's own sessions out, and one session can unlock what another
+// locked.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LockMode {
+    Shared,
+    Exclusive,
+}
+
+// What a stat tells about a file or directory. Times are in seconds since the Unix epoch.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct FileStat {
+    pub name: String, // absolute path
+    pub is_directory: bool,
+    pub size: u64, // in bytes, 0 for directories
+    pub owner: String,
+    pub owner_permission: Permission,
+    pub others_permission: Permission,
+    pub created: u64,
+    pub modified: Option<u64>, // directories only keep when they were created
+    pub accessed: Option<u64>,
+    pub open_count: usize, // descriptors on it, anyone's
+}
+
+// Paths are relative to the session's working directory unless they start with "/".
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub enum Request {
+    Register {
+        name: String,
+        password: String,
+    },
+    Login {
+        name: String,
+        password: String,
+    },
+    // Closes the client's files unless it's logged in elsewhere too. The connection stays open
+    // for another login.
+    Logout,
+    Create {
+        name: String,
+        content: Vec<u8>,
+        owner_permission: Permission,
+        others_permission: Permission,
+    },
+    Delete {
+        name: String,
+    },
+    Rename {
+        name: String,
+        new_name: String,
+    },
+    ChangePermissions {
+        name: String,
+        owner_permission: Permission,
+        others_permission: Permission,
+    },
+    Open {
+        name: String,
+        permission: Permission,
+        append: bool,
+    },
+    Close {
+        fd: usize,
+    },
+    Read {
+        fd: usize,
+        len: usize,
+    },
+    // Writes up to `len` bytes of `content`.
+    Write {
+        fd: usize,
+        content: Vec<u8>,
+        len: usize,
+    },
+    Seek {
+        fd: usize,
+        offset: i64,
+        whence: Whence,
+    },
+    // Takes or converts the descriptor's lock. Without a timeout, in milliseconds, a lock
+    // someone else holds is SystemResult::FileLocked right away.
+    Lock {
+        fd: usize,
+        mode: LockMode,
+        timeout: Option<u64>,
+    },
+    Unlock {
+        fd: usize,
+    },
+    MakeDirectory {
+        name: String,
+        owner_permission: Permission,
+        others_permission: Permission,
+    },
+    RemoveDirectory {
+        name: String,
+    },
+    // The working directory when there's no name.
+    ListDirectory {
+        name: Option<String>,
+    },
+    ChangeDirectory {
+        name: String,
+    },
+    WorkingDirectory,
+    Stat {
+        name: String,
+    },
+    // What's in a directory, the working directory when there's no name, with a stat for each
+    // entry whose name matches the glob `pattern`, like "*.txt" or "report-[0-9]?".
+    List {
+        name: Option<String>,
+        pattern: Option<String>,
+    },
+    // A file in pieces of up to MAX_CHUNK_LEN bytes. The first piece, at offset 0, creates the
+    // file or replaces what's in it, each next one goes at the end of what was sent so far. The
+    // permissions only apply to a new file.
+    Upload {
+        name: String,
+        offset: usize,
+        content: Vec<u8>,
+        owner_permission: Permission,
+        others_permission: Permission,
+    },
+    // Up to MAX_CHUNK_LEN bytes of the file from `offset` on, fewer only at its end.
+    Download {
+        name: String,
+        offset: usize,
+    },
+}
+
+// A request that went wrong is always answered with its SystemResult, one that went right with
+// what it asked for, or with SystemResult::Ok when it asked for nothing. A change the server made
+// but couldn't save went right too, it's answered with SystemResult::NotSaved instead of Ok, or
+// as a Written that isn't saved.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub enum Response {
+    Result(SystemResult),
+    Opened(usize), // the new file descriptor
+    Data(Vec<u8>), // what was read or downloaded
+    Written {
+        len: usize, // in bytes
+        saved: bool,
+    },
+    Offset(usize),
+    Names(Vec<String>), // in a directory, directories with a trailing "/"
+    WorkingDirectory(String),
+    Stat(FileStat),
+    Stats(Vec<FileStat>), // sorted by name
+}
+
+pub fn send<W: Write, T: Serialize>(writer: &mut W, message: &T) -> io::Result<()> {
+    let bytes = match bincode::serialize(message) {
+        Ok(_bytes) => _bytes,
+        Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidInput, e)),
+    };
+    writer.write_all(&(bytes.len() as u64).to_be_bytes())?;
+    writer.write_all(&bytes)?;
+    writer.flush()
+}
+
+// The next message, or UnexpectedEof once the other side is gone. A message that doesn't decode
+// is InvalidData, and the one after it can still be received. One longer than MAX_FRAME_LEN is
+// an error before it's read, nothing more can be received after it.
+pub fn receive<R: Read, T: DeserializeOwned>(reader: &mut R) -> io::Result<T> {
+    receive_at_most(reader, MAX_FRAME_LEN)
+}
+
+// Like receive, for messages up to `max_len` bytes long.
+pub fn receive_at_most<R: Read, T: DeserializeOwned>(
+    reader: &mut R,
+    max_len: u64,
+) -> io::Result<T> {
+    let mut len = [0; 8];
+    reader.read_exact(&mut len)?;
+    let len = u64::from_be_bytes(len);
+    if len > max_len {
+        return Err(io::Error::other(format!(
+            "message of {} bytes is longer than {}",
+            len, max_len
+        )));
+    }
+
+    // Grows as the bytes come in, a bogus length doesn't get allocated up front
+    let mut bytes = Vec::new();
+    reader.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+    match bincode::deserialize(&bytes) {
+        Ok(_message) => Ok(_message),
+        Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+    }
+}
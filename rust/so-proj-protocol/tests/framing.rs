@@ -0,0 +1,64 @@
+use so_proj_protocol::{receive, send, Permission, Request, Response, SystemResult, MAX_FRAME_LEN};
+
+use std::io::{Cursor, ErrorKind};
+
+#[test]
+fn messages_come_back_in_order() {
+    let mut wire = Vec::new();
+    let upload = Request::Upload {
+        name: "dir/a|b".to_string(),
+        offset: 0,
+        content: (0..=255).collect(),
+        owner_permission: Permission::ReadWrite,
+        others_permission: Permission::None,
+    };
+    send(&mut wire, &upload).unwrap();
+    send(&mut wire, &Request::WorkingDirectory).unwrap();
+
+    let mut reader = Cursor::new(wire);
+    assert_eq!(receive::<_, Request>(&mut reader).unwrap(), upload);
+    assert_eq!(
+        receive::<_, Request>(&mut reader).unwrap(),
+        Request::WorkingDirectory
+    );
+    let end = receive::<_, Request>(&mut reader).unwrap_err();
+    assert_eq!(end.kind(), ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn undecodable_message_is_skipped() {
+    let mut wire = Vec::new();
+    wire.extend_from_slice(&3u64.to_be_bytes());
+    wire.extend_from_slice(&[0xff, 0xff, 0xff]);
+    send(&mut wire, &Response::Result(SystemResult::Ok)).unwrap();
+
+    let mut reader = Cursor::new(wire);
+    let error = receive::<_, Response>(&mut reader).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    assert_eq!(
+        receive::<_, Response>(&mut reader).unwrap(),
+        Response::Result(SystemResult::Ok)
+    );
+}
+
+#[test]
+fn truncated_message_is_eof() {
+    let mut wire = Vec::new();
+    send(&mut wire, &Response::Data(vec![1; 64])).unwrap();
+    wire.truncate(wire.len() - 1);
+
+    let error = receive::<_, Response>(&mut Cursor::new(wire)).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn oversized_message_is_refused_unread() {
+    let mut wire = Vec::new();
+    wire.extend_from_slice(&(MAX_FRAME_LEN + 1).to_be_bytes());
+    wire.extend_from_slice(&[0; 16]);
+
+    let mut reader = Cursor::new(wire);
+    let error = receive::<_, Response>(&mut reader).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Other);
+    assert_eq!(reader.position(), 8);
+}
@@ -1,281 +1,1012 @@
 extern crate bincode;
-extern crate rustc_serialize;
-use num_enum::TryFromPrimitive;
+use serde::{Deserialize, Serialize};
 
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufReader, BufWriter};
+pub use so_proj_protocol::{FileStat, LockMode, Permission, SystemResult, Whence, MAX_CHUNK_LEN};
 
-use std::{thread, time};
+use crate::path::{self, HOME, ROOT};
+use crate::persistence::{Journal, Journaled};
 
-const MAX_OPEN_FILES: usize = 5;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::{Condvar, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-#[derive(RustcEncodable, RustcDecodable, Clone, Eq, PartialEq)]
-pub enum SystemResult {
-    Ok = 0,
-    Exit = -1,
-    FileAlreadyExists = -4,
-    FileDoesntExist = -5,
-    FileNotOpen = -8,
-    FileAlreadyOpen = -9,
-    OpenInInvalidMode = -10,
-    PermissionDenied = -6,
-    ReachedMaxOpenFiles = -7,
-    MiscellaneousError = -11,
-    ClientAlreadyExists = -12,
-    ClientDoesntExist = -13,
-    WrongCredentials = -14,
-}
+// Per client: every logged-in client has a descriptor table of its own.
+const MAX_OPEN_FILES: usize = 5;
+// In bytes. Files are kept in memory, a write or seek past this is refused rather than growing one.
+pub const MAX_FILE_SIZE: usize = 64 * 1024 * 1024;
+// Owns the root and /home, no client can have this name.
+const SYSTEM_OWNER: &str = "";
 
-#[derive(RustcEncodable, RustcDecodable, Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
-#[repr(i32)]
-pub enum Permission {
-    None = 0,      // 0b0000
-    Write = 1,     // 0b0001
-    Read = 2,      // 0b0010
-    ReadWrite = 3, // 0b0011
-}
+// Woken up whenever a lock is released, for whoever waits for one. There's a single file system,
+// guarded by a single mutex, to wait with.
+static LOCKS_RELEASED: Condvar = Condvar::new();
 
-#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct _File {
-    name: String,
-    content: String,
+    name: String, // absolute path
+    content: Vec<u8>,
+    owner: String, // the client that created it
+    owner_permission: Permission,
+    others_permission: Permission,
+    // In seconds since the Unix epoch. Reads aren't journaled, a restart forgets the access
+    // times since the last snapshot.
+    created: u64,
+    modified: u64,
+    accessed: u64,
+}
+// Read on a directory lets a client look up what's in it, Write lets it create and remove entries.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Directory {
+    owner: String,
     owner_permission: Permission,
     others_permission: Permission,
+    created: u64,
 }
-#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct OpenFile {
     file_name: String,
     permission: Permission,
+    offset: usize, // in bytes, where the next read or write starts
+    append: bool,  // every write goes to the end of the file
+    lock: Option<LockMode>,
 }
-#[derive(RustcEncodable, RustcDecodable, Debug)]
+
+// The files a client has open. A descriptor keeps its number until it is closed, and the lowest
+// free number is handed out first, like open(2) does.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct FdTable {
+    open_files: BTreeMap<usize, OpenFile>,
+}
+// Descriptors and the journal aren't saved, they don't outlive the server.
+#[derive(Serialize, Deserialize, Debug)]
 pub struct FileSystem {
-    files: HashMap<String, _File>,
-    open_files: Vec<OpenFile>,
+    files: HashMap<String, _File>,           // by absolute path
+    directories: HashMap<String, Directory>, // by absolute path
+    #[serde(skip)]
+    open_files: HashMap<String, FdTable>, // by client name
+    // The descriptors waiting for a lock, by client name and descriptor, and what they wait for.
+    // Locks are held per client, like the descriptors, so the wait-for graph is one of clients.
+    #[serde(skip)]
+    waiting: HashMap<(String, usize), (String, LockMode)>,
+    #[serde(skip)]
+    journal: Option<Journal>,
+}
+
+// A change to the files and directories as it is journaled. Each one sets things to how they
+// ended up, so it can be applied again on top of itself.
+#[derive(Serialize, Deserialize)]
+pub enum Change {
+    PutFile(_File),
+    WriteFile {
+        name: String,
+        offset: usize,
+        content: Vec<u8>,
+        modified: u64,
+    },
+    RemoveFile(String),
+    // The file under its new name
+    RenameFile {
+        name: String,
+        file: _File,
+    },
+    SetPermissions {
+        name: String,
+        owner_permission: Permission,
+        others_permission: Permission,
+    },
+    PutDirectory {
+        path: String,
+        directory: Directory,
+    },
+    RemoveDirectory(String),
 }
 
 impl _File {
     pub fn new(
         name: String,
-        content: String,
+        content: Vec<u8>,
+        owner: String,
         owner_permission: Permission,
         others_permission: Permission,
     ) -> _File {
+        let created = now();
         _File {
             name,
             content,
+            owner,
             owner_permission,
             others_permission,
+            created,
+            modified: created,
+            accessed: created,
         }
     }
 }
 
+// Only the size of the content, files can be large and binary.
+impl fmt::Debug for _File {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("_File")
+            .field("name", &self.name)
+            .field("content", &format_args!("{} bytes", self.content.len()))
+            .field("owner", &self.owner)
+            .field("owner_permission", &self.owner_permission)
+            .field("others_permission", &self.others_permission)
+            .finish()
+    }
+}
+
+impl Directory {
+    fn new(owner: &str, owner_permission: Permission, others_permission: Permission) -> Self {
+        Directory {
+            owner: owner.to_string(),
+            owner_permission,
+            others_permission,
+            created: now(),
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+// Whether every bit of `wanted` is granted to `client`: the owner bits for the client that
+// created the file or directory, the others bits for everyone else.
+fn grants(
+    owner: &str,
+    owner_permission: Permission,
+    others_permission: Permission,
+    client: &str,
+    wanted: Permission,
+) -> bool {
+    let granted = if owner == client {
+        owner_permission
+    } else {
+        others_permission
+    };
+    (wanted as i32 & !(granted as i32)) == 0
+}
+
+impl OpenFile {
+    fn is_writable(&self) -> bool {
+        (self.permission as i32 & Permission::Write as i32) != 0
+    }
+
+    fn is_readable(&self) -> bool {
+        (self.permission as i32 & Permission::Read as i32) != 0
+    }
+}
+
+// Overwrites `file_content` with `content` from `start` on, extending it past its end. Writing
+// past the end leaves a gap of zero bytes.
+fn splice_content(file_content: &mut Vec<u8>, start: usize, content: &[u8]) {
+    if start > file_content.len() {
+        file_content.resize(start, 0);
+    }
+    let end = (start + content.len()).min(file_content.len());
+    file_content.splice(start..end, content.iter().copied());
+}
+
+// Whether a lock held keeps `wanted` from being taken: shared locks only keep out exclusive ones.
+fn conflicts(held: LockMode, wanted: LockMode) -> bool {
+    held == LockMode::Exclusive || wanted == LockMode::Exclusive
+}
+
+// Gives `file_system` up until a lock is released or `timeout` passes.
+pub fn wait_for_release(
+    file_system: MutexGuard<FileSystem>,
+    timeout: Duration,
+) -> MutexGuard<FileSystem> {
+    LOCKS_RELEASED.wait_timeout(file_system, timeout).unwrap().0
+}
+
+// The client's open file behind `fd`. Takes the tables rather than the whole FileSystem so the
+// files can be borrowed alongside.
+fn descriptor<'a>(
+    open_files: &'a mut HashMap<String, FdTable>,
+    client: &str,
+    fd: usize,
+) -> Result<&'a mut OpenFile, SystemResult> {
+    open_files
+        .get_mut(client)
+        .and_then(|table| table.open_files.get_mut(&fd))
+        .ok_or(SystemResult::FileNotOpen)
+}
+
+impl FdTable {
+    fn insert(&mut self, open_file: OpenFile) -> Option<usize> {
+        if self.open_files.len() == MAX_OPEN_FILES {
+            return None;
+        }
+        let fd = (0..).find(|fd| !self.open_files.contains_key(fd)).unwrap();
+        self.open_files.insert(fd, open_file);
+        Some(fd)
+    }
+}
+
 impl FileSystem {
+    // Everyone can look around the root and /home, but only create files in their home.
     pub fn new() -> Self {
+        let mut directories = HashMap::default();
+        for system_directory in [ROOT, HOME] {
+            directories.insert(
+                system_directory.to_string(),
+                Directory::new(SYSTEM_OWNER, Permission::ReadWrite, Permission::Read),
+            );
+        }
         FileSystem {
             files: HashMap::default(),
-            open_files: Vec::new(),
+            directories,
+            open_files: HashMap::default(),
+            waiting: HashMap::default(),
+            journal: None,
         }
     }
 
-    pub fn get_file(&self, file_name: &str) -> &_File {
-        self.files.get(file_name).unwrap()
+    // The file system saved in `dir`, or a new one if nothing was saved there yet. Changes from
+    // now on are saved there too.
+    pub fn restore(dir: &Path) -> io::Result<Self> {
+        let (mut file_system, journal) = Journal::restore(dir, "file_system", FileSystem::new())?;
+        file_system.journal = Some(journal);
+        Ok(file_system)
     }
 
-    pub fn add_file(&mut self, file: _File) -> SystemResult {
-        match self.filename_exists(&file.name) {
-            Some(_) => {
-                thread::sleep(time::Duration::new(1, 100_000_000));
-            }
-            None => {
-                self.files.insert(String::from(&file.name), file);
+    // Called once the change is made, a snapshot taken while recording it has to include it.
+    // Failing to save it doesn't undo it, but the client is told with SystemResult::NotSaved.
+    fn record(&mut self, change: Change) -> SystemResult {
+        let mut system_result = SystemResult::Ok;
+        if let Some(mut journal) = self.journal.take() {
+            if let Err(e) = journal.record(self, &change) {
+                eprintln!("Not able to journal to {}: {}", journal.path().display(), e);
+                system_result = SystemResult::NotSaved;
             }
+            self.journal = Some(journal);
         }
+        system_result
+    }
 
-        SystemResult::Ok
+    fn path_exists(&self, path: &str) -> bool {
+        self.files.contains_key(path) || self.directories.contains_key(path)
     }
 
-    pub fn delete_file(&mut self, file_name: String) -> SystemResult {
-        match self.filename_exists(&file_name) {
-            Some(file) => match self.file_is_open(&file) {
-                Some((fd, _)) => {
-                    self.close_file(fd);
+    fn check_directory(
+        &self,
+        client: &str,
+        path: &str,
+        wanted: Permission,
+    ) -> Result<(), SystemResult> {
+        match self.directories.get(path) {
+            Some(directory) => {
+                if grants(
+                    &directory.owner,
+                    directory.owner_permission,
+                    directory.others_permission,
+                    client,
+                    wanted,
+                ) {
+                    Ok(())
+                } else {
+                    Err(SystemResult::PermissionDenied)
                 }
-                None => {}
-            },
-            None => {
-                return SystemResult::FileDoesntExist;
             }
-        };
+            None if self.files.contains_key(path) => Err(SystemResult::NotADirectory),
+            None => Err(SystemResult::FileDoesntExist),
+        }
+    }
 
-        self.files.remove(&file_name);
+    // Reaching `path` takes Read on every directory above it.
+    fn check_lookup(&self, client: &str, path: &str) -> Result<(), SystemResult> {
+        for ancestor in path::ancestors(path) {
+            self.check_directory(client, ancestor, Permission::Read)?;
+        }
+        Ok(())
+    }
 
-        SystemResult::Ok
+    // Creating or removing `path` also takes Write on the directory holding it.
+    fn check_parent_writable(&self, client: &str, path: &str) -> Result<(), SystemResult> {
+        self.check_lookup(client, path)?;
+        match path::parent(path) {
+            Some(parent) => self.check_directory(client, parent, Permission::Write),
+            None => Err(SystemResult::PermissionDenied),
+        }
     }
 
-    pub fn rename_file(&mut self, file_name: String, new_file_name: String) -> SystemResult {
-        let old_file: &_File;
+    fn children<'a>(&'a self, path: &'a str) -> impl Iterator<Item = &'a String> + 'a {
+        self.files
+            .keys()
+            .chain(self.directories.keys())
+            .filter(move |child| path::parent(child) == Some(path))
+    }
 
+    pub fn add_file(&mut self, client: &str, file: _File) -> SystemResult {
+        match self.check_parent_writable(client, &file.name) {
+            Err(system_result) => system_result,
+            Ok(_) if self.path_exists(&file.name) => SystemResult::FileAlreadyExists,
+            Ok(_) if file.content.len() > MAX_FILE_SIZE => SystemResult::FileTooLarge,
+            Ok(_) => {
+                self.files.insert(String::from(&file.name), file.clone());
+                self.record(Change::PutFile(file))
+            }
+        }
+    }
+
+    pub fn delete_file(&mut self, client: &str, file_name: String) -> SystemResult {
+        if let Err(system_result) = self.check_parent_writable(client, &file_name) {
+            return system_result;
+        }
         match self.filename_exists(&file_name) {
-            // Check if the file to rename exists
-            Some(file) => old_file = file,
+            Some(file) if file.owner != client => return SystemResult::PermissionDenied,
+            Some(_) => {}
+            None if self.directories.contains_key(&file_name) => return SystemResult::IsADirectory,
             None => return SystemResult::FileDoesntExist,
         }
-        match self.filename_exists(&new_file_name) {
-            // Check if the new file name exists
-            Some(_) => return SystemResult::FileAlreadyExists,
-            None => {}
+        // Whoever has it open loses the descriptor, and its lock
+        for table in self.open_files.values_mut() {
+            table
+                .open_files
+                .retain(|_, open_file| open_file.file_name != file_name);
         }
+        LOCKS_RELEASED.notify_all();
 
-        let new_file = _File {
-            // Create new file from old file with replaced content
-            name: new_file_name.clone(),
-            content: old_file.content.clone(),
-            owner_permission: old_file.owner_permission,
-            others_permission: old_file.others_permission,
-        };
+        self.files.remove(&file_name);
+        self.record(Change::RemoveFile(file_name))
+    }
 
-        let opt: Option<Permission> = match self.file_is_open(old_file) {
-            Some((_, open_file)) => Some(open_file.permission),
-            None => None,
+    pub fn rename_file(
+        &mut self,
+        client: &str,
+        file_name: String,
+        new_file_name: String,
+    ) -> SystemResult {
+        // The file can move to another directory, both have to be writable
+        for path in [&file_name, &new_file_name] {
+            if let Err(system_result) = self.check_parent_writable(client, path) {
+                return system_result;
+            }
+        }
+        // Check if the file to rename exists and belongs to the client
+        match self.filename_exists(&file_name) {
+            Some(file) if file.owner != client => return SystemResult::PermissionDenied,
+            Some(_) => {}
+            None if self.directories.contains_key(&file_name) => return SystemResult::IsADirectory,
+            None => return SystemResult::FileDoesntExist,
         };
+        // Check if the new file name exists
+        if self.path_exists(&new_file_name) {
+            return SystemResult::FileAlreadyExists;
+        }
 
-        self.delete_file(file_name);
-        self.add_file(new_file.clone());
+        let mut file = self.files.remove(&file_name).unwrap();
+        file.name = new_file_name.clone();
+        self.files.insert(new_file_name.clone(), file.clone());
+        let system_result = self.record(Change::RenameFile {
+            name: file_name.clone(),
+            file,
+        });
 
-        if let Some(open_permission) = opt {
-            self.open_file(new_file_name, open_permission);
-        };
+        // Descriptors stay open on the renamed file
+        for table in self.open_files.values_mut() {
+            for open_file in table.open_files.values_mut() {
+                if open_file.file_name == file_name {
+                    open_file.file_name = new_file_name.clone();
+                }
+            }
+        }
 
-        SystemResult::Ok
+        system_result
     }
 
-    pub fn open_file(&mut self, file_name: String, open_permission: Permission) -> SystemResult {
-        if self.open_files.len() == MAX_OPEN_FILES {
-            return SystemResult::ReachedMaxOpenFiles;
+    // Only the owner changes the permissions of a file or directory. Descriptors already open
+    // keep the mode they were opened with.
+    pub fn change_permissions(
+        &mut self,
+        client: &str,
+        file_name: &str,
+        owner_permission: Permission,
+        others_permission: Permission,
+    ) -> SystemResult {
+        if let Err(system_result) = self.check_lookup(client, file_name) {
+            return system_result;
         }
-        let file_name_to_open;
-        match self.filename_exists(&file_name) {
-            Some(file) => {
-                match self.file_is_open(&file) {
-                    Some((_, _)) => return SystemResult::FileAlreadyOpen,
-                    None => {
-                        if !self.check_permission(&open_permission, &file) {
-                            return SystemResult::PermissionDenied;
-                        }
-                    }
-                };
-                file_name_to_open = file.name.clone();
+        if let Some(directory) = self.directories.get_mut(file_name) {
+            if directory.owner != client {
+                return SystemResult::PermissionDenied;
             }
-            None => return SystemResult::FileDoesntExist,
-        };
-
-        self.open_files.push(OpenFile {
-            file_name: file_name_to_open,
-            permission: open_permission,
-        });
+            directory.owner_permission = owner_permission;
+            directory.others_permission = others_permission;
+        } else {
+            match self.files.get_mut(file_name) {
+                Some(file) if file.owner != client => return SystemResult::PermissionDenied,
+                Some(file) => {
+                    file.owner_permission = owner_permission;
+                    file.others_permission = others_permission;
+                }
+                None => return SystemResult::FileDoesntExist,
+            }
+        }
+        self.record(Change::SetPermissions {
+            name: file_name.to_string(),
+            owner_permission,
+            others_permission,
+        })
+    }
 
-        SystemResult::Ok
+    pub fn make_directory(
+        &mut self,
+        client: &str,
+        path: String,
+        owner_permission: Permission,
+        others_permission: Permission,
+    ) -> SystemResult {
+        match self.check_parent_writable(client, &path) {
+            Err(system_result) => system_result,
+            Ok(_) if self.path_exists(&path) => SystemResult::FileAlreadyExists,
+            Ok(_) => {
+                let directory = Directory::new(client, owner_permission, others_permission);
+                self.directories.insert(path.clone(), directory.clone());
+                self.record(Change::PutDirectory { path, directory })
+            }
+        }
     }
 
-    pub fn close_file(&mut self, fd: usize) -> SystemResult {
-        if fd > MAX_OPEN_FILES {
-            return SystemResult::MiscellaneousError;
+    // Only empty directories are removed, by their owner.
+    pub fn remove_directory(&mut self, client: &str, path: &str) -> SystemResult {
+        if let Err(system_result) = self.check_parent_writable(client, path) {
+            return system_result;
         }
-        if self.open_files.len() < fd {
-            return SystemResult::FileNotOpen;
+        match self.directories.get(path) {
+            Some(directory) if directory.owner != client => SystemResult::PermissionDenied,
+            Some(_) if self.children(path).next().is_some() => SystemResult::DirectoryNotEmpty,
+            Some(_) => {
+                self.directories.remove(path);
+                self.record(Change::RemoveDirectory(path.to_string()))
+            }
+            None if self.files.contains_key(path) => SystemResult::NotADirectory,
+            None => SystemResult::FileDoesntExist,
         }
-        self.open_files.remove(fd);
-        SystemResult::Ok
     }
 
-    pub fn read_file(&mut self, fd: usize, len_to_read: usize) -> SystemResult {
-        match self.check_file_descriptor(fd) {
-            Some(open_file) => {
-                if self.get_file(&open_file.file_name).content.len() < len_to_read {
-                    return SystemResult::MiscellaneousError;
-                }
-                if self.check_readability(fd) {
-                    let (first, _) = self
-                        .get_file(&open_file.file_name)
-                        .content
-                        .split_at(len_to_read);
-                    println!("File content: \"{}\"", first);
-                    SystemResult::Ok
+    // The names in the directory, sorted, with a "/" after those of directories.
+    pub fn list_directory(&self, client: &str, path: &str) -> Result<Vec<String>, SystemResult> {
+        self.check_lookup(client, path)?;
+        self.check_directory(client, path, Permission::Read)?;
+        let mut names: Vec<String> = self
+            .children(path)
+            .map(|child| {
+                if self.directories.contains_key(child) {
+                    format!("{}/", path::base_name(child))
                 } else {
-                    SystemResult::OpenInInvalidMode
+                    path::base_name(child).to_string()
+                }
+            })
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    // Anyone who can reach a file or directory can stat it, like stat(2).
+    pub fn stat(&self, client: &str, path: &str) -> Result<FileStat, SystemResult> {
+        self.check_lookup(client, path)?;
+        self.stat_entry(path).ok_or(SystemResult::FileDoesntExist)
+    }
+
+    // A stat for each entry in the directory whose name matches `pattern`, sorted by name.
+    pub fn list(
+        &self,
+        client: &str,
+        path: &str,
+        pattern: Option<&str>,
+    ) -> Result<Vec<FileStat>, SystemResult> {
+        self.check_lookup(client, path)?;
+        self.check_directory(client, path, Permission::Read)?;
+        let mut stats: Vec<FileStat> = self
+            .children(path)
+            .filter(|child| {
+                pattern.is_none_or(|pattern| path::glob_matches(pattern, path::base_name(child)))
+            })
+            .filter_map(|child| self.stat_entry(child))
+            .collect();
+        stats.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(stats)
+    }
+
+    fn stat_entry(&self, path: &str) -> Option<FileStat> {
+        if let Some(directory) = self.directories.get(path) {
+            return Some(FileStat {
+                name: path.to_string(),
+                is_directory: true,
+                size: 0,
+                owner: directory.owner.clone(),
+                owner_permission: directory.owner_permission,
+                others_permission: directory.others_permission,
+                created: directory.created,
+                modified: None,
+                accessed: None,
+                open_count: 0,
+            });
+        }
+        self.files.get(path).map(|file| FileStat {
+            name: file.name.clone(),
+            is_directory: false,
+            size: file.content.len() as u64,
+            owner: file.owner.clone(),
+            owner_permission: file.owner_permission,
+            others_permission: file.others_permission,
+            created: file.created,
+            modified: Some(file.modified),
+            accessed: Some(file.accessed),
+            open_count: self
+                .open_files
+                .values()
+                .flat_map(|table| table.open_files.values())
+                .filter(|open_file| open_file.file_name == file.name)
+                .count(),
+        })
+    }
+
+    // Whether `client` can make `path` its working directory.
+    pub fn change_directory(&self, client: &str, path: &str) -> SystemResult {
+        let result = self
+            .check_lookup(client, path)
+            .and_then(|_| self.check_directory(client, path, Permission::Read));
+        match result {
+            Ok(_) => SystemResult::Ok,
+            Err(system_result) => system_result,
+        }
+    }
+
+    // Every client gets a home directory of its own when it registers, the others can look in it.
+    pub fn create_home(&mut self, client: &str) -> SystemResult {
+        let home = path::home(client);
+        if self.directories.contains_key(&home) {
+            return SystemResult::Ok;
+        }
+        let directory = Directory::new(client, Permission::ReadWrite, Permission::Read);
+        self.directories.insert(home.clone(), directory.clone());
+        self.record(Change::PutDirectory {
+            path: home,
+            directory,
+        })
+    }
+
+    pub fn directory_exists(&self, path: &str) -> bool {
+        self.directories.contains_key(path)
+    }
+
+    // The new descriptor of `client`.
+    pub fn open_file(
+        &mut self,
+        client: &str,
+        file_name: String,
+        open_permission: Permission,
+        append: bool,
+    ) -> Result<usize, SystemResult> {
+        self.check_lookup(client, &file_name)?;
+        if self.directories.contains_key(&file_name) {
+            return Err(SystemResult::IsADirectory);
+        }
+        // Others having it open is fine, locks keep them apart
+        let file_name_to_open = match self.filename_exists(&file_name) {
+            Some(file) => {
+                if !self.check_permission(client, &open_permission, file) {
+                    return Err(SystemResult::PermissionDenied);
                 }
+                file.name.clone()
+            }
+            None => return Err(SystemResult::FileDoesntExist),
+        };
+
+        let table = self.open_files.entry(client.to_string()).or_default();
+        match table.insert(OpenFile {
+            file_name: file_name_to_open,
+            permission: open_permission,
+            offset: 0,
+            append,
+            lock: None,
+        }) {
+            Some(fd) => Ok(fd),
+            None => Err(SystemResult::ReachedMaxOpenFiles),
+        }
+    }
+
+    pub fn close_file(&mut self, client: &str, fd: usize) -> SystemResult {
+        let closed = self
+            .open_files
+            .get_mut(client)
+            .and_then(|table| table.open_files.remove(&fd));
+        match closed {
+            Some(_) => {
+                LOCKS_RELEASED.notify_all();
+                SystemResult::Ok
             }
             None => SystemResult::FileNotOpen,
         }
     }
 
-    pub fn write_to_file(&mut self, fd: usize, content: String, len_to_write: usize) -> SystemResult {
-        let file_name;
-        match self.check_file_descriptor(fd) {
-            Some(open_file) => {
-                if self.check_writability(fd) {
-                    file_name = self.get_file(&open_file.file_name).name.to_string();
-                } else {
-                    return SystemResult::OpenInInvalidMode;
+    // Closes everything `client` has open, when its last session ends.
+    pub fn close_all(&mut self, client: &str) {
+        self.open_files.remove(client);
+        LOCKS_RELEASED.notify_all();
+    }
+
+    // The clients whose descriptors on `file_name`, other than `fd` of `client`, hold a lock that
+    // keeps `mode` from being taken.
+    fn blockers(&self, client: &str, fd: usize, file_name: &str, mode: LockMode) -> Vec<&str> {
+        self.open_files
+            .iter()
+            .flat_map(|(holder, table)| {
+                table
+                    .open_files
+                    .iter()
+                    .map(move |(held_fd, open_file)| (holder.as_str(), *held_fd, open_file))
+            })
+            .filter(|(holder, held_fd, open_file)| {
+                open_file.file_name == file_name
+                    && (*holder, *held_fd) != (client, fd)
+                    && open_file.lock.is_some_and(|held| conflicts(held, mode))
+            })
+            .map(|(holder, _, _)| holder)
+            .collect()
+    }
+
+    // Whether `client` waiting for the lock would close a cycle of clients each waiting for a
+    // lock the next one holds, none of which would ever be released. A lock held by another of
+    // the client's own descriptors is a cycle already: it can't tell its sessions apart.
+    fn would_deadlock(&self, client: &str, fd: usize, file_name: &str, mode: LockMode) -> bool {
+        let mut to_visit = self.blockers(client, fd, file_name, mode);
+        let mut visited = HashSet::new();
+        while let Some(holder) = to_visit.pop() {
+            if holder == client {
+                return true;
+            }
+            if !visited.insert(holder) {
+                continue;
+            }
+            for ((waiter, waiter_fd), (file_name, mode)) in self.waiting.iter() {
+                if waiter == holder {
+                    to_visit.extend(self.blockers(waiter, *waiter_fd, file_name, *mode));
                 }
             }
-            None => return SystemResult::FileNotOpen,
         }
-        let mut len_to_write = len_to_write;
-        let content_len = content.chars().count();
-        if content_len < len_to_write {
-            len_to_write = content_len;
+        false
+    }
+
+    // Takes the lock, or converts the one the descriptor holds, unless another descriptor holds
+    // one in the way. Going from exclusive to shared lets others in.
+    pub fn lock_file(&mut self, client: &str, fd: usize, mode: LockMode) -> SystemResult {
+        let file_name = match descriptor(&mut self.open_files, client, fd) {
+            Ok(open_file) => open_file.file_name.clone(),
+            Err(system_result) => return system_result,
+        };
+        if !self.blockers(client, fd, &file_name, mode).is_empty() {
+            return SystemResult::FileLocked;
+        }
+        let open_file = descriptor(&mut self.open_files, client, fd).unwrap();
+        if open_file.lock == Some(LockMode::Exclusive) && mode == LockMode::Shared {
+            LOCKS_RELEASED.notify_all();
         }
-        self.files.get_mut(&file_name).unwrap().content = content[..len_to_write].to_string();
+        open_file.lock = Some(mode);
         SystemResult::Ok
     }
 
-    pub fn filename_exists(&self, file_name: &str) -> Option<&_File> {
-        for (_, file) in self.files.iter() {
-            if file.name == file_name {
-                return Some(file);
+    // Like flock(2), unlocking a descriptor without a lock is fine.
+    pub fn unlock_file(&mut self, client: &str, fd: usize) -> SystemResult {
+        match descriptor(&mut self.open_files, client, fd) {
+            Ok(open_file) => {
+                if open_file.lock.take().is_some() {
+                    LOCKS_RELEASED.notify_all();
+                }
+                SystemResult::Ok
             }
+            Err(system_result) => system_result,
         }
-        None
     }
 
-    pub fn file_is_open(&self, file: &_File) -> Option<(usize, &OpenFile)> {
-        for (fd, open_file) in self.open_files.iter().enumerate() {
-            if open_file.file_name == file.name {
-                return Some((fd, open_file));
-            }
+    // Marks the descriptor as waiting for the lock, so others can tell they'd be waiting for
+    // each other. Deadlock if it already is the case.
+    pub fn wait_for_lock(&mut self, client: &str, fd: usize, mode: LockMode) -> SystemResult {
+        let file_name = match descriptor(&mut self.open_files, client, fd) {
+            Ok(open_file) => open_file.file_name.clone(),
+            Err(system_result) => return system_result,
+        };
+        if self.would_deadlock(client, fd, &file_name, mode) {
+            return SystemResult::Deadlock;
         }
-        None
+        self.waiting
+            .insert((client.to_string(), fd), (file_name, mode));
+        SystemResult::Ok
     }
 
-    pub fn check_file_descriptor(&self, fd: usize) -> Option<&OpenFile> {
-        if self.open_files.len() <= fd {
-            return None;
+    pub fn stop_waiting(&mut self, client: &str, fd: usize) {
+        self.waiting.remove(&(client.to_string(), fd));
+    }
+
+    // Whether someone else's lock on the file keeps `client` from reading, with `mode` Shared,
+    // or writing, with `mode` Exclusive. Its own locks don't count.
+    fn locked_for(&self, client: &str, file_name: &str, mode: LockMode) -> bool {
+        self.open_files
+            .iter()
+            .filter(|(holder, _)| holder.as_str() != client)
+            .flat_map(|(_, table)| table.open_files.values())
+            .any(|open_file| {
+                open_file.file_name == file_name
+                    && open_file.lock.is_some_and(|held| conflicts(held, mode))
+            })
+    }
+
+    // Up to `len_to_read` bytes from the descriptor's offset, fewer at the end of the file or
+    // past MAX_CHUNK_LEN, and none past the end.
+    pub fn read_file(
+        &mut self,
+        client: &str,
+        fd: usize,
+        len_to_read: usize,
+    ) -> Result<Vec<u8>, SystemResult> {
+        let open_file = descriptor(&mut self.open_files, client, fd)?;
+        if !open_file.is_readable() {
+            return Err(SystemResult::OpenInInvalidMode);
+        }
+        let file_name = open_file.file_name.clone();
+        if self.locked_for(client, &file_name, LockMode::Shared) {
+            return Err(SystemResult::FileLocked);
+        }
+        let open_file = descriptor(&mut self.open_files, client, fd)?;
+        let file = self.files.get_mut(&open_file.file_name).unwrap();
+        file.accessed = now();
+        let content = &file.content;
+        let start = open_file.offset.min(content.len());
+        let end = start
+            .saturating_add(len_to_read.min(MAX_CHUNK_LEN))
+            .min(content.len());
+        open_file.offset = end.max(open_file.offset);
+        Ok(content[start..end].to_vec())
+    }
+
+    // Writes up to `len_to_write` bytes of `content` at the descriptor's offset, or at the end
+    // of the file in append mode, overwriting what's there and extending the file past its end.
+    // Returns the bytes written, with SystemResult::NotSaved if they couldn't be journaled.
+    pub fn write_to_file(
+        &mut self,
+        client: &str,
+        fd: usize,
+        content: Vec<u8>,
+        len_to_write: usize,
+    ) -> Result<(usize, SystemResult), SystemResult> {
+        let open_file = descriptor(&mut self.open_files, client, fd)?;
+        if !open_file.is_writable() {
+            return Err(SystemResult::OpenInInvalidMode);
+        }
+        let file_name = open_file.file_name.clone();
+        if self.locked_for(client, &file_name, LockMode::Exclusive) {
+            return Err(SystemResult::FileLocked);
+        }
+        let open_file = descriptor(&mut self.open_files, client, fd)?;
+        let file = self.files.get_mut(&open_file.file_name).unwrap();
+        if open_file.append {
+            open_file.offset = file.content.len();
+        }
+        let start = open_file.offset;
+        let content = &content[..len_to_write.min(content.len())];
+        if start.saturating_add(content.len()) > MAX_FILE_SIZE {
+            return Err(SystemResult::FileTooLarge);
+        }
+
+        let modified = now();
+        file.modified = modified;
+        splice_content(&mut file.content, start, content);
+        open_file.offset = start + content.len();
+        let name = open_file.file_name.clone();
+        let system_result = self.record(Change::WriteFile {
+            name,
+            offset: start,
+            content: content.to_vec(),
+            modified,
+        });
+        Ok((content.len(), system_result))
+    }
+
+    // A piece of an uploaded file. The first one, at offset 0, creates the file or replaces the
+    // content of one the client can write, the next ones go at its end. The permissions only
+    // apply to a new file.
+    pub fn upload_file(
+        &mut self,
+        client: &str,
+        file_name: String,
+        offset: usize,
+        content: Vec<u8>,
+        owner_permission: Permission,
+        others_permission: Permission,
+    ) -> SystemResult {
+        if let Err(system_result) = self.check_lookup(client, &file_name) {
+            return system_result;
+        }
+        match self.files.get(&file_name) {
+            Some(file) if !self.check_permission(client, &Permission::Write, file) => {
+                SystemResult::PermissionDenied
+            }
+            Some(_) if self.locked_for(client, &file_name, LockMode::Exclusive) => {
+                SystemResult::FileLocked
+            }
+            Some(_) if offset.saturating_add(content.len()) > MAX_FILE_SIZE => {
+                SystemResult::FileTooLarge
+            }
+            Some(_) if offset == 0 => {
+                let file = self.files.get_mut(&file_name).unwrap();
+                file.content = content;
+                file.modified = now();
+                let file = file.clone();
+                self.record(Change::PutFile(file))
+            }
+            // Only the end of what was uploaded so far, pieces don't leave gaps
+            Some(file) if offset != file.content.len() => SystemResult::InvalidOffset,
+            Some(_) => {
+                let file = self.files.get_mut(&file_name).unwrap();
+                let modified = now();
+                file.content.extend_from_slice(&content);
+                file.modified = modified;
+                self.record(Change::WriteFile {
+                    name: file_name,
+                    offset,
+                    content,
+                    modified,
+                })
+            }
+            None if offset != 0 => SystemResult::FileDoesntExist,
+            None => self.add_file(
+                client,
+                _File::new(
+                    file_name,
+                    content,
+                    client.to_string(),
+                    owner_permission,
+                    others_permission,
+                ),
+            ),
         }
+    }
 
-        Some(&self.open_files[fd])
+    // Up to MAX_CHUNK_LEN bytes of a file the client can read from `offset` on, without opening
+    // it. None past its end.
+    pub fn download_file(
+        &mut self,
+        client: &str,
+        file_name: &str,
+        offset: usize,
+    ) -> Result<Vec<u8>, SystemResult> {
+        self.check_lookup(client, file_name)?;
+        match self.files.get(file_name) {
+            Some(file) if !self.check_permission(client, &Permission::Read, file) => {
+                Err(SystemResult::PermissionDenied)
+            }
+            Some(_) if self.locked_for(client, file_name, LockMode::Shared) => {
+                Err(SystemResult::FileLocked)
+            }
+            Some(_) => {
+                let file = self.files.get_mut(file_name).unwrap();
+                file.accessed = now();
+                let start = offset.min(file.content.len());
+                let end = start.saturating_add(MAX_CHUNK_LEN).min(file.content.len());
+                Ok(file.content[start..end].to_vec())
+            }
+            None if self.directories.contains_key(file_name) => Err(SystemResult::IsADirectory),
+            None => Err(SystemResult::FileDoesntExist),
+        }
     }
 
-    pub fn check_writability(&self, fd: usize) -> bool {
-        (self.open_files[fd].permission as i32 & Permission::Write as i32) != 0
+    // Moves the descriptor's offset, which can go past the end of the file but not before its
+    // start, nor past the largest a file can be. Returns the new offset.
+    pub fn seek_file(
+        &mut self,
+        client: &str,
+        fd: usize,
+        offset: i64,
+        whence: Whence,
+    ) -> Result<usize, SystemResult> {
+        let open_file = descriptor(&mut self.open_files, client, fd)?;
+        let base = match whence {
+            Whence::Set => 0,
+            Whence::Current => open_file.offset,
+            Whence::End => self.files[&open_file.file_name].content.len(),
+        };
+        let new_offset = (base as i64)
+            .checked_add(offset)
+            .filter(|new_offset| (0..=MAX_FILE_SIZE as i64).contains(new_offset))
+            .ok_or(SystemResult::InvalidOffset)?;
+        open_file.offset = new_offset as usize;
+        Ok(open_file.offset)
     }
 
-    pub fn check_readability(&self, fd: usize) -> bool {
-        (self.open_files[fd].permission as i32 & Permission::Read as i32) != 0
+    pub fn filename_exists(&self, file_name: &str) -> Option<&_File> {
+        self.files.get(file_name)
     }
 
-    pub fn check_permission(&self, open_permission: &Permission, file: &_File) -> bool {
-        (*open_permission as i32 & file.owner_permission as i32) != 0
+    pub fn check_permission(
+        &self,
+        client: &str,
+        open_permission: &Permission,
+        file: &_File,
+    ) -> bool {
+        grants(
+            &file.owner,
+            file.owner_permission,
+            file.others_permission,
+            client,
+            *open_permission,
+        )
     }
 
     pub fn debug_print(&self) {
+        for (path, directory) in self.directories.iter() {
+            println!("{} {:?}", path, directory);
+        }
         for (_, file) in self.files.iter() {
             println!("{:?}", file);
         }
 
-        for open_file in self.open_files.iter() {
-            println!("{:?}", open_file)
+        for (client, table) in self.open_files.iter() {
+            for (fd, open_file) in table.open_files.iter() {
+                println!("{} {}: {:?}", client, fd, open_file)
+            }
+        }
+    }
+}
+
+impl Journaled for FileSystem {
+    type Entry = Change;
+
+    fn apply(&mut self, change: Change) {
+        match change {
+            Change::PutFile(file) => {
+                self.files.insert(file.name.clone(), file);
+            }
+            Change::WriteFile {
+                name,
+                offset,
+                content,
+                modified,
+            } => {
+                // Journals from before there was a size limit can have writes past it
+                if offset.saturating_add(content.len()) > MAX_FILE_SIZE {
+                    return;
+                }
+                if let Some(file) = self.files.get_mut(&name) {
+                    splice_content(&mut file.content, offset, &content);
+                    file.modified = modified;
+                }
+            }
+            Change::RemoveFile(name) => {
+                self.files.remove(&name);
+            }
+            Change::RenameFile { name, file } => {
+                self.files.remove(&name);
+                self.files.insert(file.name.clone(), file);
+            }
+            Change::SetPermissions {
+                name,
+                owner_permission,
+                others_permission,
+            } => {
+                if let Some(directory) = self.directories.get_mut(&name) {
+                    directory.owner_permission = owner_permission;
+                    directory.others_permission = others_permission;
+                } else if let Some(file) = self.files.get_mut(&name) {
+                    file.owner_permission = owner_permission;
+                    file.others_permission = others_permission;
+                }
+            }
+            Change::PutDirectory { path, directory } => {
+                self.directories.insert(path, directory);
+            }
+            Change::RemoveDirectory(path) => {
+                self.directories.remove(&path);
+            }
         }
     }
 }
@@ -2,22 +2,35 @@
  * Made by Guilherme Fontes
 */
 
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 
 mod file_system;
 mod option_handling;
-mod simple_user_input;
+mod path;
+mod persistence;
 mod server_handler;
 
 use file_system::FileSystem;
 use server_handler::ClientSystem;
 
-pub static SOCKET_PATH: &'static str = "/tmp/fs_socket";
+pub static SOCKET_PATH: &str = "/tmp/fs_socket";
+// Where the files and clients are kept between runs, relative to where the server is started.
+pub static DATA_PATH: &str = "so-proj-data";
 
 fn main() {
-    let file_system = Arc::new(Mutex::new(FileSystem::new()));
-
-    let client_system = Arc::new(Mutex::new(ClientSystem::new()));
+    let file_system = match FileSystem::restore(Path::new(DATA_PATH)) {
+        Ok(_file_system) => Arc::new(Mutex::new(_file_system)),
+        Err(e) => panic!(
+            "Not able to restore the file system from {}: {}",
+            DATA_PATH, e
+        ),
+    };
+
+    let client_system = match ClientSystem::restore(Path::new(DATA_PATH)) {
+        Ok(_client_system) => Arc::new(Mutex::new(_client_system)),
+        Err(e) => panic!("Not able to restore the clients from {}: {}", DATA_PATH, e),
+    };
 
     server_handler::await_connections(SOCKET_PATH, &file_system, &client_system);
 
@@ -1,94 +1,1041 @@
 use crate::file_system::*;
+use crate::path;
+use crate::server_handler::Session;
 
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-pub fn create_file(file_system: &Arc<Mutex<FileSystem>>, args: Vec<String>) -> SystemResult {
-    /* get items from args vector: */
-    let name = get_string(&args, 0);
-    let content = get_string(&args, 1);
-    let owner_permission = get_permission(&args, 2);
-    let others_permission = get_permission(&args, 3);
-
-    let new_file = _File::new(name, content, owner_permission, others_permission);
-    file_system.lock().unwrap().add_file(new_file)
+pub fn create_file(
+    file_system: &Arc<Mutex<FileSystem>>,
+    session: &Session,
+    name: &str,
+    content: Vec<u8>,
+    owner_permission: Permission,
+    others_permission: Permission,
+) -> SystemResult {
+    let new_file = _File::new(
+        get_path(name, session),
+        content,
+        session.client.clone(),
+        owner_permission,
+        others_permission,
+    );
+    file_system
+        .lock()
+        .unwrap()
+        .add_file(&session.client, new_file)
 }
 
-pub fn delete_file(file_system: &Arc<Mutex<FileSystem>>, args: Vec<String>) -> SystemResult {
-    let name = get_string(&args, 0);
+pub fn delete_file(
+    file_system: &Arc<Mutex<FileSystem>>,
+    session: &Session,
+    name: &str,
+) -> SystemResult {
+    let name = get_path(name, session);
 
-    file_system.lock().unwrap().delete_file(name)
+    file_system
+        .lock()
+        .unwrap()
+        .delete_file(&session.client, name)
 }
 
-pub fn rename_file(file_system: &Arc<Mutex<FileSystem>>, args: Vec<String>) -> SystemResult {
-    let name = get_string(&args, 0);
-    let new_name = get_string(&args, 1);
+pub fn rename_file(
+    file_system: &Arc<Mutex<FileSystem>>,
+    session: &Session,
+    name: &str,
+    new_name: &str,
+) -> SystemResult {
+    let name = get_path(name, session);
+    let new_name = get_path(new_name, session);
 
-    file_system.lock().unwrap().rename_file(name, new_name)
+    file_system
+        .lock()
+        .unwrap()
+        .rename_file(&session.client, name, new_name)
 }
 
-pub fn open_file(file_system: &Arc<Mutex<FileSystem>>, args: Vec<String>) -> SystemResult {
-    let name = get_string(&args, 0);
-    let open_permission = get_permission(&args, 1);
+pub fn change_permissions(
+    file_system: &Arc<Mutex<FileSystem>>,
+    session: &Session,
+    name: &str,
+    owner_permission: Permission,
+    others_permission: Permission,
+) -> SystemResult {
+    let name = get_path(name, session);
 
-    file_system.lock().unwrap().open_file(name, open_permission)
+    file_system.lock().unwrap().change_permissions(
+        &session.client,
+        &name,
+        owner_permission,
+        others_permission,
+    )
 }
 
-pub fn close_file(file_system: &Arc<Mutex<FileSystem>>, args: Vec<String>) -> SystemResult {
-    let fd = get_usize(&args, 0);
+pub fn open_file(
+    file_system: &Arc<Mutex<FileSystem>>,
+    session: &Session,
+    name: &str,
+    open_permission: Permission,
+    append: bool,
+) -> Result<usize, SystemResult> {
+    let name = get_path(name, session);
 
-    file_system.lock().unwrap().close_file(fd)
+    file_system
+        .lock()
+        .unwrap()
+        .open_file(&session.client, name, open_permission, append)
 }
 
-pub fn read_file(file_system: &Arc<Mutex<FileSystem>>, args: Vec<String>) -> SystemResult {
-    let fd = get_usize(&args, 0);
-    let len_to_read = get_usize(&args, 1);
+pub fn close_file(
+    file_system: &Arc<Mutex<FileSystem>>,
+    session: &Session,
+    fd: usize,
+) -> SystemResult {
+    file_system.lock().unwrap().close_file(&session.client, fd)
+}
 
-    file_system.lock().unwrap().read_file(fd, len_to_read)
+pub fn read_file(
+    file_system: &Arc<Mutex<FileSystem>>,
+    session: &Session,
+    fd: usize,
+    len_to_read: usize,
+) -> Result<Vec<u8>, SystemResult> {
+    file_system
+        .lock()
+        .unwrap()
+        .read_file(&session.client, fd, len_to_read)
 }
 
-pub fn write_to_file(file_system: &Arc<Mutex<FileSystem>>, args: Vec<String>) -> SystemResult {
-    let fd = get_usize(&args, 0);
-    let content = get_string(&args, 1);
-    let len_to_write = get_usize(&args, 2);
+pub fn write_to_file(
+    file_system: &Arc<Mutex<FileSystem>>,
+    session: &Session,
+    fd: usize,
+    content: Vec<u8>,
+    len_to_write: usize,
+) -> Result<(usize, SystemResult), SystemResult> {
+    file_system
+        .lock()
+        .unwrap()
+        .write_to_file(&session.client, fd, content, len_to_write)
+}
 
+pub fn seek_file(
+    file_system: &Arc<Mutex<FileSystem>>,
+    session: &Session,
+    fd: usize,
+    offset: i64,
+    whence: Whence,
+) -> Result<usize, SystemResult> {
     file_system
         .lock()
         .unwrap()
-        .write_to_file(fd, content, len_to_write)
+        .seek_file(&session.client, fd, offset, whence)
 }
 
-fn get_usize(args: &Vec<String>, index: usize) -> usize {
-    match args.get(index) {
-        Some(_goes_into_name) => match _goes_into_name.trim().parse() {
-            Ok(_gets_returned) => _gets_returned,
-            Err(e) => panic!(
-                "args vector should have a number at index {index}!: {error}",
-                index = index,
-                error = e
-            ),
-        },
-        None => panic!("args vector should have name at index {}!", index),
+// Waits for the lock while the timeout lasts, but not if the clients in the way are waiting for
+// this one.
+pub fn lock_file(
+    file_system: &Arc<Mutex<FileSystem>>,
+    session: &Session,
+    fd: usize,
+    mode: LockMode,
+    timeout: Option<u64>,
+) -> SystemResult {
+    let deadline = timeout.map(|timeout| Instant::now() + Duration::from_millis(timeout));
+    let mut file_system = file_system.lock().unwrap();
+    loop {
+        let system_result = file_system.lock_file(&session.client, fd, mode);
+        if system_result != SystemResult::FileLocked {
+            return system_result;
+        }
+        let remaining = match deadline {
+            Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+            None => Duration::ZERO,
+        };
+        if remaining.is_zero() {
+            return SystemResult::FileLocked;
+        }
+        let system_result = file_system.wait_for_lock(&session.client, fd, mode);
+        if system_result != SystemResult::Ok {
+            return system_result;
+        }
+        file_system = wait_for_release(file_system, remaining);
+        file_system.stop_waiting(&session.client, fd);
     }
 }
 
-fn get_string(args: &Vec<String>, index: usize) -> String {
-    match args.get(index) {
-        Some(_goes_into_name) => _goes_into_name.to_string(),
-        None => panic!("args vector should have name at index {}!", index),
+pub fn unlock_file(
+    file_system: &Arc<Mutex<FileSystem>>,
+    session: &Session,
+    fd: usize,
+) -> SystemResult {
+    file_system.lock().unwrap().unlock_file(&session.client, fd)
+}
+
+pub fn upload_file(
+    file_system: &Arc<Mutex<FileSystem>>,
+    session: &Session,
+    name: &str,
+    offset: usize,
+    content: Vec<u8>,
+    owner_permission: Permission,
+    others_permission: Permission,
+) -> SystemResult {
+    let name = get_path(name, session);
+
+    file_system.lock().unwrap().upload_file(
+        &session.client,
+        name,
+        offset,
+        content,
+        owner_permission,
+        others_permission,
+    )
+}
+
+pub fn download_file(
+    file_system: &Arc<Mutex<FileSystem>>,
+    session: &Session,
+    name: &str,
+    offset: usize,
+) -> Result<Vec<u8>, SystemResult> {
+    let name = get_path(name, session);
+
+    file_system
+        .lock()
+        .unwrap()
+        .download_file(&session.client, &name, offset)
+}
+
+pub fn make_directory(
+    file_system: &Arc<Mutex<FileSystem>>,
+    session: &Session,
+    name: &str,
+    owner_permission: Permission,
+    others_permission: Permission,
+) -> SystemResult {
+    let name = get_path(name, session);
+
+    file_system.lock().unwrap().make_directory(
+        &session.client,
+        name,
+        owner_permission,
+        others_permission,
+    )
+}
+
+pub fn remove_directory(
+    file_system: &Arc<Mutex<FileSystem>>,
+    session: &Session,
+    name: &str,
+) -> SystemResult {
+    let name = get_path(name, session);
+
+    file_system
+        .lock()
+        .unwrap()
+        .remove_directory(&session.client, &name)
+}
+
+pub fn stat(
+    file_system: &Arc<Mutex<FileSystem>>,
+    session: &Session,
+    name: &str,
+) -> Result<FileStat, SystemResult> {
+    let name = get_path(name, session);
+
+    file_system.lock().unwrap().stat(&session.client, &name)
+}
+
+// The working directory when no path is given, everything in it when there's no pattern.
+pub fn list(
+    file_system: &Arc<Mutex<FileSystem>>,
+    session: &Session,
+    name: Option<&str>,
+    pattern: Option<&str>,
+) -> Result<Vec<FileStat>, SystemResult> {
+    let name = match name {
+        Some(name) => get_path(name, session),
+        None => session.cwd.clone(),
+    };
+
+    file_system
+        .lock()
+        .unwrap()
+        .list(&session.client, &name, pattern)
+}
+
+// The working directory when no path is given.
+pub fn list_directory(
+    file_system: &Arc<Mutex<FileSystem>>,
+    session: &Session,
+    name: Option<&str>,
+) -> Result<Vec<String>, SystemResult> {
+    let name = match name {
+        Some(name) => get_path(name, session),
+        None => session.cwd.clone(),
+    };
+
+    file_system
+        .lock()
+        .unwrap()
+        .list_directory(&session.client, &name)
+}
+
+pub fn change_directory(
+    file_system: &Arc<Mutex<FileSystem>>,
+    session: &mut Session,
+    name: &str,
+) -> SystemResult {
+    let name = get_path(name, session);
+
+    let system_result = file_system
+        .lock()
+        .unwrap()
+        .change_directory(&session.client, &name);
+    if system_result == SystemResult::Ok {
+        session.cwd = name;
     }
+    system_result
+}
+
+// A path relative to the session's working directory, as an absolute one.
+fn get_path(name: &str, session: &Session) -> String {
+    path::resolve(&session.cwd, name)
 }
 
-fn get_permission(args: &Vec<String>, index: usize) -> Permission {
-    match args.get(index) {
-        Some(element) => {
-            match element.as_str() {
-                "Write" => Permission::Write,
-                "Read" => Permission::Read,
-                "ReadWrite" => Permission::ReadWrite,
-                "None" => Permission::None,
-                _ => panic!("FSReturn not compatible!"),
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    // Homes for "al" and "bo", and files "a" and "b" in al's that anyone can read and write.
+    fn file_system() -> Arc<Mutex<FileSystem>> {
+        let mut file_system = FileSystem::new();
+        for client in ["al", "bo"] {
+            file_system.create_home(client);
+        }
+        for name in ["/home/al/a", "/home/al/b"] {
+            let file = _File::new(
+                name.to_string(),
+                Vec::new(),
+                "al".to_string(),
+                Permission::ReadWrite,
+                Permission::ReadWrite,
+            );
+            assert_eq!(file_system.add_file("al", file), SystemResult::Ok);
+        }
+        Arc::new(Mutex::new(file_system))
+    }
+
+    fn session(client: &str) -> Session {
+        Session {
+            client: client.to_string(),
+            cwd: "/home/al".to_string(),
+        }
+    }
+
+    fn open(file_system: &Arc<Mutex<FileSystem>>, session: &Session, name: &str) -> usize {
+        open_file(file_system, session, name, Permission::ReadWrite, false).unwrap()
+    }
+
+    #[test]
+    fn shared_locks_keep_out_exclusive_ones() {
+        let file_system = file_system();
+        let (al, bo) = (session("al"), session("bo"));
+        let al_fd = open(&file_system, &al, "a");
+        let bo_fd = open(&file_system, &bo, "a");
+
+        assert_eq!(
+            lock_file(&file_system, &al, al_fd, LockMode::Shared, None),
+            SystemResult::Ok
+        );
+        assert_eq!(
+            lock_file(&file_system, &bo, bo_fd, LockMode::Shared, None),
+            SystemResult::Ok
+        );
+        assert_eq!(
+            lock_file(&file_system, &bo, bo_fd, LockMode::Exclusive, None),
+            SystemResult::FileLocked
+        );
+
+        assert_eq!(unlock_file(&file_system, &al, al_fd), SystemResult::Ok);
+        assert_eq!(
+            lock_file(&file_system, &bo, bo_fd, LockMode::Exclusive, None),
+            SystemResult::Ok
+        );
+        assert_eq!(
+            lock_file(&file_system, &al, al_fd, LockMode::Shared, None),
+            SystemResult::FileLocked
+        );
+        assert_eq!(
+            read_file(&file_system, &al, al_fd, 1),
+            Err(SystemResult::FileLocked)
+        );
+    }
+
+    #[test]
+    fn waiting_stops_when_the_timeout_runs_out() {
+        let file_system = file_system();
+        let (al, bo) = (session("al"), session("bo"));
+        let al_fd = open(&file_system, &al, "a");
+        let bo_fd = open(&file_system, &bo, "a");
+        lock_file(&file_system, &al, al_fd, LockMode::Exclusive, None);
+
+        let start = Instant::now();
+        assert_eq!(
+            lock_file(&file_system, &bo, bo_fd, LockMode::Shared, Some(100)),
+            SystemResult::FileLocked
+        );
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    // Each client holds one file and waits for the other's. Whichever starts waiting second is
+    // refused and gives its lock up, so the first one gets what it waits for.
+    #[test]
+    fn waiting_for_each_other_is_a_deadlock() {
+        let file_system = file_system();
+        let (al, bo) = (session("al"), session("bo"));
+        let al_fds = (open(&file_system, &al, "a"), open(&file_system, &al, "b"));
+        let bo_fds = (open(&file_system, &bo, "a"), open(&file_system, &bo, "b"));
+        lock_file(&file_system, &al, al_fds.0, LockMode::Exclusive, None);
+        lock_file(&file_system, &bo, bo_fds.1, LockMode::Exclusive, None);
+
+        let start = Instant::now();
+        let wait_for = |session: Session, (held, wanted): (usize, usize)| {
+            let file_system = Arc::clone(&file_system);
+            thread::spawn(move || {
+                let system_result = lock_file(
+                    &file_system,
+                    &session,
+                    wanted,
+                    LockMode::Exclusive,
+                    Some(5000),
+                );
+                if system_result == SystemResult::Deadlock {
+                    unlock_file(&file_system, &session, held);
+                }
+                system_result
+            })
+        };
+        let al_waiting = wait_for(al, al_fds);
+        let bo_waiting = wait_for(bo, (bo_fds.1, bo_fds.0));
+
+        let mut system_results = [al_waiting.join().unwrap(), bo_waiting.join().unwrap()];
+        system_results.sort_by_key(|system_result| *system_result as i32);
+        assert_eq!(system_results, [SystemResult::Deadlock, SystemResult::Ok]);
+        assert!(start.elapsed() < Duration::from_millis(5000));
+    }
+
+    // Two sessions of al share its descriptors and so its locks, bo is kept out of both.
+    #[test]
+    fn locks_are_shared_by_every_session_of_a_client() {
+        let file_system = file_system();
+        let (first, second, bo) = (session("al"), session("al"), session("bo"));
+        let fd = open(&file_system, &first, "a");
+        let other_fd = open(&file_system, &second, "a");
+        let bo_fd = open(&file_system, &bo, "a");
+        lock_file(&file_system, &first, fd, LockMode::Exclusive, None);
+
+        assert_eq!(
+            write_to_file(&file_system, &second, other_fd, b"al".to_vec(), 2),
+            Ok((2, SystemResult::Ok))
+        );
+        assert_eq!(
+            read_file(&file_system, &bo, bo_fd, 2),
+            Err(SystemResult::FileLocked)
+        );
+        // Waiting for its own lock would never end, whichever session holds it
+        let start = Instant::now();
+        assert_eq!(
+            lock_file(
+                &file_system,
+                &second,
+                other_fd,
+                LockMode::Shared,
+                Some(5000)
+            ),
+            SystemResult::Deadlock
+        );
+        assert!(start.elapsed() < Duration::from_millis(5000));
+
+        assert_eq!(unlock_file(&file_system, &second, fd), SystemResult::Ok);
+        assert_eq!(read_file(&file_system, &bo, bo_fd, 2), Ok(b"al".to_vec()));
+    }
+
+    #[test]
+    fn closing_releases_the_lock() {
+        let file_system = file_system();
+        let (al, bo) = (session("al"), session("bo"));
+        let al_fd = open(&file_system, &al, "a");
+        let bo_fd = open(&file_system, &bo, "a");
+        lock_file(&file_system, &al, al_fd, LockMode::Exclusive, None);
+
+        let bo_waiting = {
+            let file_system = Arc::clone(&file_system);
+            thread::spawn(move || {
+                lock_file(&file_system, &bo, bo_fd, LockMode::Exclusive, Some(5000))
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        let start = Instant::now();
+        assert_eq!(close_file(&file_system, &al, al_fd), SystemResult::Ok);
+        assert_eq!(bo_waiting.join().unwrap(), SystemResult::Ok);
+        assert!(start.elapsed() < Duration::from_millis(5000));
+
+        // And so does ending the session
+        let al_fd = open(&file_system, &al, "b");
+        lock_file(&file_system, &al, al_fd, LockMode::Exclusive, None);
+        file_system.lock().unwrap().close_all("al");
+        let bo_fd = open(&file_system, &session("bo"), "b");
+        assert_eq!(
+            lock_file(
+                &file_system,
+                &session("bo"),
+                bo_fd,
+                LockMode::Exclusive,
+                None
+            ),
+            SystemResult::Ok
+        );
+    }
+
+    #[test]
+    fn owner_and_others_get_their_own_permissions() {
+        let file_system = file_system();
+        let (al, bo) = (session("al"), session("bo"));
+        assert_eq!(
+            create_file(
+                &file_system,
+                &al,
+                "c",
+                b"secret".to_vec(),
+                Permission::Read,
+                Permission::None
+            ),
+            SystemResult::Ok
+        );
+        // The owner's bits don't give it Write, the others' give nothing
+        assert_eq!(
+            open_file(&file_system, &al, "c", Permission::Write, false),
+            Err(SystemResult::PermissionDenied)
+        );
+        assert!(open_file(&file_system, &al, "c", Permission::Read, false).is_ok());
+        assert_eq!(
+            open_file(&file_system, &bo, "c", Permission::Read, false),
+            Err(SystemResult::PermissionDenied)
+        );
+
+        // Only the owner changes them
+        assert_eq!(
+            change_permissions(
+                &file_system,
+                &bo,
+                "c",
+                Permission::ReadWrite,
+                Permission::ReadWrite
+            ),
+            SystemResult::PermissionDenied
+        );
+        assert_eq!(
+            change_permissions(
+                &file_system,
+                &al,
+                "c",
+                Permission::ReadWrite,
+                Permission::Read
+            ),
+            SystemResult::Ok
+        );
+        let bo_fd = open_file(&file_system, &bo, "c", Permission::Read, false).unwrap();
+        assert_eq!(
+            read_file(&file_system, &bo, bo_fd, 6),
+            Ok(b"secret".to_vec())
+        );
+        assert_eq!(
+            open_file(&file_system, &bo, "c", Permission::ReadWrite, false),
+            Err(SystemResult::PermissionDenied)
+        );
+        assert!(open_file(&file_system, &al, "c", Permission::Write, false).is_ok());
+    }
+
+    #[test]
+    fn only_the_owner_deletes_or_renames_a_file() {
+        let file_system = file_system();
+        let (al, bo) = (session("al"), session("bo"));
+        // Anyone can write in the directory, but not to someone else's files in it
+        assert_eq!(
+            make_directory(
+                &file_system,
+                &al,
+                "shared",
+                Permission::ReadWrite,
+                Permission::ReadWrite
+            ),
+            SystemResult::Ok
+        );
+        assert_eq!(
+            create_file(
+                &file_system,
+                &bo,
+                "shared/bo",
+                Vec::new(),
+                Permission::ReadWrite,
+                Permission::ReadWrite
+            ),
+            SystemResult::Ok
+        );
+        assert_eq!(
+            delete_file(&file_system, &al, "shared/bo"),
+            SystemResult::PermissionDenied
+        );
+        assert_eq!(
+            rename_file(&file_system, &al, "shared/bo", "shared/al"),
+            SystemResult::PermissionDenied
+        );
+        assert_eq!(
+            rename_file(&file_system, &bo, "shared/bo", "shared/bo2"),
+            SystemResult::Ok
+        );
+        assert_eq!(
+            delete_file(&file_system, &bo, "shared/bo2"),
+            SystemResult::Ok
+        );
+
+        // Nor can others touch files in al's home, which they can only read
+        assert_eq!(
+            delete_file(&file_system, &bo, "a"),
+            SystemResult::PermissionDenied
+        );
+        assert_eq!(
+            change_permissions(&file_system, &bo, "a", Permission::None, Permission::None),
+            SystemResult::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn paths_are_relative_to_the_working_directory() {
+        let file_system = file_system();
+        let mut al = session("al");
+        assert_eq!(
+            make_directory(
+                &file_system,
+                &al,
+                "docs",
+                Permission::ReadWrite,
+                Permission::Read
+            ),
+            SystemResult::Ok
+        );
+        assert_eq!(
+            change_directory(&file_system, &mut al, "docs"),
+            SystemResult::Ok
+        );
+        assert_eq!(al.cwd, "/home/al/docs");
+        assert_eq!(
+            create_file(
+                &file_system,
+                &al,
+                "./notes",
+                Vec::new(),
+                Permission::ReadWrite,
+                Permission::None
+            ),
+            SystemResult::Ok
+        );
+        assert_eq!(
+            list_directory(&file_system, &al, Some("..")),
+            Ok(vec!["a".to_string(), "b".to_string(), "docs/".to_string()])
+        );
+        assert_eq!(
+            list_directory(&file_system, &al, None),
+            Ok(vec!["notes".to_string()])
+        );
+        assert!(open_file(
+            &file_system,
+            &al,
+            "/home/al/docs/notes",
+            Permission::Read,
+            false
+        )
+        .is_ok());
+
+        // ".." above the root stays there, and the working directory only changes when it can
+        assert_eq!(
+            change_directory(&file_system, &mut al, "../../../../.."),
+            SystemResult::Ok
+        );
+        assert_eq!(al.cwd, "/");
+        for (name, system_result) in [
+            ("home/al/a", SystemResult::NotADirectory),
+            ("home/cy", SystemResult::FileDoesntExist),
+        ] {
+            assert_eq!(change_directory(&file_system, &mut al, name), system_result);
+            assert_eq!(al.cwd, "/");
+        }
+    }
+
+    #[test]
+    fn homes_belong_to_their_client() {
+        let file_system = file_system();
+        let (mut al, mut bo) = (session("al"), session("bo"));
+        // Others can look in al's home but not write to it, and no one writes in / or /home
+        assert_eq!(
+            change_directory(&file_system, &mut bo, "/home/al"),
+            SystemResult::Ok
+        );
+        assert_eq!(
+            list_directory(&file_system, &bo, None),
+            Ok(vec!["a".to_string(), "b".to_string()])
+        );
+        for (session, name) in [(&bo, "/home/al/c"), (&al, "/c"), (&al, "/home/c")] {
+            assert_eq!(
+                create_file(
+                    &file_system,
+                    session,
+                    name,
+                    Vec::new(),
+                    Permission::ReadWrite,
+                    Permission::None
+                ),
+                SystemResult::PermissionDenied,
+                "{}",
+                name
+            );
+        }
+        assert_eq!(
+            make_directory(
+                &file_system,
+                &bo,
+                "/home/bo/d",
+                Permission::ReadWrite,
+                Permission::None
+            ),
+            SystemResult::Ok
+        );
+        // A directory only its owner can read can't be listed or entered by others
+        assert_eq!(
+            list_directory(&file_system, &al, Some("/home/bo/d")),
+            Err(SystemResult::PermissionDenied)
+        );
+        assert_eq!(
+            change_directory(&file_system, &mut al, "/home/bo/d"),
+            SystemResult::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn only_empty_directories_are_removed() {
+        let file_system = file_system();
+        let al = session("al");
+        make_directory(
+            &file_system,
+            &al,
+            "docs",
+            Permission::ReadWrite,
+            Permission::Read,
+        );
+        create_file(
+            &file_system,
+            &al,
+            "docs/notes",
+            Vec::new(),
+            Permission::ReadWrite,
+            Permission::None,
+        );
+        assert_eq!(
+            remove_directory(&file_system, &al, "docs"),
+            SystemResult::DirectoryNotEmpty
+        );
+        assert_eq!(
+            remove_directory(&file_system, &al, "a"),
+            SystemResult::NotADirectory
+        );
+        assert_eq!(
+            delete_file(&file_system, &al, "docs"),
+            SystemResult::IsADirectory
+        );
+        assert_eq!(
+            delete_file(&file_system, &al, "docs/notes"),
+            SystemResult::Ok
+        );
+        assert_eq!(
+            remove_directory(&file_system, &al, "docs"),
+            SystemResult::Ok
+        );
+        assert_eq!(
+            remove_directory(&file_system, &session("bo"), "/home/al"),
+            SystemResult::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn every_descriptor_has_its_own_offset() {
+        let file_system = file_system();
+        let al = session("al");
+        let fd = open(&file_system, &al, "a");
+        let other_fd = open(&file_system, &al, "a");
+        assert_eq!(
+            write_to_file(&file_system, &al, fd, b"hello".to_vec(), 5),
+            Ok((5, SystemResult::Ok))
+        );
+        // Nothing left to read at the end, the other descriptor is still at the start
+        assert_eq!(read_file(&file_system, &al, fd, 5), Ok(Vec::new()));
+        assert_eq!(
+            read_file(&file_system, &al, other_fd, 3),
+            Ok(b"hel".to_vec())
+        );
+        assert_eq!(
+            read_file(&file_system, &al, other_fd, 10),
+            Ok(b"lo".to_vec())
+        );
+
+        assert_eq!(seek_file(&file_system, &al, fd, -2, Whence::Current), Ok(3));
+        write_to_file(&file_system, &al, fd, b"p!".to_vec(), 2).unwrap();
+        assert_eq!(seek_file(&file_system, &al, fd, 0, Whence::Set), Ok(0));
+        assert_eq!(read_file(&file_system, &al, fd, 10), Ok(b"help!".to_vec()));
+
+        // Writing past the end leaves a gap of zeros
+        assert_eq!(seek_file(&file_system, &al, fd, 2, Whence::End), Ok(7));
+        write_to_file(&file_system, &al, fd, b"x".to_vec(), 1).unwrap();
+        seek_file(&file_system, &al, fd, 0, Whence::Set).unwrap();
+        assert_eq!(
+            read_file(&file_system, &al, fd, 10),
+            Ok(b"help!\0\0x".to_vec())
+        );
+
+        for (offset, whence) in [
+            (-1, Whence::Set),
+            (-9, Whence::End),
+            (MAX_FILE_SIZE as i64 + 1, Whence::Set),
+            (i64::MAX, Whence::Current),
+        ] {
+            assert_eq!(
+                seek_file(&file_system, &al, fd, offset, whence),
+                Err(SystemResult::InvalidOffset)
+            );
+        }
+        // A failed seek leaves the offset alone
+        assert_eq!(seek_file(&file_system, &al, fd, 0, Whence::Current), Ok(8));
+    }
+
+    #[test]
+    fn appending_writes_at_the_end() {
+        let file_system = file_system();
+        let al = session("al");
+        let fd = open(&file_system, &al, "a");
+        write_to_file(&file_system, &al, fd, b"log".to_vec(), 3).unwrap();
+        let append_fd = open_file(&file_system, &al, "a", Permission::ReadWrite, true).unwrap();
+
+        // Wherever the offset was moved to, and however the file changed since
+        seek_file(&file_system, &al, append_fd, 0, Whence::Set).unwrap();
+        write_to_file(&file_system, &al, append_fd, b" one".to_vec(), 4).unwrap();
+        write_to_file(&file_system, &al, fd, b" two and".to_vec(), 8).unwrap();
+        write_to_file(&file_system, &al, append_fd, b" three".to_vec(), 6).unwrap();
+        assert_eq!(
+            seek_file(&file_system, &al, append_fd, 0, Whence::Current),
+            Ok(17)
+        );
+        seek_file(&file_system, &al, fd, 0, Whence::Set).unwrap();
+        assert_eq!(
+            read_file(&file_system, &al, fd, 20),
+            Ok(b"log two and three".to_vec())
+        );
+    }
+
+    #[test]
+    fn files_stop_at_max_file_size() {
+        let file_system = file_system();
+        let al = session("al");
+        let fd = open(&file_system, &al, "a");
+        seek_file(&file_system, &al, fd, MAX_FILE_SIZE as i64 - 1, Whence::Set).unwrap();
+        assert_eq!(
+            write_to_file(&file_system, &al, fd, b"xy".to_vec(), 2),
+            Err(SystemResult::FileTooLarge)
+        );
+        assert_eq!(
+            write_to_file(&file_system, &al, fd, b"x".to_vec(), 1),
+            Ok((1, SystemResult::Ok))
+        );
+    }
+
+    // Every byte value, over more than a piece.
+    fn binary_content() -> Vec<u8> {
+        (0..MAX_CHUNK_LEN + 300).map(|i| (i % 256) as u8).collect()
+    }
+
+    fn upload(
+        file_system: &Arc<Mutex<FileSystem>>,
+        session: &Session,
+        name: &str,
+        offset: usize,
+        content: &[u8],
+    ) -> SystemResult {
+        upload_file(
+            file_system,
+            session,
+            name,
+            offset,
+            content.to_vec(),
+            Permission::ReadWrite,
+            Permission::None,
+        )
+    }
+
+    #[test]
+    fn uploads_and_downloads_go_in_pieces() {
+        let file_system = file_system();
+        let al = session("al");
+        let content = binary_content();
+        let (first, rest) = content.split_at(MAX_CHUNK_LEN);
+
+        assert_eq!(upload(&file_system, &al, "c", 0, first), SystemResult::Ok);
+        // Pieces only go at the end of what was uploaded so far
+        assert_eq!(
+            upload(&file_system, &al, "c", MAX_CHUNK_LEN + 1, rest),
+            SystemResult::InvalidOffset
+        );
+        assert_eq!(
+            upload(&file_system, &al, "c", MAX_CHUNK_LEN, rest),
+            SystemResult::Ok
+        );
+
+        let mut downloaded = Vec::new();
+        loop {
+            let piece = download_file(&file_system, &al, "c", downloaded.len()).unwrap();
+            assert!(piece.len() <= MAX_CHUNK_LEN);
+            if piece.is_empty() {
+                break;
             }
+            downloaded.extend(piece);
+        }
+        assert_eq!(downloaded, content);
+
+        // Reads stop at a piece too
+        let fd = open(&file_system, &al, "c");
+        assert_eq!(
+            read_file(&file_system, &al, fd, content.len()).map(|read| read.len()),
+            Ok(MAX_CHUNK_LEN)
+        );
+    }
+
+    #[test]
+    fn uploading_at_the_start_replaces_the_file() {
+        let file_system = file_system();
+        let (al, bo) = (session("al"), session("bo"));
+        assert_eq!(
+            upload(&file_system, &al, "c", 3, b"late"),
+            SystemResult::FileDoesntExist
+        );
+        upload(&file_system, &al, "c", 0, b"first version");
+        assert_eq!(
+            upload(&file_system, &al, "c", 0, b"\0\xff"),
+            SystemResult::Ok
+        );
+        assert_eq!(
+            download_file(&file_system, &al, "c", 0),
+            Ok(b"\0\xff".to_vec())
+        );
+        assert_eq!(download_file(&file_system, &al, "c", 5), Ok(Vec::new()));
+
+        // The permissions of the first upload stay, others can't read or replace it
+        assert_eq!(
+            download_file(&file_system, &bo, "/home/al/c", 0),
+            Err(SystemResult::PermissionDenied)
+        );
+        assert_eq!(
+            upload(&file_system, &bo, "/home/al/c", 0, b"mine"),
+            SystemResult::PermissionDenied
+        );
+        assert_eq!(
+            download_file(&file_system, &al, "/home", 0),
+            Err(SystemResult::IsADirectory)
+        );
+    }
+
+    #[test]
+    fn stat_tells_size_owner_and_who_has_it_open() {
+        let file_system = file_system();
+        let (al, bo) = (session("al"), session("bo"));
+        let fd = open(&file_system, &al, "a");
+        write_to_file(&file_system, &al, fd, b"hello".to_vec(), 5).unwrap();
+        open(&file_system, &bo, "a");
+
+        // Anyone who can reach it, with a path relative to their working directory
+        let file = stat(&file_system, &bo, "a").unwrap();
+        assert_eq!(file.name, "/home/al/a");
+        assert!(!file.is_directory);
+        assert_eq!(file.size, 5);
+        assert_eq!(file.owner, "al");
+        assert_eq!(
+            (file.owner_permission, file.others_permission),
+            (Permission::ReadWrite, Permission::ReadWrite)
+        );
+        assert_eq!(file.open_count, 2);
+        assert!(file.created <= file.modified.unwrap());
+
+        let home = stat(&file_system, &al, ".").unwrap();
+        assert!(home.is_directory);
+        assert_eq!((home.size, home.modified, home.open_count), (0, None, 0));
+        assert_eq!(
+            stat(&file_system, &al, "c"),
+            Err(SystemResult::FileDoesntExist)
+        );
+    }
+
+    #[test]
+    fn listing_filters_names_with_a_glob() {
+        let file_system = file_system();
+        let al = session("al");
+        for name in ["report-1", "report-2b", "notes.txt", "todo.txt"] {
+            create_file(
+                &file_system,
+                &al,
+                name,
+                Vec::new(),
+                Permission::ReadWrite,
+                Permission::None,
+            );
         }
-        None => panic!("args vector should have name at index {}!", index),
+        make_directory(
+            &file_system,
+            &al,
+            "old.txt",
+            Permission::ReadWrite,
+            Permission::None,
+        );
+        let names = |name: Option<&str>, pattern: Option<&str>| -> Vec<String> {
+            list(&file_system, &al, name, pattern)
+                .unwrap()
+                .into_iter()
+                .map(|stat| stat.name)
+                .collect()
+        };
+
+        assert_eq!(names(None, None).len(), 7);
+        assert_eq!(
+            names(None, Some("*.txt")),
+            [
+                "/home/al/notes.txt",
+                "/home/al/old.txt",
+                "/home/al/todo.txt"
+            ]
+        );
+        assert_eq!(names(None, Some("report-[0-9]")), ["/home/al/report-1"]);
+        assert_eq!(names(None, Some("report-[!a-z]?")), ["/home/al/report-2b"]);
+        assert_eq!(names(None, Some("[ab]")), ["/home/al/a", "/home/al/b"]);
+        assert!(names(Some("/home"), Some("*.txt")).is_empty());
+        assert_eq!(names(Some("/home"), Some("?o")), ["/home/bo"]);
+
+        // A directory only its owner can read can't be listed by others
+        assert_eq!(
+            list(&file_system, &session("bo"), Some("old.txt"), None),
+            Err(SystemResult::PermissionDenied)
+        );
+        assert_eq!(
+            list(&file_system, &al, Some("a"), None),
+            Err(SystemResult::NotADirectory)
+        );
     }
 }
@@ -0,0 +1,136 @@
+// Paths in the virtual file system. Files and directories are kept by their absolute path, always
+// "/" separated with no "." or ".." left and no trailing "/" other than the root itself.
+
+pub static ROOT: &str = "/";
+pub static HOME: &str = "/home";
+
+// The absolute path `path` names when the working directory is `cwd`. ".." above the root stays
+// at the root.
+pub fn resolve(cwd: &str, path: &str) -> String {
+    let mut parts: Vec<&str> = if path.starts_with('/') {
+        Vec::new()
+    } else {
+        cwd.split('/').filter(|part| !part.is_empty()).collect()
+    };
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    format!("/{}", parts.join("/"))
+}
+
+// The directory holding `path`, None for the root.
+pub fn parent(path: &str) -> Option<&str> {
+    match path.rfind('/') {
+        _ if path == ROOT => None,
+        Some(0) => Some(ROOT),
+        Some(i) => Some(&path[..i]),
+        None => None,
+    }
+}
+
+// Every directory above `path`, from the root down.
+pub fn ancestors(path: &str) -> Vec<&str> {
+    let mut ancestors = Vec::new();
+    let mut current = path;
+    while let Some(parent) = parent(current) {
+        ancestors.push(parent);
+        current = parent;
+    }
+    ancestors.reverse();
+    ancestors
+}
+
+pub fn base_name(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(i) => &path[i + 1..],
+        None => path,
+    }
+}
+
+pub fn home(client: &str) -> String {
+    format!("{}/{}", HOME, client)
+}
+
+// Whether `name` matches the shell style `pattern`: "*" is any run of characters, "?" any one,
+// and "[...]" any one of those inside, with ranges like "a-z" and a leading "!" or "^" for those
+// not inside.
+pub fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where the last "*" was, and how much of the name it has taken so far
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if pattern.get(p) == Some(&'*') {
+            p += 1;
+            star = Some((p, n));
+            continue;
+        }
+        if p < pattern.len() {
+            if let Some(len) = match_one(&pattern[p..], name[n]) {
+                p += len;
+                n += 1;
+                continue;
+            }
+        }
+        // The "*" takes one more character and the rest of the pattern goes again from there
+        match star {
+            Some((star_p, star_n)) => {
+                p = star_p;
+                n = star_n + 1;
+                star = Some((star_p, n));
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+// How much of `pattern` matched `c`: one character, or a whole "[...]".
+fn match_one(pattern: &[char], c: char) -> Option<usize> {
+    match pattern[0] {
+        '?' => Some(1),
+        '[' => match_class(pattern, c),
+        literal if literal == c => Some(1),
+        _ => None,
+    }
+}
+
+fn match_class(pattern: &[char], c: char) -> Option<usize> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+    let first = i;
+    let mut matched = false;
+    loop {
+        match pattern.get(i) {
+            // Never closed, so it's just a "["
+            None => return if c == '[' { Some(1) } else { None },
+            // A "]" right at the start is one of the characters
+            Some(']') if i > first => break,
+            Some(&low) => match (pattern.get(i + 1), pattern.get(i + 2)) {
+                (Some('-'), Some(&high)) if high != ']' => {
+                    matched |= low <= c && c <= high;
+                    i += 3;
+                }
+                _ => {
+                    matched |= low == c;
+                    i += 1;
+                }
+            },
+        }
+    }
+    if matched != negated {
+        Some(i + 1)
+    } else {
+        None
+    }
+}
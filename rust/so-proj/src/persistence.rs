@@ -0,0 +1,205 @@
+// Keeping the file system and the clients across restarts. Each system is saved as a bincode
+// snapshot of the whole thing, and every change since is appended to a journal, framed like the
+// messages on the socket. Restoring replays the journal on top of the snapshot, then a new
+// snapshot is taken and the journal starts empty again.
+
+extern crate bincode;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use so_proj_protocol::{receive_at_most, send};
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, ErrorKind};
+use std::path::{Path, PathBuf};
+
+// Journal entries before the snapshot is taken again, so restoring doesn't replay forever.
+const SNAPSHOT_EVERY: usize = 1000;
+
+// A system that can be restored from its snapshot and journal. Entries can end up applied twice,
+// when the server stops between taking a snapshot and emptying the journal, so applying one has
+// to set things to how they were rather than change them relative to how they are.
+pub trait Journaled: Serialize + DeserializeOwned {
+    type Entry: Serialize + DeserializeOwned;
+
+    fn apply(&mut self, entry: Self::Entry);
+}
+
+#[derive(Debug)]
+pub struct Journal {
+    snapshot_path: PathBuf,
+    journal_path: PathBuf,
+    journal: File,
+    entries: usize,
+}
+
+impl Journal {
+    // `name`.bin and `name`.journal in `dir`. Without them the system starts as `empty`.
+    pub fn restore<T: Journaled>(dir: &Path, name: &str, empty: T) -> io::Result<(T, Journal)> {
+        fs::create_dir_all(dir)?;
+        let snapshot_path = dir.join(format!("{}.bin", name));
+        let journal_path = dir.join(format!("{}.journal", name));
+
+        let mut system = match File::open(&snapshot_path) {
+            Ok(file) => match bincode::deserialize_from(BufReader::new(file)) {
+                Ok(_system) => _system,
+                Err(e) => return Err(io::Error::new(ErrorKind::InvalidData, e)),
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => empty,
+            Err(e) => return Err(e),
+        };
+        match File::open(&journal_path) {
+            Ok(file) => {
+                let mut reader = BufReader::new(file);
+                loop {
+                    // Entries can hold whole files, the server wrote them itself
+                    match receive_at_most(&mut reader, u64::MAX) {
+                        Ok(entry) => system.apply(entry),
+                        // The last entry is cut short if the server stopped while writing it
+                        Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        let journal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&journal_path)?;
+        let mut journal = Journal {
+            snapshot_path,
+            journal_path,
+            journal,
+            entries: 0,
+        };
+        journal.snapshot(&system)?;
+        Ok((system, journal))
+    }
+
+    // Appends the change `system` just went through, taking a snapshot every so often.
+    pub fn record<T: Journaled>(&mut self, system: &T, entry: &T::Entry) -> io::Result<()> {
+        send(&mut self.journal, entry)?;
+        self.journal.sync_data()?;
+        self.entries += 1;
+        if self.entries >= SNAPSHOT_EVERY {
+            self.snapshot(system)?;
+        }
+        Ok(())
+    }
+
+    // The snapshot replaces the old one in one rename, so there's always a whole one on disk.
+    fn snapshot<T: Journaled>(&mut self, system: &T) -> io::Result<()> {
+        let temporary_path = self.snapshot_path.with_extension("bin.tmp");
+        let mut writer = BufWriter::new(File::create(&temporary_path)?);
+        if let Err(e) = bincode::serialize_into(&mut writer, system) {
+            return Err(io::Error::other(e));
+        }
+        let file = match writer.into_inner() {
+            Ok(_file) => _file,
+            Err(e) => return Err(e.into_error()),
+        };
+        file.sync_all()?;
+        fs::rename(&temporary_path, &self.snapshot_path)?;
+
+        self.journal.set_len(0)?;
+        self.journal.sync_all()?;
+        self.entries = 0;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.journal_path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_system::{_File, Change, FileSystem, Permission, SystemResult};
+
+    use std::io::Write;
+
+    fn data_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("so-proj-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    // A home for "al" with "/home/al/notes" in it, written to after it was created.
+    fn make_changes(file_system: &mut FileSystem) {
+        assert_eq!(file_system.create_home("al"), SystemResult::Ok);
+        let file = _File::new(
+            "/home/al/notes".to_string(),
+            b"hello".to_vec(),
+            "al".to_string(),
+            Permission::ReadWrite,
+            Permission::None,
+        );
+        assert_eq!(file_system.add_file("al", file), SystemResult::Ok);
+        let fd = file_system
+            .open_file("al", "/home/al/notes".to_string(), Permission::Write, true)
+            .unwrap();
+        assert_eq!(
+            file_system.write_to_file("al", fd, b" world".to_vec(), 6),
+            Ok((6, SystemResult::Ok))
+        );
+    }
+
+    fn notes(file_system: &mut FileSystem) -> Vec<u8> {
+        file_system
+            .download_file("al", "/home/al/notes", 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn restores_snapshot_and_journal() {
+        let dir = data_dir("restore");
+        make_changes(&mut FileSystem::restore(&dir).unwrap());
+        assert!(fs::metadata(dir.join("file_system.journal")).unwrap().len() > 0);
+
+        let mut file_system = FileSystem::restore(&dir).unwrap();
+        assert_eq!(notes(&mut file_system), b"hello world");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cut_short_last_entry_is_left_out() {
+        let dir = data_dir("cut-short");
+        make_changes(&mut FileSystem::restore(&dir).unwrap());
+        let mut entry = Vec::new();
+        send(
+            &mut entry,
+            &Change::RemoveFile("/home/al/notes".to_string()),
+        )
+        .unwrap();
+        let mut journal = OpenOptions::new()
+            .append(true)
+            .open(dir.join("file_system.journal"))
+            .unwrap();
+        journal.write_all(&entry[..entry.len() - 1]).unwrap();
+
+        let mut file_system = FileSystem::restore(&dir).unwrap();
+        assert_eq!(notes(&mut file_system), b"hello world");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // As when the server stops right after a snapshot, before the journal is emptied.
+    #[test]
+    fn journal_applied_again_on_its_snapshot_changes_nothing() {
+        let dir = data_dir("applied-twice");
+        make_changes(&mut FileSystem::restore(&dir).unwrap());
+        let journal_path = dir.join("file_system.journal");
+        let entries = fs::read(&journal_path).unwrap();
+
+        drop(FileSystem::restore(&dir).unwrap());
+        assert_eq!(fs::metadata(&journal_path).unwrap().len(), 0);
+        fs::write(&journal_path, entries).unwrap();
+
+        let mut file_system = FileSystem::restore(&dir).unwrap();
+        assert_eq!(notes(&mut file_system), b"hello world");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,28 +1,82 @@
-extern crate bincode;
-extern crate rustc_serialize;
-
 use crate::file_system::*;
 use crate::option_handling::*;
+use crate::path::{self, ROOT};
+use crate::persistence::{Journal, Journaled};
+use so_proj_protocol::{receive, send, Request, Response};
 
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::prelude::*;
-use std::io::{self, Write};
-use std::io::{BufReader, BufWriter};
+use std::io::{self, BufReader};
 use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::thread;
 
-#[derive(Debug, Serialize, Deserialize, RustcEncodable, RustcDecodable)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Client {
     name: String,
     password: String,
 }
-#[derive(Debug, Serialize, Deserialize, RustcEncodable, RustcDecodable)]
+// Who's online and the journal aren't saved, no one is online when the server starts.
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ClientSystem {
     clients: HashMap<String, Client>,
+    #[serde(skip)]
     clients_online: Vec<String>,
+    #[serde(skip)]
+    journal: Option<Journal>,
+}
+
+// The client logged in on a connection, whose descriptor table file operations use, and the
+// directory relative paths start from.
+pub struct Session {
+    pub client: String,
+    pub cwd: String,
+}
+
+impl Session {
+    // Starts in the client's home directory, or at the root for clients registered before there
+    // were home directories.
+    fn start(file_system: &Arc<Mutex<FileSystem>>, client: &str) -> Self {
+        let home = path::home(client);
+        let cwd = if file_system.lock().unwrap().directory_exists(&home) {
+            home
+        } else {
+            ROOT.to_string()
+        };
+        Session {
+            client: client.to_string(),
+            cwd,
+        }
+    }
+}
+
+// Where a connection is. Only registering and logging in work until a client logs in, and every
+// file operation after that is done as that client. Logging out goes back to the start, and
+// nothing works once the connection is closed.
+pub enum SessionState {
+    Unauthenticated,
+    Authenticated(Session),
+    Closed,
+}
+
+impl SessionState {
+    // Leaves the client's session for `next`. Descriptors and locks belong to the client, not to
+    // one of its sessions, so its files are only closed once it isn't logged in anywhere else.
+    fn end(
+        &mut self,
+        next: SessionState,
+        file_system: &Arc<Mutex<FileSystem>>,
+        client_system: &Arc<Mutex<ClientSystem>>,
+    ) {
+        if let SessionState::Authenticated(session) = std::mem::replace(self, next) {
+            // Kept until the files are closed, so no session of the client starts in between
+            let mut client_system = client_system.lock().unwrap();
+            if !client_system.logout_client(&session.client) {
+                file_system.lock().unwrap().close_all(&session.client);
+            }
+        }
+    }
 }
 
 impl Client {
@@ -36,27 +90,43 @@ impl ClientSystem {
         ClientSystem {
             clients: HashMap::default(),
             clients_online: Vec::default(),
+            journal: None,
         }
     }
 
-    pub fn save_status(&self) {
-        let mut writer = BufWriter::new(File::create("ClientList.bin").unwrap());
-        bincode::serialize_into(&mut writer, self).unwrap();
+    // The clients saved in `dir`, or none if nothing was saved there yet. Clients registered
+    // from now on are saved there too.
+    pub fn restore(dir: &Path) -> io::Result<Self> {
+        let (mut client_system, journal) =
+            Journal::restore(dir, "client_system", ClientSystem::new())?;
+        client_system.journal = Some(journal);
+        Ok(client_system)
     }
 
-    pub fn recover_status(&mut self) {
-        let mut reader = BufReader::new(File::open("ClientList.bin").unwrap());
-        let decoded: ClientSystem = bincode::deserialize_from(&mut reader).unwrap();
-        *self = decoded;
+    // Like FileSystem::record, the client stays registered if it couldn't be saved.
+    fn record(&mut self, client: &Client) -> SystemResult {
+        let mut system_result = SystemResult::Ok;
+        if let Some(mut journal) = self.journal.take() {
+            if let Err(e) = journal.record(self, client) {
+                eprintln!("Not able to journal to {}: {}", journal.path().display(), e);
+                system_result = SystemResult::NotSaved;
+            }
+            self.journal = Some(journal);
+        }
+        system_result
     }
 
     pub fn register_client(&mut self, name: String, password: String) -> SystemResult {
+        // The name is also the name of the client's home directory
+        if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+            return SystemResult::MiscellaneousError;
+        }
         match self.client_exists(&name) {
-            Some(_) => SystemResult::ClientDoesntExist,
+            Some(_) => SystemResult::ClientAlreadyExists,
             None => {
                 let client = Client::new(name.clone(), password);
-                self.clients.insert(name, client);
-                SystemResult::Ok
+                self.clients.insert(name, client.clone());
+                self.record(&client)
             }
         }
     }
@@ -75,13 +145,24 @@ impl ClientSystem {
         }
     }
 
-    pub fn client_exists(&self, name: &str) -> Option<&Client> {
-        for (_, client) in self.clients.iter() {
-            if client.name == name {
-                return Some(&client);
-            }
+    // One of the client's sessions ended. Whether it's still online, logged in somewhere else.
+    pub fn logout_client(&mut self, name: &str) -> bool {
+        if let Some(index) = self.clients_online.iter().position(|online| online == name) {
+            self.clients_online.remove(index);
         }
-        None
+        self.clients_online.iter().any(|online| online == name)
+    }
+
+    pub fn client_exists(&self, name: &str) -> Option<&Client> {
+        self.clients.values().find(|client| client.name == name)
+    }
+}
+
+impl Journaled for ClientSystem {
+    type Entry = Client;
+
+    fn apply(&mut self, client: Client) {
+        self.clients.insert(client.name.clone(), client);
     }
 }
 
@@ -99,121 +180,209 @@ pub fn bind(socket_path: &str) -> UnixListener {
     }
 }
 
-fn send_result(stream: &mut UnixStream, bytes: &[u8]) {
-    match stream.write_all(bytes) {
-        Ok(_) => { /* Successful write to socket! */ }
-        Err(e) => panic!("Not able to write to socket!: {}", e),
-    };
-}
-
 pub fn client_handler(
     mut stream: UnixStream,
     file_system: Arc<Mutex<FileSystem>>,
     client_system: Arc<Mutex<ClientSystem>>,
 ) {
-    let buf_stream = BufReader::new(match stream.try_clone() {
-        Ok(_cloned_stream) => _cloned_stream,
-        Err(e) => panic!("Not able to clone UnixStream!: {}", e),
-    });
-    let temp_stream = BufReader::new(match stream.try_clone() {
+    let mut buf_stream = BufReader::new(match stream.try_clone() {
         Ok(_cloned_stream) => _cloned_stream,
         Err(e) => panic!("Not able to clone UnixStream!: {}", e),
     });
-    let mut sub_strings: Vec<String>;
-    let name: String;
-    for line in temp_stream.lines() {
-        sub_strings = line.unwrap().split("|").map(|s| s.to_string()).collect();
-        name = match sub_strings.get(2) {
-            Some(_goes_into_name) => _goes_into_name.to_string(),
-            None => panic!("There should be a string at index 2"),
-        };
-        break;
-    }
+    let mut state = SessionState::Unauthenticated;
 
-    for line in buf_stream.lines() {
-        sub_strings = line.unwrap().split("|").map(|s| s.to_string()).collect();
-        let system_result = interact(sub_strings, &file_system, &client_system);
-        send_result(
-            &mut stream,
-            format!("{}\n", system_result as i32).as_bytes(),
-        );
+    loop {
+        let response = match receive::<_, Request>(&mut buf_stream) {
+            Ok(request) => interact(request, &mut state, &file_system, &client_system),
+            // The whole message was read, so the next one can still be
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                Response::Result(SystemResult::MiscellaneousError)
+            }
+            Err(_) => break,
+        };
+        if send(&mut stream, &response).is_err() {
+            break;
+        }
         print_debug(&file_system, &client_system)
     }
+
+    state.end(SessionState::Closed, &file_system, &client_system);
 }
 
 pub fn interact(
-    args: Vec<String>,
+    request: Request,
+    state: &mut SessionState,
     file_system: &Arc<Mutex<FileSystem>>,
     client_system: &Arc<Mutex<ClientSystem>>,
-) -> SystemResult {
-    let system_result: SystemResult;
-    match args.get(0) {
-        Some(arg) => {
-            let option = args[1].as_str();
-            match arg.as_str() {
-                "c" => {
-                    system_result = interact_client_menu(file_system, option, args[2..].to_vec());
+) -> Response {
+    match request {
+        Request::Register { name, password } => {
+            let system_result = client_system
+                .lock()
+                .unwrap()
+                .register_client(name.clone(), password);
+            // Registered even if it couldn't be saved, so it gets its home all the same
+            match system_result {
+                SystemResult::Ok | SystemResult::NotSaved => {
+                    match file_system.lock().unwrap().create_home(&name) {
+                        SystemResult::Ok => Response::Result(system_result),
+                        home_result => Response::Result(home_result),
+                    }
                 }
-                "m" => {
-                    system_result = interact_main_menu(client_system, option, args[2..].to_vec());
-                }
-                _ => panic!("This menu tag shouldn't exist!"),
+                _ => Response::Result(system_result),
             }
         }
-        None => panic!("Arguments vector is empty!"),
+        Request::Login { name, password } => match state {
+            SessionState::Unauthenticated => {
+                let system_result = client_system
+                    .lock()
+                    .unwrap()
+                    .login_client(name.clone(), password);
+                if system_result == SystemResult::Ok {
+                    *state = SessionState::Authenticated(Session::start(file_system, &name));
+                }
+                Response::Result(system_result)
+            }
+            SessionState::Authenticated(_) => Response::Result(SystemResult::AlreadyLoggedIn),
+            SessionState::Closed => Response::Result(SystemResult::PermissionDenied),
+        },
+        Request::Logout => match state {
+            SessionState::Authenticated(_) => {
+                state.end(SessionState::Unauthenticated, file_system, client_system);
+                Response::Result(SystemResult::Ok)
+            }
+            _ => Response::Result(SystemResult::PermissionDenied),
+        },
+        request => match state {
+            SessionState::Authenticated(session) => {
+                interact_client_menu(file_system, session, request)
+            }
+            _ => Response::Result(SystemResult::PermissionDenied),
+        },
     }
-    system_result
 }
 
-pub fn interact_main_menu(
-    client_system: &Arc<Mutex<ClientSystem>>,
-    option: &str,
-    args: Vec<String>,
-) -> SystemResult {
-    match option {
-        "r" | "R" => client_system.lock().unwrap().register_client(
-            args.get(0)
-                .expect("args vector should have name at index 0")
-                .clone(),
-            args.get(1)
-                .expect("args vector should have name at index 1")
-                .clone(),
+pub fn interact_client_menu(
+    file_system: &Arc<Mutex<FileSystem>>,
+    session: &mut Session,
+    request: Request,
+) -> Response {
+    let system_result = match request {
+        Request::Create {
+            name,
+            content,
+            owner_permission,
+            others_permission,
+        } => create_file(
+            file_system,
+            session,
+            &name,
+            content,
+            owner_permission,
+            others_permission,
         ),
-        "l" | "L" => client_system.lock().unwrap().login_client(
-            args.get(0)
-                .expect("args vector should have name at index 0")
-                .clone(),
-            args.get(1)
-                .expect("args vector should have name at index 1")
-                .clone(),
+        Request::Delete { name } => delete_file(file_system, session, &name),
+        Request::Rename { name, new_name } => rename_file(file_system, session, &name, &new_name),
+        Request::ChangePermissions {
+            name,
+            owner_permission,
+            others_permission,
+        } => change_permissions(
+            file_system,
+            session,
+            &name,
+            owner_permission,
+            others_permission,
         ),
-        "e" | "E" => SystemResult::Exit,
-        other_option => {
-            /* Option shouldn't be reached */
-            panic!("There shouldn't be this option!: {}", other_option)
+        Request::Open {
+            name,
+            permission,
+            append,
+        } => match open_file(file_system, session, &name, permission, append) {
+            Ok(fd) => return Response::Opened(fd),
+            Err(_system_result) => _system_result,
+        },
+        Request::Close { fd } => close_file(file_system, session, fd),
+        Request::Read { fd, len } => match read_file(file_system, session, fd, len) {
+            Ok(content) => return Response::Data(content),
+            Err(_system_result) => _system_result,
+        },
+        Request::Write { fd, content, len } => {
+            match write_to_file(file_system, session, fd, content, len) {
+                Ok((len, system_result)) => {
+                    return Response::Written {
+                        len,
+                        saved: system_result == SystemResult::Ok,
+                    }
+                }
+                Err(_system_result) => _system_result,
+            }
         }
-    }
-}
-
-pub fn interact_client_menu(
-    file_system: &Arc<Mutex<FileSystem>>,
-    option: &str,
-    args: Vec<String>,
-) -> SystemResult {
-    match option {
-        "e" | "E" => SystemResult::Exit,
-        "c" | "C" => create_file(file_system, args),
-        "d" | "D" => delete_file(file_system, args),
-        "r" | "R" => rename_file(file_system, args),
-        "o" | "O" => open_file(file_system, args),
-        "x" | "X" => close_file(file_system, args),
-        "l" | "L" => read_file(file_system, args),
-        "w" | "W" => write_to_file(file_system, args),
-        other_option => {
-            /* Option shouldn't be reached */
-            panic!("There shouldn't be this option!: {}", other_option)
+        Request::Seek { fd, offset, whence } => {
+            match seek_file(file_system, session, fd, offset, whence) {
+                Ok(offset) => return Response::Offset(offset),
+                Err(_system_result) => _system_result,
+            }
         }
-    }
+        Request::Lock { fd, mode, timeout } => lock_file(file_system, session, fd, mode, timeout),
+        Request::Unlock { fd } => unlock_file(file_system, session, fd),
+        Request::MakeDirectory {
+            name,
+            owner_permission,
+            others_permission,
+        } => make_directory(
+            file_system,
+            session,
+            &name,
+            owner_permission,
+            others_permission,
+        ),
+        Request::RemoveDirectory { name } => remove_directory(file_system, session, &name),
+        Request::ListDirectory { name } => {
+            match list_directory(file_system, session, name.as_deref()) {
+                Ok(names) => return Response::Names(names),
+                Err(_system_result) => _system_result,
+            }
+        }
+        Request::ChangeDirectory { name } => change_directory(file_system, session, &name),
+        Request::WorkingDirectory => return Response::WorkingDirectory(session.cwd.clone()),
+        Request::Stat { name } => match stat(file_system, session, &name) {
+            Ok(stat) => return Response::Stat(stat),
+            Err(_system_result) => _system_result,
+        },
+        Request::List { name, pattern } => {
+            match list(file_system, session, name.as_deref(), pattern.as_deref()) {
+                Ok(stats) => return Response::Stats(stats),
+                Err(_system_result) => _system_result,
+            }
+        }
+        Request::Upload {
+            name,
+            offset,
+            content,
+            owner_permission,
+            others_permission,
+        } => upload_file(
+            file_system,
+            session,
+            &name,
+            offset,
+            content,
+            owner_permission,
+            others_permission,
+        ),
+        Request::Download { name, offset } => {
+            match download_file(file_system, session, &name, offset) {
+                Ok(content) => return Response::Data(content),
+                Err(_system_result) => _system_result,
+            }
+        }
+        Request::Register { .. } | Request::Login { .. } | Request::Logout => {
+            /* Handled by interact, with or without a session */
+            unreachable!("Main menu request in the client menu!")
+        }
+    };
+    Response::Result(system_result)
 }
 
 pub fn await_connections(
@@ -243,3 +412,164 @@ fn print_debug(file_system: &Arc<Mutex<FileSystem>>, client_system: &Arc<Mutex<C
     println!("FileSystem:\n{:?}\n", file_system.lock().unwrap());
     println!("ClientSystem:\n{:?}", client_system.lock().unwrap())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Server {
+        file_system: Arc<Mutex<FileSystem>>,
+        client_system: Arc<Mutex<ClientSystem>>,
+    }
+
+    impl Server {
+        // With "al" registered.
+        fn new() -> Self {
+            let server = Server {
+                file_system: Arc::new(Mutex::new(FileSystem::new())),
+                client_system: Arc::new(Mutex::new(ClientSystem::new())),
+            };
+            let mut state = SessionState::Unauthenticated;
+            assert_eq!(
+                server.interact(register("al"), &mut state),
+                Response::Result(SystemResult::Ok)
+            );
+            server
+        }
+
+        fn interact(&self, request: Request, state: &mut SessionState) -> Response {
+            interact(request, state, &self.file_system, &self.client_system)
+        }
+
+        fn logged_in(&self) -> SessionState {
+            let mut state = SessionState::Unauthenticated;
+            assert_eq!(
+                self.interact(login("al", "secret"), &mut state),
+                Response::Result(SystemResult::Ok)
+            );
+            state
+        }
+
+        fn disconnect(&self, mut state: SessionState) {
+            state.end(SessionState::Closed, &self.file_system, &self.client_system);
+        }
+    }
+
+    fn register(name: &str) -> Request {
+        Request::Register {
+            name: name.to_string(),
+            password: "secret".to_string(),
+        }
+    }
+
+    fn login(name: &str, password: &str) -> Request {
+        Request::Login {
+            name: name.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    fn create(name: &str) -> Request {
+        Request::Create {
+            name: name.to_string(),
+            content: b"hello".to_vec(),
+            owner_permission: Permission::ReadWrite,
+            others_permission: Permission::None,
+        }
+    }
+
+    fn result(system_result: SystemResult) -> Response {
+        Response::Result(system_result)
+    }
+
+    #[test]
+    fn files_only_work_while_logged_in() {
+        let server = Server::new();
+        let mut state = SessionState::Unauthenticated;
+        assert_eq!(
+            server.interact(create("a"), &mut state),
+            result(SystemResult::PermissionDenied)
+        );
+        assert_eq!(
+            server.interact(Request::Logout, &mut state),
+            result(SystemResult::PermissionDenied)
+        );
+        assert_eq!(
+            server.interact(login("al", "wrong"), &mut state),
+            result(SystemResult::WrongCredentials)
+        );
+        assert_eq!(
+            server.interact(login("bo", "secret"), &mut state),
+            result(SystemResult::ClientDoesntExist)
+        );
+
+        let mut state = server.logged_in();
+        assert_eq!(
+            server.interact(login("al", "secret"), &mut state),
+            result(SystemResult::AlreadyLoggedIn)
+        );
+        // Sessions start at home
+        assert_eq!(
+            server.interact(Request::WorkingDirectory, &mut state),
+            Response::WorkingDirectory("/home/al".to_string())
+        );
+        assert_eq!(
+            server.interact(create("a"), &mut state),
+            result(SystemResult::Ok)
+        );
+
+        assert_eq!(
+            server.interact(Request::Logout, &mut state),
+            result(SystemResult::Ok)
+        );
+        assert_eq!(
+            server.interact(create("b"), &mut state),
+            result(SystemResult::PermissionDenied)
+        );
+        // The connection can log in again, but not once it's closed
+        assert_eq!(
+            server.interact(login("al", "secret"), &mut state),
+            result(SystemResult::Ok)
+        );
+        let mut state = SessionState::Closed;
+        assert_eq!(
+            server.interact(login("al", "secret"), &mut state),
+            result(SystemResult::PermissionDenied)
+        );
+    }
+
+    // Descriptors belong to the client, so one session can use what another opened, and they
+    // stay open until the client's last session ends.
+    #[test]
+    fn files_stay_open_until_the_last_session_ends() {
+        let server = Server::new();
+        let mut first = server.logged_in();
+        let mut second = server.logged_in();
+        server.interact(create("a"), &mut first);
+        let fd = match server.interact(
+            Request::Open {
+                name: "a".to_string(),
+                permission: Permission::Read,
+                append: false,
+            },
+            &mut first,
+        ) {
+            Response::Opened(fd) => fd,
+            response => panic!("not opened: {:?}", response),
+        };
+        let read = Request::Read { fd, len: 5 };
+
+        server.disconnect(first);
+        assert_eq!(
+            server.interact(read.clone(), &mut second),
+            Response::Data(b"hello".to_vec())
+        );
+
+        server.interact(Request::Logout, &mut second);
+        let mut third = server.logged_in();
+        assert_eq!(
+            server.interact(read, &mut third),
+            result(SystemResult::FileNotOpen)
+        );
+    }
+}